@@ -0,0 +1,296 @@
+use std::{
+    io,
+    ops::{Add, Mul, Sub},
+    path::Path,
+};
+
+use glam::Vec3;
+
+use crate::{
+    camera::Camera,
+    ray::HittableList,
+    renderer::{encode_framebuffer, render, render_framebuffer, RenderConfig},
+    video::FrameWriter,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Smooth curve through the keyframes, tangents come from the neighbouring keyframes.
+    CatmullRom,
+}
+
+/// Camera state at a point in time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    pub time: f32, // Seconds
+    pub position: Vec3,
+    pub look_at: Vec3,
+    pub fov: f32, // Deg
+    pub focus_dist: f32,
+}
+
+/// Keyframed camera, sampled before each frame and applied to a copy of the camera.
+#[derive(Clone, Debug)]
+pub struct CameraAnimation {
+    pub keyframes: Vec<CameraKeyframe>, // Sorted by time, see add_keyframe
+    pub interpolation: Interpolation,
+    pub up: Vec3,
+}
+
+impl Default for CameraAnimation {
+    fn default() -> Self {
+        Self::new(Interpolation::Linear)
+    }
+}
+
+impl CameraAnimation {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            keyframes: Vec::new(),
+            interpolation,
+            up: Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    pub fn add_keyframe(&mut self, keyframe: CameraKeyframe) {
+        let index = self
+            .keyframes
+            .partition_point(|other| other.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    /// Interpolated camera state, held at the first and last keyframes outside their range.
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if time <= first.time {
+            return Some(CameraKeyframe { time, ..*first });
+        }
+        if time >= last.time {
+            return Some(CameraKeyframe { time, ..*last });
+        }
+
+        // Segment from keyframe i to i + 1 containing time
+        let i = self.keyframes.partition_point(|other| other.time <= time) - 1;
+        Some(CameraKeyframe {
+            time,
+            position: self.interpolate(i, time, |k| k.position),
+            look_at: self.interpolate(i, time, |k| k.look_at),
+            fov: self.interpolate(i, time, |k| k.fov),
+            focus_dist: self.interpolate(i, time, |k| k.focus_dist),
+        })
+    }
+
+    fn interpolate<T>(&self, i: usize, time: f32, value: impl Fn(&CameraKeyframe) -> T) -> T
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+    {
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+        let duration = k2.time - k1.time;
+        let s = (time - k1.time) / duration;
+        let (p1, p2) = (value(k1), value(k2));
+
+        match self.interpolation {
+            Interpolation::Linear => p1 * (1.0 - s) + p2 * s,
+            Interpolation::CatmullRom => {
+                // Finite difference tangents, one sided at the ends, scaled for uneven spacing
+                let k0 = &self.keyframes[i.saturating_sub(1)];
+                let k3 = &self.keyframes[(i + 2).min(self.keyframes.len() - 1)];
+                let m1 = (p2 - value(k0)) * (1.0 / (k2.time - k0.time));
+                let m2 = (value(k3) - p1) * (1.0 / (k3.time - k1.time));
+
+                let s2 = s * s;
+                let s3 = s2 * s;
+                let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+                let h10 = s3 - 2.0 * s2 + s;
+                let h01 = -2.0 * s3 + 3.0 * s2;
+                let h11 = s3 - s2;
+                p1 * h00 + m1 * (h10 * duration) + p2 * h01 + m2 * (h11 * duration)
+            }
+        }
+    }
+
+    /// Moves `camera` to its state at `time`, the camera is left as is without keyframes.
+    pub fn apply(&self, camera: &mut Camera, time: f32) {
+        if let Some(keyframe) = self.sample(time) {
+            camera.position = keyframe.position;
+            camera.look_at(keyframe.look_at, self.up);
+            camera.fov = keyframe.fov;
+            camera.focus_dist = keyframe.focus_dist;
+        }
+    }
+}
+
+/// Range of numbered frames rendered from an animation, frame 1 is at time 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameSequence {
+    pub start_frame: i32,
+    pub end_frame: i32,      // Inclusive
+    pub frame_rate: f32,     // Frames per second
+    pub skip_existing: bool, // Frames whose image is already on disk are not rendered again
+}
+
+impl Default for FrameSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameSequence {
+    pub fn new() -> Self {
+        Self {
+            start_frame: 1,
+            end_frame: 24,
+            frame_rate: 24.0,
+            skip_existing: true,
+        }
+    }
+
+    pub fn frame_time(&self, frame: i32) -> f32 {
+        (frame - 1) as f32 / self.frame_rate
+    }
+
+    /// `frame.ppm` becomes `frame_0001.ppm` and so on.
+    pub fn frame_path(render_file_path: &str, frame: i32) -> String {
+        let path = Path::new(render_file_path);
+        let stem = path.with_extension("");
+        match path.extension() {
+            Some(extension) => format!(
+                "{}_{:04}.{}",
+                stem.display(),
+                frame,
+                extension.to_string_lossy()
+            ),
+            None => format!("{}_{:04}", stem.display(), frame),
+        }
+    }
+
+    /// Renders every frame of the range, returns the paths written.
+    pub fn render(
+        &self,
+        world: &HittableList,
+        camera: &Camera,
+        animation: &CameraAnimation,
+        config: &RenderConfig,
+        render_file_path: &str,
+    ) -> Result<Vec<String>, io::Error> {
+        let mut written: Vec<String> = Vec::new();
+        for frame in self.start_frame..=self.end_frame {
+            let frame_path = Self::frame_path(render_file_path, frame);
+            if self.skip_existing && Path::new(&frame_path).exists() {
+                println!("Skipping frame {}, {} exists", frame, frame_path);
+                continue;
+            }
+
+            println!("Rendering frame {} of {}...", frame, self.end_frame);
+            let mut frame_camera = camera.clone();
+            animation.apply(&mut frame_camera, self.frame_time(frame));
+            render(world, &mut frame_camera, config, &frame_path)?;
+            written.push(frame_path);
+        }
+        Ok(written)
+    }
+
+    /// Renders every frame of the range into `video` instead of image files, see Y4mWriter
+    /// and GifEncoder. Nothing is skipped as the video needs every frame.
+    pub fn render_video(
+        &self,
+        world: &HittableList,
+        camera: &Camera,
+        animation: &CameraAnimation,
+        config: &RenderConfig,
+        video: &mut impl FrameWriter,
+    ) -> Result<(), io::Error> {
+        for frame in self.start_frame..=self.end_frame {
+            println!("Rendering frame {} of {}...", frame, self.end_frame);
+            let mut frame_camera = camera.clone();
+            animation.apply(&mut frame_camera, self.frame_time(frame));
+            let (framebuffer, _aovs) = render_framebuffer(world, &mut frame_camera, config);
+            video.write_frame(&encode_framebuffer(&framebuffer, world, config))?;
+        }
+        video.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::*;
+
+    fn keyframe(time: f32, x: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            position: Vec3::new(x, 0.0, 5.0),
+            look_at: Vec3::new(0.0, 0.0, 0.0),
+            fov: 40.0 + x,
+            focus_dist: 5.0,
+        }
+    }
+
+    #[test]
+    fn test_keyframe_interpolation() {
+        let mut animation = CameraAnimation::new(Interpolation::Linear);
+        animation.add_keyframe(keyframe(2.0, 4.0));
+        animation.add_keyframe(keyframe(0.0, 0.0));
+        animation.add_keyframe(keyframe(1.0, 1.0));
+        assert_eq!(animation.keyframes[2].time, 2.0);
+
+        let middle = animation.sample(1.5).unwrap();
+        assert!((middle.position.x - 2.5).abs() < 1e-5);
+        assert!((middle.fov - 42.5).abs() < 1e-4);
+        assert_eq!(animation.sample(-1.0).unwrap().position.x, 0.0);
+        assert_eq!(animation.sample(3.0).unwrap().position.x, 4.0);
+
+        // Catmull-Rom passes through the keyframes and bends towards the faster segment
+        animation.interpolation = Interpolation::CatmullRom;
+        for k in animation.keyframes.clone() {
+            assert!((animation.sample(k.time).unwrap().position.x - k.position.x).abs() < 1e-5);
+        }
+        assert!(animation.sample(1.5).unwrap().position.x < 2.5);
+        let before = animation.sample(0.999).unwrap().position.x;
+        let after = animation.sample(1.001).unwrap().position.x;
+        assert!((after - before) / 0.002 > 1.0);
+
+        let mut camera = Camera::new();
+        animation.apply(&mut camera, 2.0);
+        assert_eq!(camera.position, Vec3::new(4.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn test_frame_sequence_skips_existing() {
+        assert_eq!(
+            FrameSequence::frame_path("img/frame.ppm", 12),
+            "img/frame_0012.ppm"
+        );
+
+        // Per process, so concurrent runs don't delete each other's frames
+        let directory =
+            std::env::temp_dir().join(format!("rtiow_frame_sequence_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let render_file_path = directory.join("frame.ppm").display().to_string();
+        let sequence = FrameSequence {
+            start_frame: 3,
+            end_frame: 4,
+            ..FrameSequence::new()
+        };
+        for frame in 3..=4 {
+            File::create(FrameSequence::frame_path(&render_file_path, frame)).unwrap();
+        }
+
+        let written = sequence
+            .render(
+                &HittableList::new(),
+                &Camera::new(),
+                &CameraAnimation::default(),
+                &RenderConfig::default(),
+                &render_file_path,
+            )
+            .unwrap();
+        assert!(written.is_empty());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    thread,
+};
+
+use glam::Vec3;
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    interval::Interval,
+    material::MATERIAL_DIELECTRIC,
+    ray::{HitResult, HittableList, Ray},
+    sampler::{begin_pixel_sample, end_pixel_sample, next_2d},
+    warp::{local_to_world, square_to_uniform_hemisphere, uniform_hemisphere_pdf},
+};
+
+/// Id written for pixels where no sample hit anything.
+pub const AOV_NO_HIT_ID: i32 = -1;
+
+/// How far an occluder may be from the first hit and still count for `AovPixel::occlusion`.
+pub const AOV_OCCLUSION_DISTANCE: f32 = 2.0;
+
+/// First hit data for one pixel, averaged over the samples that hit something.
+/// Pixels that only see the background keep the zero defaults and `AOV_NO_HIT_ID`.
+#[derive(Clone, Copy)]
+pub struct AovPixel {
+    pub depth: f32, // Distance along the camera ray, `t`
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Vec3,
+    pub material_id: i32,  // From the first sample that hit
+    pub primitive_id: i32, // Index of the object in the HittableList
+    pub coverage: f32,     // Fraction of the samples that hit something
+    pub occlusion: f32,    // Fraction of the hemisphere above the hit left open, ambient occlusion
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        Self {
+            depth: 0.0,
+            normal: Vec3::ZERO,
+            albedo: Color::new(0.0, 0.0, 0.0, 1.0),
+            position: Vec3::ZERO,
+            material_id: AOV_NO_HIT_ID,
+            primitive_id: AOV_NO_HIT_ID,
+            coverage: 0.0,
+            occlusion: 0.0,
+        }
+    }
+}
+
+/// Albedo used as a feature buffer, glass is seen through so it counts as white.
+fn aov_albedo(world: &HittableList, hit_result: &HitResult) -> Color {
+    if hit_result.material_id == MATERIAL_DIELECTRIC {
+        return Color::new(1.0, 1.0, 1.0, 1.0);
+    }
+    let albedo = world
+        .working_space
+        .convert_linear_srgb(hit_result.surface.albedo);
+    Color::new(albedo.red, albedo.green, albedo.blue, 1.0)
+}
+
+/// Arbitrary output variables, first hit buffers rendered alongside the beauty image.
+pub struct AovBuffers {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<AovPixel>, // Row major, top row first like the beauty image
+}
+
+impl AovBuffers {
+    /// Traces `samples_per_pixel` camera rays per pixel and keeps their first hits.
+    pub fn render(world: &HittableList, camera: &Camera) -> Self {
+        let (width, height) = camera.get_image_xy();
+        let mut pixels: Vec<AovPixel> = vec![AovPixel::default(); (width * height) as usize];
+
+        let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let rows_per_thread = (height as usize).div_ceil(num_threads).max(1);
+        thread::scope(|s| {
+            for (chunk_index, chunk) in pixels
+                .chunks_mut(rows_per_thread * width as usize)
+                .enumerate()
+            {
+                let first_row = chunk_index * rows_per_thread;
+                s.spawn(move || {
+                    for (i, pixel) in chunk.iter_mut().enumerate() {
+                        let x = (i % width as usize) as i32;
+                        let y = (first_row + i / width as usize) as i32;
+                        *pixel = render_aov_pixel(world, camera, x, y);
+                    }
+                });
+            }
+        });
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Writes every buffer as a PFM image next to `render_file_path`,
+    /// `render.ppm` becomes `render.depth.pfm`, `render.normal.pfm` and so on.
+    /// PFM has no color space tag, the albedo is in the world's working space.
+    pub fn save(&self, render_file_path: &str) -> Result<(), io::Error> {
+        let path = Path::new(render_file_path);
+        let stem = path.with_extension("");
+        let aov_path = |name: &str| format!("{}.{}.pfm", stem.display(), name);
+
+        self.write_pfm(&aov_path("depth"), |p| [p.depth; 3], 1)?;
+        self.write_pfm(&aov_path("normal"), |p| p.normal.to_array(), 3)?;
+        self.write_pfm(
+            &aov_path("albedo"),
+            |p| [p.albedo.red, p.albedo.green, p.albedo.blue],
+            3,
+        )?;
+        self.write_pfm(&aov_path("position"), |p| p.position.to_array(), 3)?;
+        self.write_pfm(&aov_path("material_id"), |p| [p.material_id as f32; 3], 1)?;
+        self.write_pfm(&aov_path("primitive_id"), |p| [p.primitive_id as f32; 3], 1)?;
+        self.write_pfm(&aov_path("occlusion"), |p| [p.occlusion; 3], 1)?;
+
+        Ok(())
+    }
+
+    /// Portable float map, `channels` is 1 (Pf) or 3 (PF). Rows are stored bottom to top.
+    fn write_pfm(
+        &self,
+        file_path: &str,
+        value: impl Fn(&AovPixel) -> [f32; 3],
+        channels: usize,
+    ) -> Result<(), io::Error> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        let magic = if channels == 1 { "Pf" } else { "PF" };
+        write!(writer, "{}\n{} {}\n-1.0\n", magic, self.width, self.height)?;
+
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let values = value(&self.pixels[(x + y * self.width) as usize]);
+                for v in values.iter().take(channels) {
+                    writer.write_all(&v.to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// One sample estimate of the solid angle above the hit that reaches `AOV_OCCLUSION_DISTANCE`
+/// unblocked, over the solid angle of the hemisphere.
+fn open_hemisphere(world: &HittableList, ray: &Ray, hit_result: &HitResult) -> f32 {
+    let local = square_to_uniform_hemisphere(next_2d());
+    let mut occlusion_ray = Ray::new(
+        hit_result.location,
+        local_to_world(local, hit_result.normal),
+    );
+    occlusion_ray.time = ray.time;
+    let visibility = world.transmittance(
+        &occlusion_ray,
+        Interval::new(0.0001, AOV_OCCLUSION_DISTANCE),
+    );
+    visibility / uniform_hemisphere_pdf(local) / (2.0 * PI)
+}
+
+fn render_aov_pixel(world: &HittableList, camera: &Camera, x: i32, y: i32) -> AovPixel {
+    let mut pixel = AovPixel::default();
+    let mut num_hits = 0;
+    for aa in 0..camera.samples_per_pixel {
+        // Same pixel samples as the beauty render, so deterministic samplers trace the same rays
+        begin_pixel_sample(&camera.sampler, x, y, aa as u32);
+        let Some(camera_ray) = camera.get_ray(x, y) else {
+            continue;
+        };
+        let Some((hit_result, index)) =
+            world.hit_all_indexed(&camera_ray.ray, Interval::new(0.0001, f32::INFINITY))
+        else {
+            continue;
+        };
+
+        if num_hits == 0 {
+            pixel.material_id = hit_result.material_id;
+            pixel.primitive_id = index as i32;
+        }
+        num_hits += 1;
+        pixel.depth += hit_result.t;
+        pixel.normal += hit_result.normal;
+        pixel.albedo += aov_albedo(world, &hit_result);
+        pixel.position += hit_result.location;
+        pixel.occlusion += open_hemisphere(world, &camera_ray.ray, &hit_result);
+    }
+    end_pixel_sample();
+
+    if num_hits > 0 {
+        let inv_hits = 1.0 / num_hits as f32;
+        pixel.depth *= inv_hits;
+        pixel.normal = pixel.normal.normalize_or_zero();
+        pixel.albedo *= inv_hits;
+        pixel.albedo.alpha = 1.0;
+        pixel.position *= inv_hits;
+        pixel.occlusion *= inv_hits;
+        pixel.coverage = num_hits as f32 / camera.samples_per_pixel.max(1) as f32;
+    }
+
+    pixel
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        material::MATERIAL_LAMBERTIAN,
+        ray::{Sphere, SurfaceAttributes},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_aov_first_hit_buffers() {
+        let mut world = HittableList::new();
+        // Behind the camera, only there to give the visible sphere index 1
+        world.add_hittable(Box::new(Sphere {
+            center: Vec3::new(0.0, 0.0, 5.0),
+            radius: 1.0,
+            material_id: MATERIAL_LAMBERTIAN,
+            surface: SurfaceAttributes::default(),
+        }));
+        world.add_hittable(Box::new(Sphere {
+            center: Vec3::new(0.0, 0.0, -5.0),
+            radius: 1.0,
+            material_id: MATERIAL_LAMBERTIAN,
+            surface: SurfaceAttributes {
+                albedo: Color::new(0.2, 0.4, 0.6, 1.0),
+                ..Default::default()
+            },
+        }));
+
+        let mut camera = Camera::new();
+        camera.image_width = 9;
+        camera.fov = 60.0;
+        camera.initialize();
+
+        let aovs = AovBuffers::render(&world, &camera);
+        let center = aovs.pixels[(4 + 4 * aovs.width) as usize];
+        assert!((center.depth - 4.0).abs() < 1e-3);
+        assert!((center.normal - Vec3::Z).length() < 1e-3);
+        assert!((center.position - Vec3::new(0.0, 0.0, -4.0)).length() < 1e-3);
+        assert!((center.albedo.green - 0.4).abs() < 1e-6);
+        assert_eq!(center.material_id, MATERIAL_LAMBERTIAN);
+        assert_eq!(center.primitive_id, 1);
+        assert_eq!(center.coverage, 1.0);
+        // Nothing else within reach of the visible sphere
+        assert!((center.occlusion - 1.0).abs() < 1e-5);
+
+        let corner = aovs.pixels[0];
+        assert_eq!(corner.primitive_id, AOV_NO_HIT_ID);
+        assert_eq!(corner.depth, 0.0);
+    }
+}
@@ -0,0 +1,134 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{
+    color::Color,
+    spectrum::{cie_xyz, xyz_to_linear_srgb},
+};
+
+const PLANCK: f64 = 6.626_070_15e-34;
+const LIGHT_SPEED: f64 = 299_792_458.0;
+const BOLTZMANN: f64 = 1.380_649e-23;
+const STEFAN_BOLTZMANN: f32 = 5.670_374e-8;
+/// Lumens per watt of 555 nm light, the peak of the luminosity function.
+pub const MAX_LUMINOUS_EFFICACY: f32 = 683.0;
+
+/// Planck's law, spectral radiance of a blackbody in W / (sr m^2 m) at `lambda` in nm.
+pub fn planck(lambda: f32, temperature: f32) -> f32 {
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+    let lambda = lambda as f64 * 1e-9;
+    let numerator = 2.0 * PLANCK * LIGHT_SPEED * LIGHT_SPEED;
+    let exponent = PLANCK * LIGHT_SPEED / (lambda * BOLTZMANN * temperature as f64);
+    (numerator / (lambda.powi(5) * exponent.exp_m1())) as f32
+}
+
+/// XYZ radiance of a blackbody, integrated over the range of the color matching functions.
+fn blackbody_xyz(temperature: f32) -> Vec3 {
+    let mut xyz = Vec3::ZERO;
+    let mut lambda = 360.5;
+    while lambda < 830.0 {
+        // 1 nm steps, Planck's law is per meter
+        xyz += cie_xyz(lambda) * planck(lambda, temperature) * 1e-9;
+        lambda += 1.0;
+    }
+    xyz
+}
+
+/// Total power of a light, converted with the luminous efficacy of its spectrum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightPower {
+    Watts(f32),
+    Lumens(f32),
+}
+
+/// Emitter with the spectrum of a blackbody at `temperature` in Kelvin.
+/// Colors are scaled so their luminance is in lumens / 683, which keeps one watt of 555 nm
+/// light at luminance 1 and treats the renderer's values as watts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Blackbody {
+    pub temperature: f32,
+    pub power: LightPower,
+}
+
+impl Blackbody {
+    pub fn new(temperature: f32, power: LightPower) -> Self {
+        Self { temperature, power }
+    }
+
+    /// Chromaticity of the blackbody in linear sRGB, normalized to luminance 1.
+    pub fn color(&self) -> Color {
+        let xyz = blackbody_xyz(self.temperature);
+        if xyz.y <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0, 1.0);
+        }
+        let [red, green, blue] = xyz_to_linear_srgb(xyz / xyz.y);
+        // Very low temperatures fall outside the gamut
+        Color::new(red.max(0.0), green.max(0.0), blue.max(0.0), 1.0)
+    }
+
+    /// Lumens per watt of emitted power, the visible part of the spectrum weighted by
+    /// the luminosity function.
+    pub fn luminous_efficacy(&self) -> f32 {
+        if self.temperature <= 0.0 {
+            return 0.0;
+        }
+        // Stefan-Boltzmann gives the exitance over all wavelengths, pi times the radiance
+        let radiance = STEFAN_BOLTZMANN * self.temperature.powi(4) / PI;
+        MAX_LUMINOUS_EFFICACY * blackbody_xyz(self.temperature).y / radiance
+    }
+
+    pub fn luminous_flux(&self) -> f32 {
+        match self.power {
+            LightPower::Watts(watts) => watts * self.luminous_efficacy(),
+            LightPower::Lumens(lumens) => lumens,
+        }
+    }
+
+    /// Radiant intensity of a point light emitting the power equally in all directions,
+    /// for `PointLight::intensity`.
+    pub fn intensity(&self) -> Color {
+        self.color() * (self.luminous_flux() / MAX_LUMINOUS_EFFICACY / (4.0 * PI))
+    }
+
+    /// Radiance of a diffuse emitter with `area` in m^2 emitting the power from one side,
+    /// for `SurfaceAttributes::emissive`.
+    pub fn radiance(&self, area: f32) -> Color {
+        if area <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0, 1.0);
+        }
+        self.color() * (self.luminous_flux() / MAX_LUMINOUS_EFFICACY / (PI * area))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::framebuffer::luminance;
+
+    use super::*;
+
+    #[test]
+    fn test_blackbody_chromaticity() {
+        // D65 is close to a 6504 K blackbody
+        let white = Blackbody::new(6504.0, LightPower::Lumens(1.0)).color();
+        assert!((luminance(white) - 1.0).abs() < 1e-3);
+        assert!((white.red - white.blue).abs() < 0.1 && (white.green - white.blue).abs() < 0.1);
+
+        let candle = Blackbody::new(1900.0, LightPower::Lumens(1.0)).color();
+        assert!(candle.red > candle.green && candle.green > candle.blue);
+    }
+
+    #[test]
+    fn test_blackbody_power() {
+        // Sun like and incandescent bulb efficacies
+        let sun = Blackbody::new(5800.0, LightPower::Watts(1.0));
+        assert!((sun.luminous_efficacy() - 93.0).abs() < 5.0);
+        let bulb = Blackbody::new(2856.0, LightPower::Watts(1.0));
+        assert!((bulb.luminous_efficacy() - 15.0).abs() < 2.0);
+
+        let light = Blackbody::new(3000.0, LightPower::Lumens(4.0 * PI * MAX_LUMINOUS_EFFICACY));
+        assert!((luminance(light.intensity()) - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufReader, Read},
+    sync::Arc,
+};
+
+use glam::Vec2;
+
+use crate::warp::{sample_cdf, square_to_concentric_disk, square_to_uniform_triangle};
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Transmission of the lens opening from a grayscale image, white is open.
+/// The image covers the square around the unit disk of the aperture.
+pub struct ApertureMask {
+    pub width: usize,
+    pub height: usize,
+    row_cdf: Vec<f32>,    // Marginal over the rows, height + 1 entries
+    column_cdf: Vec<f32>, // Conditional within each row, width + 1 entries per row
+}
+
+impl ApertureMask {
+    pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Result<Self, io::Error> {
+        if width == 0 || height == 0 {
+            return Err(invalid_data("aperture mask dimensions must not be zero"));
+        }
+        if width.checked_mul(height) != Some(weights.len()) {
+            return Err(invalid_data("aperture mask weights do not match its size"));
+        }
+
+        let mut column_cdf: Vec<f32> = Vec::with_capacity((width + 1) * height);
+        let mut row_cdf: Vec<f32> = vec![0.0; height + 1];
+        for y in 0..height {
+            let start = column_cdf.len();
+            column_cdf.push(0.0);
+            for x in 0..width {
+                let previous = column_cdf[start + x];
+                column_cdf.push(previous + weights[x + y * width].max(0.0));
+            }
+            let row_sum = column_cdf[start + width];
+            row_cdf[y + 1] = row_cdf[y] + row_sum;
+            if row_sum > 0.0 {
+                for value in column_cdf[start..].iter_mut() {
+                    *value /= row_sum;
+                }
+            }
+        }
+        let total = row_cdf[height];
+        if total <= 0.0 || total.is_nan() {
+            return Err(invalid_data("aperture mask is fully closed"));
+        }
+        for value in row_cdf.iter_mut() {
+            *value /= total;
+        }
+
+        Ok(Self {
+            width,
+            height,
+            row_cdf,
+            column_cdf,
+        })
+    }
+
+    /// Binary or plain PGM and PPM, colors are averaged.
+    pub fn load(path: &str) -> Result<Self, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read(&mut reader)
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, io::Error> {
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        // Header tokens, skipping comments, followed by one whitespace before binary data
+        let mut position = 0;
+        let next_token = |position: &mut usize| -> Result<String, io::Error> {
+            loop {
+                while *position < bytes.len() && bytes[*position].is_ascii_whitespace() {
+                    *position += 1;
+                }
+                if *position < bytes.len() && bytes[*position] == b'#' {
+                    while *position < bytes.len() && bytes[*position] != b'\n' {
+                        *position += 1;
+                    }
+                    continue;
+                }
+                break;
+            }
+            let start = *position;
+            while *position < bytes.len() && !bytes[*position].is_ascii_whitespace() {
+                *position += 1;
+            }
+            if start == *position {
+                return Err(invalid_data("unexpected end of image"));
+            }
+            Ok(String::from_utf8_lossy(&bytes[start..*position]).to_string())
+        };
+        let parse = |token: String| -> Result<usize, io::Error> {
+            token
+                .parse::<usize>()
+                .map_err(|_| invalid_data("invalid image header"))
+        };
+
+        let magic = next_token(&mut position)?;
+        let (channels, binary) = match magic.as_str() {
+            "P2" => (1, false),
+            "P3" => (3, false),
+            "P5" => (1, true),
+            "P6" => (3, true),
+            _ => return Err(invalid_data("aperture mask must be a PGM or PPM image")),
+        };
+        let width = parse(next_token(&mut position)?)?;
+        let height = parse(next_token(&mut position)?)?;
+        let max_value = parse(next_token(&mut position)?)?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid_data("invalid image max value"));
+        }
+
+        if width == 0 || height == 0 {
+            return Err(invalid_data("aperture mask dimensions must not be zero"));
+        }
+        // Every value takes at least one byte, larger headers cannot match the file
+        let num_values = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(channels))
+            .filter(|n| *n <= bytes.len())
+            .ok_or_else(|| invalid_data("image is larger than its data"))?;
+        let mut values: Vec<f32> = Vec::with_capacity(num_values);
+        if binary {
+            position += 1;
+            let bytes_per_value = if max_value < 256 { 1 } else { 2 };
+            let data = bytes
+                .get(position..position + num_values * bytes_per_value)
+                .ok_or_else(|| invalid_data("image data is too short"))?;
+            for value in data.chunks_exact(bytes_per_value) {
+                let value = if bytes_per_value == 1 {
+                    value[0] as f32
+                } else {
+                    u16::from_be_bytes([value[0], value[1]]) as f32
+                };
+                values.push(value / max_value as f32);
+            }
+        } else {
+            for _i in 0..num_values {
+                values.push(parse(next_token(&mut position)?)? as f32 / max_value as f32);
+            }
+        }
+
+        let weights: Vec<f32> = values
+            .chunks_exact(channels)
+            .map(|pixel| pixel.iter().sum::<f32>() / channels as f32)
+            .collect();
+        Self::new(width, height, weights)
+    }
+
+    /// Point in [-1, 1]^2 distributed like the mask, +y is the top row of the image.
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        let (y, v) = sample_cdf(&self.row_cdf, u.y);
+        let row = &self.column_cdf[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let (x, s) = sample_cdf(row, u.x);
+        let image = Vec2::new(
+            (x as f32 + s) / self.width as f32,
+            (y as f32 + v) / self.height as f32,
+        );
+        Vec2::new(image.x * 2.0 - 1.0, 1.0 - image.y * 2.0)
+    }
+}
+
+/// Shape of the lens opening, and so of out of focus highlights.
+#[derive(Clone, Default)]
+pub enum ApertureShape {
+    #[default]
+    Circle,
+    /// Regular polygon inscribed in the circle, `rotation` in deg turns the first blade.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+    Mask(Arc<ApertureMask>),
+}
+
+impl ApertureShape {
+    /// Point on the aperture within the unit disk, from a uniform `u`.
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        match self {
+            ApertureShape::Circle => square_to_concentric_disk(u),
+            ApertureShape::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                // Pick a blade's triangle from the center, then a point in it
+                let scaled = u.x * blades as f32;
+                let blade = (scaled as u32).min(blades - 1);
+                let u = Vec2::new(scaled - blade as f32, u.y);
+
+                let angle = |i: u32| rotation.to_radians() + 2.0 * PI * i as f32 / blades as f32;
+                let corner = |i: u32| Vec2::new(angle(i).cos(), angle(i).sin());
+                let b = square_to_uniform_triangle(u);
+                corner(blade) * b.x + corner(blade + 1) * b.y
+            }
+            ApertureShape::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+/// Lens opening and the mechanical vignetting of the barrel around it.
+#[derive(Clone, Default)]
+pub struct Aperture {
+    pub shape: ApertureShape,
+    /// Offset of the barrel opening at the image corners, in aperture radii. Off axis pixels
+    /// only see the overlap of the two, out of focus highlights become cat's eyes and the
+    /// corners darken. 0 turns it off.
+    pub cat_eye: f32,
+}
+
+impl Aperture {
+    /// Aperture point in the unit disk for a pixel at `image_position`, which is -1 to 1 from
+    /// corner to corner. `None` when the barrel blocks it.
+    pub fn sample(&self, u: Vec2, image_position: Vec2) -> Option<Vec2> {
+        let p = self.shape.sample(u);
+        if self.cat_eye > 0.0 {
+            let barrel_center = image_position * self.cat_eye;
+            if (p - barrel_center).length_squared() > 1.0 {
+                return None;
+            }
+        }
+        Some(p)
+    }
+}
+
+/// Lens dispersion, each ray picks one color channel and is weighted to only carry it.
+/// Red is pushed out and blue pulled in, green stays where the lens is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChromaticAberration {
+    pub lateral: f32, // Relative change in image scale between green and the outer channels
+    pub longitudinal: f32, // Relative change in focus distance between green and the outer channels
+}
+
+impl ChromaticAberration {
+    pub fn is_enabled(&self) -> bool {
+        self.lateral != 0.0 || self.longitudinal != 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygon_aperture_stays_inside() {
+        let shape = ApertureShape::Polygon {
+            blades: 6,
+            rotation: 15.0,
+        };
+        // Distance from the center to the edges of the hexagon
+        let apothem = (PI / 6.0).cos();
+        let mut max_radius: f32 = 0.0;
+        for i in 0..32 {
+            for j in 0..32 {
+                let u = Vec2::new((i as f32 + 0.5) / 32.0, (j as f32 + 0.5) / 32.0);
+                let p = shape.sample(u);
+                assert!(p.length() <= 1.0 + 1e-5);
+                max_radius = max_radius.max(p.length());
+                let angle = p.y.atan2(p.x) - 15.0_f32.to_radians();
+                let sector = (angle / (PI / 3.0)).rem_euclid(1.0) * PI / 3.0 - PI / 6.0;
+                assert!(p.length() * sector.cos() <= apothem + 1e-4);
+            }
+        }
+        assert!(max_radius > 0.9);
+
+        // The barrel clips samples far off axis
+        let aperture = Aperture {
+            shape: ApertureShape::Circle,
+            cat_eye: 1.0,
+        };
+        assert!(aperture
+            .sample(Vec2::new(0.05, 0.5), Vec2::new(1.0, 0.0))
+            .is_none());
+        assert!(aperture
+            .sample(Vec2::new(0.95, 0.5), Vec2::new(1.0, 0.0))
+            .is_some());
+    }
+
+    #[test]
+    fn test_aperture_mask_from_pgm() {
+        // Only the top right pixel is open
+        let pgm = b"P2\n# mask\n2 2\n255\n0 255\n0 0\n";
+        let mask = ApertureMask::read(&mut &pgm[..]).unwrap();
+        for u in [
+            Vec2::new(0.1, 0.2),
+            Vec2::new(0.9, 0.7),
+            Vec2::new(0.5, 0.5),
+        ] {
+            let p = mask.sample(u);
+            assert!(p.x >= 0.0 && p.x <= 1.0 && p.y >= 0.0 && p.y <= 1.0);
+        }
+
+        let binary = b"P5 2 1 255\n\x00\xff";
+        let mask = ApertureMask::read(&mut &binary[..]).unwrap();
+        // Only the right half is open
+        for u in [Vec2::new(0.1, 0.5), Vec2::new(0.9, 0.5)] {
+            assert!(mask.sample(u).x > 0.0);
+        }
+
+        // Empty, oversized and fully closed masks cannot be sampled
+        assert!(ApertureMask::read(&mut &b"P2 0 1 255\n"[..]).is_err());
+        let huge = b"P5 4294967296 4294967296 255\n\x00";
+        assert!(ApertureMask::read(&mut &huge[..]).is_err());
+        assert!(ApertureMask::read(&mut &b"P2 2 1 255\n0 0\n"[..]).is_err());
+    }
+}
@@ -12,25 +12,53 @@ use std::{
 use palette::{Clamp, Srgb, Srgba};
 
 use crate::{
+    bokeh::{Aperture, ChromaticAberration},
     color::{color::*, Color},
+    exposure::{Exposure, ExposureMode},
+    filter::ReconstructionFilter,
     interval::*,
+    lens::LensSystem,
     material::*,
     math::{math::*, *},
     progress_bar::ProgressBar,
     random::*,
     ray::*,
+    sampler::{next_1d, next_2d, IndependentSampler, Sampler},
 };
 
+/// Ray leaving the camera, `weight` scales what it brings back.
+pub struct CameraRay {
+    pub ray: Ray,
+    pub weight: Color,
+}
+
+/// How pixels map to rays.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// Full sphere by longitude and latitude, for an image twice as wide as high. Rays start
+    /// `eye_offset` to the right of their direction around the position, which gives
+    /// omnidirectional stereo with plus and minus half the interocular distance.
+    Equirectangular { eye_offset: f32 },
+}
+
 #[derive(Clone)]
 pub struct Camera {
     pub position: Vec3,
     pub rotation: Vec4,
     pub fov: f32, // Deg
+    pub projection: Projection,
+    pub viewport_shift: Vec2, // In viewport sizes, off axis projection without turning the camera
 
     pub aspect_ratio: f32,
     pub image_width: i32,
     pub samples_per_pixel: i32,
     pub max_ray_per_pixel: i32,
+    pub spectral: bool, // Trace hero wavelengths instead of RGB, needed for dispersion
+    pub filter: ReconstructionFilter, // How samples are weighted into the pixels around them
+    pub sampler: Arc<dyn Sampler + Sync + Send>, // Drives pixel jitter, lens and BSDF samples
+    pub exposure: Exposure, // Physical settings, also drive the aperture and shutter unless off
 
     pub image_height: i32,
     image_size: [i32; 2],
@@ -43,6 +71,9 @@ pub struct Camera {
 
     pub defocus_angle: f32, // Variation angle of rays through each pixel
     pub focus_dist: f32,    // distance from camera lookfrom point to plane of perfect focus
+    pub aperture: Aperture, // Bokeh shape and cat's eye vignetting, used with defocus
+    pub chromatic_aberration: ChromaticAberration,
+    pub lens: Option<LensSystem>, // Traced instead of the thin lens, the film is the exposure's sensor
 
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
@@ -60,10 +91,16 @@ impl Camera {
             position: Vec3::new(0.0, 0.0, 0.0),
             rotation: Vec4::new(0.0, 0.0, 0.0, 0.0),
             fov: 90.0,
+            projection: Projection::Perspective,
+            viewport_shift: Vec2::new(0.0, 0.0),
             aspect_ratio: 1.0,
             image_width: 100,
             samples_per_pixel: 1,
             max_ray_per_pixel: 3,
+            spectral: false,
+            filter: ReconstructionFilter::Box,
+            sampler: Arc::new(IndependentSampler {}),
+            exposure: Exposure::new(),
             image_height: 0,
             image_size: [0, 0],
             pixel_delta_u: Vec3::new(0.0, 0.0, 0.0),
@@ -76,6 +113,9 @@ impl Camera {
             ),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            aperture: Aperture::default(),
+            chromatic_aberration: ChromaticAberration::default(),
+            lens: None,
             defocus_disk_u: Vec3::new(1.0, 0.0, 0.0),
             defocus_disk_v: Vec3::new(0.0, 1.0, 0.0),
         }
@@ -90,6 +130,11 @@ impl Camera {
         self.camera_mat = Mat3::from_cols(right, up, forward);
     }
 
+    /// World direction of the image's +x.
+    pub fn right(&self) -> Vec3 {
+        self.camera_mat.x_axis
+    }
+
     pub fn get_image_xy(&self) -> (i32, i32) {
         (self.image_width, self.image_height)
     }
@@ -109,47 +154,168 @@ impl Camera {
         let viewport_upper_left = self.position
             - self.camera_mat.z_axis * self.focus_dist as f32
             - viewport_u / 2.0
-            - viewport_v / 2.0;
+            - viewport_v / 2.0
+            + viewport_u * self.viewport_shift.x
+            - viewport_v * self.viewport_shift.y;
 
         self.pixel_delta_u = viewport_u / self.image_width as f32;
         self.pixel_delta_v = viewport_v / self.image_height as f32;
 
         self.pixel00_loc = viewport_upper_left + (self.pixel_delta_u + self.pixel_delta_v) * 0.5;
 
-        let defocus_radius: f32 =
-            (self.focus_dist * deg_to_rad(self.defocus_angle as f64 * 0.5) as f32).tan();
+        let defocus_radius: f32 = if self.exposure.mode != ExposureMode::Off {
+            // The lens opening of the f-stop, defocus_angle is kept in sync for reference
+            let aperture_radius = 0.5 * self.exposure.aperture_diameter(self.fov);
+            self.defocus_angle = 2.0 * (aperture_radius / self.focus_dist).atan().to_degrees();
+            aperture_radius
+        } else {
+            (self.focus_dist * deg_to_rad(self.defocus_angle as f64 * 0.5) as f32).tan()
+        };
         self.defocus_disk_u = self.camera_mat.x_axis * defocus_radius;
         self.defocus_disk_v = self.camera_mat.y_axis * defocus_radius;
+
+        let film_size = self.film_size();
+        if let Some(lens) = &mut self.lens {
+            lens.prepare(film_size, self.focus_dist);
+        }
     }
 
-    pub fn get_ray(&self, x: i32, y: i32) -> Ray {
-        let pixel_center: Vec3 =
-            self.pixel00_loc + (self.pixel_delta_u * x as f32) + (self.pixel_delta_v * y as f32);
+    /// Physical size of the sensor, the exposure's sensor height at the image aspect ratio.
+    pub fn film_size(&self) -> Vec2 {
+        let aspect_ratio = self.image_width as f32 / self.image_height.max(1) as f32;
+        Vec2::new(
+            self.exposure.sensor_height * aspect_ratio,
+            self.exposure.sensor_height,
+        )
+    }
+
+    pub fn get_ray(&self, x: i32, y: i32) -> Option<CameraRay> {
         let pixel_rand_offset = if self.samples_per_pixel > 1 {
             self.pixel_sample_square()
         } else {
-            Vec3::new(0.0, 0.0, 0.0)
+            Vec2::new(0.0, 0.0)
         };
-        let pixel_sample = pixel_center + pixel_rand_offset;
+        self.get_ray_offset(x, y, pixel_rand_offset)
+    }
+
+    /// Ray through pixel (x, y) displaced by `offset` pixels from its center.
+    /// `None` when the lens barrel blocks it, see `Aperture::cat_eye`.
+    pub fn get_ray_offset(&self, x: i32, y: i32, offset: Vec2) -> Option<CameraRay> {
+        if let Projection::Equirectangular { eye_offset } = self.projection {
+            return Some(self.get_equirectangular_ray(eye_offset, x, y, offset));
+        }
+        if let Some(lens) = &self.lens {
+            return self.get_lens_ray(lens, x, y, offset);
+        }
+
+        let pixel_center: Vec3 =
+            self.pixel00_loc + (self.pixel_delta_u * x as f32) + (self.pixel_delta_v * y as f32);
+        let mut pixel_sample =
+            pixel_center + (offset.x * self.pixel_delta_u) + (offset.y * self.pixel_delta_v);
+
+        let mut weight: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+        if self.chromatic_aberration.is_enabled() {
+            // One channel per ray, weighted by 3 so the channels average back to white
+            let channel = ((next_1d() * 3.0) as usize).min(2);
+            let shift = 1.0 - channel as f32;
+            let focus_center = self.position - self.camera_mat.z_axis * self.focus_dist;
+            pixel_sample = focus_center
+                + (pixel_sample - focus_center) * (1.0 + self.chromatic_aberration.lateral * shift);
+            pixel_sample = self.position
+                + (pixel_sample - self.position)
+                    * (1.0 + self.chromatic_aberration.longitudinal * shift);
+
+            let mut channels = [0.0; 3];
+            channels[channel] = 3.0;
+            weight = Color::new(channels[0], channels[1], channels[2], 1.0);
+        }
 
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.position
         } else {
-            self.defocus_disk_sample()
+            // Corners at length 1, with +y up like the defocus disk
+            let half_diagonal = 0.5 * (self.image_size[0] as f32).hypot(self.image_size[1] as f32);
+            let image_position = Vec2::new(
+                x as f32 + 0.5 + offset.x - 0.5 * self.image_size[0] as f32,
+                0.5 * self.image_size[1] as f32 - (y as f32 + 0.5 + offset.y),
+            ) / half_diagonal;
+            self.defocus_disk_sample(image_position)?
         };
         let ray_direction = pixel_sample - ray_origin;
 
-        Ray::new(ray_origin, ray_direction)
+        let mut ray: Ray = Ray::new(ray_origin, ray_direction);
+        ray.time = self.shutter_time_sample();
+        Some(CameraRay { ray, weight })
+    }
+
+    /// Like `get_ray_offset`, traced through the elements of `lens`.
+    fn get_lens_ray(&self, lens: &LensSystem, x: i32, y: i32, offset: Vec2) -> Option<CameraRay> {
+        let film_size = self.film_size();
+        let s = Vec2::new(
+            (x as f32 + 0.5 + offset.x) / self.image_size[0] as f32,
+            (y as f32 + 0.5 + offset.y) / self.image_size[1] as f32,
+        );
+        // The lens flips the image, the top left pixel is at the bottom right of the film
+        let film_point = Vec2::new((0.5 - s.x) * film_size.x, (s.y - 0.5) * film_size.y);
+        let (lens_ray, weight) = lens.generate_ray(film_point, next_2d())?;
+
+        // Lens space looks down +z, the camera down -z_axis
+        let to_world = |v: Vec3| {
+            self.camera_mat.x_axis * v.x + self.camera_mat.y_axis * v.y
+                - self.camera_mat.z_axis * v.z
+        };
+        let mut ray: Ray = Ray::new(
+            self.position + to_world(lens_ray.origin),
+            to_world(lens_ray.direction),
+        );
+        ray.time = self.shutter_time_sample();
+        Some(CameraRay {
+            ray,
+            weight: Color::new(weight, weight, weight, 1.0),
+        })
+    }
+
+    fn get_equirectangular_ray(&self, eye_offset: f32, x: i32, y: i32, offset: Vec2) -> CameraRay {
+        let s = Vec2::new(
+            (x as f32 + 0.5 + offset.x) / self.image_size[0] as f32,
+            (y as f32 + 0.5 + offset.y) / self.image_size[1] as f32,
+        );
+        // Longitude 0 looks where the camera does, latitude is up from the horizon
+        let longitude = (s.x - 0.5) * 2.0 * std::f32::consts::PI;
+        let latitude = (0.5 - s.y) * std::f32::consts::PI;
+        let (right, up, forward) = (
+            self.camera_mat.x_axis,
+            self.camera_mat.y_axis,
+            -self.camera_mat.z_axis,
+        );
+        let direction = right * (longitude.sin() * latitude.cos())
+            + up * latitude.sin()
+            + forward * (longitude.cos() * latitude.cos());
+        let eye_right = right * longitude.cos() - forward * longitude.sin();
+
+        let mut ray: Ray = Ray::new(self.position + eye_right * eye_offset, direction);
+        ray.time = self.shutter_time_sample();
+        CameraRay {
+            ray,
+            weight: Color::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Seconds into the shutter, always 0 without physical exposure.
+    fn shutter_time_sample(&self) -> f32 {
+        if self.exposure.mode == ExposureMode::Off {
+            return 0.0;
+        }
+        next_1d() * self.exposure.shutter_time
     }
 
-    fn pixel_sample_square(&self) -> Vec3 {
-        let px = rand_range(-0.5..0.5);
-        let py = rand_range(-0.5..0.5);
-        (px * self.pixel_delta_u) + (py * self.pixel_delta_v)
+    /// Uniform offset within the pixel square, in pixels from the center.
+    pub fn pixel_sample_square(&self) -> Vec2 {
+        next_2d() - Vec2::new(0.5, 0.5)
     }
 
-    fn defocus_disk_sample(&self) -> Vec3 {
-        let p = rand_disc_vec2();
-        return self.position + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v);
+    fn defocus_disk_sample(&self, image_position: Vec2) -> Option<Vec3> {
+        let p = self.aperture.sample(next_2d(), image_position)?;
+        return Some(self.position + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v));
     }
 }
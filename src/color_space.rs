@@ -0,0 +1,200 @@
+use palette::{LinSrgb, Srgb};
+
+use crate::{color::Color, framebuffer::luminance};
+
+type Matrix = [[f32; 3]; 3];
+
+// Primaries conversions through linear sRGB, ACEScg is adapted from D60 to D65 with Bradford
+const SRGB_TO_ACESCG: Matrix = [
+    [0.613_097_4, 0.339_523_1, 0.047_379_5],
+    [0.070_193_7, 0.916_353_9, 0.013_452_4],
+    [0.020_615_6, 0.109_569_8, 0.869_814_7],
+];
+const ACESCG_TO_SRGB: Matrix = [
+    [1.705_051, -0.621_792_1, -0.083_259],
+    [-0.130_256_4, 1.140_804_8, -0.010_548_4],
+    [-0.024_003_4, -0.128_969, 1.152_972_4],
+];
+const SRGB_TO_REC2020: Matrix = [
+    [0.627_404, 0.329_282, 0.043_313_6],
+    [0.069_097, 0.919_54, 0.011_361_2],
+    [0.016_391_6, 0.088_013_2, 0.895_595],
+];
+const REC2020_TO_SRGB: Matrix = [
+    [1.660_491, -0.587_641_1, -0.072_849_9],
+    [-0.124_550_5, 1.132_899_9, -0.008_349_4],
+    [-0.018_150_8, -0.100_578_9, 1.118_729_7],
+];
+const SRGB_TO_DISPLAY_P3: Matrix = [
+    [0.822_462_2, 0.177_538, 0.0],
+    [0.033_194_2, 0.966_805_8, 0.0],
+    [0.017_082_7, 0.072_397_4, 0.910_519_9],
+];
+
+fn transform(matrix: &Matrix, color: Color) -> Color {
+    let rgb = [color.red, color.green, color.blue];
+    let row = |r: &[f32; 3]| r[0] * rgb[0] + r[1] * rgb[1] + r[2] * rgb[2];
+    Color::new(
+        row(&matrix[0]),
+        row(&matrix[1]),
+        row(&matrix[2]),
+        color.alpha,
+    )
+}
+
+/// Primaries the renderer does its math in. Scene colors (albedos, emission, lights, sky)
+/// are given in linear sRGB and converted to this space when a ray reads them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorSpace {
+    #[default]
+    LinearSrgb,
+    AcesCg,
+    Rec2020,
+}
+
+impl ColorSpace {
+    /// Converts a linear sRGB color into this space.
+    pub fn convert_linear_srgb(self, color: Color) -> Color {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => transform(&SRGB_TO_ACESCG, color),
+            ColorSpace::Rec2020 => transform(&SRGB_TO_REC2020, color),
+        }
+    }
+
+    /// Converts a color in this space back to linear sRGB, unclamped.
+    pub fn to_linear_srgb(self, color: Color) -> Color {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::AcesCg => transform(&ACESCG_TO_SRGB, color),
+            ColorSpace::Rec2020 => transform(&REC2020_TO_SRGB, color),
+        }
+    }
+
+    /// Luminance of a color in this space, in the same units as for linear sRGB.
+    pub fn luminance(self, color: Color) -> f32 {
+        luminance(self.to_linear_srgb(color))
+    }
+
+    fn to_linear_rec2020(self, color: Color) -> Color {
+        match self {
+            ColorSpace::Rec2020 => color,
+            _ => transform(&SRGB_TO_REC2020, self.to_linear_srgb(color)),
+        }
+    }
+}
+
+/// ST 2084 perceptual quantizer, `nits` up to 10000 to a signal in [0, 1].
+pub fn pq_encode(nits: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+    let y = (nits / 10000.0).clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+/// How the linear render is encoded into the output image.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputTransform {
+    /// Clipped to the sRGB gamut with the sRGB transfer function.
+    #[default]
+    Srgb,
+    /// Wider P3 primaries with the sRGB transfer function.
+    DisplayP3,
+    /// HDR, nothing is clipped below 10000 nits. A linear value of 1 is shown at `paper_white_nits`.
+    Rec2020Pq { paper_white_nits: f32 },
+}
+
+impl OutputTransform {
+    /// Encoded signal in [0, 1] of a color in `working_space`.
+    pub fn encode(self, color: Color, working_space: ColorSpace) -> [f32; 3] {
+        let srgb_transfer = |linear: Color| {
+            let clamped = LinSrgb::new(
+                linear.red.clamp(0.0, 1.0),
+                linear.green.clamp(0.0, 1.0),
+                linear.blue.clamp(0.0, 1.0),
+            );
+            let encoded = Srgb::from_linear(clamped);
+            [encoded.red, encoded.green, encoded.blue]
+        };
+
+        match self {
+            OutputTransform::Srgb => srgb_transfer(working_space.to_linear_srgb(color)),
+            OutputTransform::DisplayP3 => srgb_transfer(transform(
+                &SRGB_TO_DISPLAY_P3,
+                working_space.to_linear_srgb(color),
+            )),
+            OutputTransform::Rec2020Pq { paper_white_nits } => {
+                let linear = working_space.to_linear_rec2020(color);
+                [linear.red, linear.green, linear.blue]
+                    .map(|value| pq_encode(value.max(0.0) * paper_white_nits))
+            }
+        }
+    }
+
+    /// Name written into image headers that have room for it.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputTransform::Srgb => "sRGB",
+            OutputTransform::DisplayP3 => "Display P3",
+            OutputTransform::Rec2020Pq { .. } => "Rec.2020 PQ",
+        }
+    }
+
+    /// Largest code value of saved images, PQ spreads 10000 nits over the range and bands at 8 bits.
+    pub fn max_code_value(self) -> u16 {
+        match self {
+            OutputTransform::Srgb | OutputTransform::DisplayP3 => 255,
+            OutputTransform::Rec2020Pq { .. } => 65535,
+        }
+    }
+
+    /// H.273 primaries, transfer function, matrix and full range flag for a PNG cICP chunk.
+    pub fn cicp(self) -> [u8; 4] {
+        match self {
+            OutputTransform::Srgb => [1, 13, 0, 1],
+            OutputTransform::DisplayP3 => [12, 13, 0, 1],
+            OutputTransform::Rec2020Pq { .. } => [9, 16, 0, 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_working_space_round_trip() {
+        let color = Color::new(0.8, 0.3, 0.05, 1.0);
+        for space in [
+            ColorSpace::LinearSrgb,
+            ColorSpace::AcesCg,
+            ColorSpace::Rec2020,
+        ] {
+            // White stays white and conversions invert each other
+            let white = space.convert_linear_srgb(Color::new(1.0, 1.0, 1.0, 1.0));
+            assert!((white.red - 1.0).abs() < 1e-3 && (white.blue - 1.0).abs() < 1e-3);
+
+            let round_trip = space.to_linear_srgb(space.convert_linear_srgb(color));
+            let luminance = space.luminance(space.convert_linear_srgb(color));
+            assert!((luminance - crate::framebuffer::luminance(color)).abs() < 1e-4);
+            assert!((round_trip.red - color.red).abs() < 1e-4);
+            assert!((round_trip.blue - color.blue).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_output_transforms() {
+        assert!((pq_encode(10000.0) - 1.0).abs() < 1e-5);
+        assert!((pq_encode(100.0) - 0.508).abs() < 1e-3);
+
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        let srgb = OutputTransform::Srgb.encode(red, ColorSpace::LinearSrgb);
+        assert!((srgb[0] - 1.0).abs() < 1e-5 && srgb[1] == 0.0 && srgb[2] == 0.0);
+        // sRGB red is inside P3, so it needs less than full P3 red
+        let p3 = OutputTransform::DisplayP3.encode(red, ColorSpace::LinearSrgb);
+        assert!(p3[0] < 1.0 && p3[1] > 0.0);
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use crate::{
+    interval::Interval,
+    ray::{HitResult, HitSpan, Hittable, Ray},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOperation {
+    fn is_inside(&self, inside_left: bool, inside_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_left || inside_right,
+            CsgOperation::Intersection => inside_left && inside_right,
+            CsgOperation::Difference => inside_left && !inside_right,
+        }
+    }
+}
+
+/// Boolean combination of two closed hittables, `Difference` removes `right` from `left`.
+/// Each surface keeps the material of the operand it came from.
+#[derive(Clone)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Arc<dyn Hittable + Sync + Send>,
+    pub right: Arc<dyn Hittable + Sync + Send>,
+}
+
+struct SpanEvent {
+    t: f32,
+    is_left: bool,
+    is_enter: bool,
+    hit_result: Option<HitResult>,
+}
+
+impl Csg {
+    pub fn new(
+        operation: CsgOperation,
+        left: Arc<dyn Hittable + Sync + Send>,
+        right: Arc<dyn Hittable + Sync + Send>,
+    ) -> Self {
+        Self {
+            operation,
+            left,
+            right,
+        }
+    }
+
+    fn combine_spans(&self, left_spans: &[HitSpan], right_spans: &[HitSpan]) -> Vec<HitSpan> {
+        let mut events: Vec<SpanEvent> =
+            Vec::with_capacity(2 * (left_spans.len() + right_spans.len()));
+        for (spans, is_left) in [(left_spans, true), (right_spans, false)] {
+            for span in spans {
+                events.push(SpanEvent {
+                    t: span.enter_t(),
+                    is_left,
+                    is_enter: true,
+                    hit_result: span.enter,
+                });
+                events.push(SpanEvent {
+                    t: span.exit_t(),
+                    is_left,
+                    is_enter: false,
+                    hit_result: span.exit,
+                });
+            }
+        }
+        events.sort_by(|a, b| a.t.total_cmp(&b.t));
+
+        let mut combined: Vec<HitSpan> = Vec::new();
+        let mut inside_left = false;
+        let mut inside_right = false;
+        let mut enter: Option<HitResult> = None;
+        for event in events {
+            let was_inside = self.operation.is_inside(inside_left, inside_right);
+            if event.is_left {
+                inside_left = event.is_enter;
+            } else {
+                inside_right = event.is_enter;
+            }
+            let is_inside = self.operation.is_inside(inside_left, inside_right);
+
+            // Operand normals already face against the ray, only the side of the result changes
+            let mut hit_result = event.hit_result;
+            if let Some(hit_result) = hit_result.as_mut() {
+                hit_result.front_face = Some(is_inside);
+            }
+
+            if !was_inside && is_inside {
+                enter = hit_result;
+            } else if was_inside && !is_inside {
+                combined.push(HitSpan {
+                    enter,
+                    exit: hit_result,
+                });
+                enter = None;
+            }
+        }
+
+        if self.operation.is_inside(inside_left, inside_right) {
+            combined.push(HitSpan { enter, exit: None });
+        }
+
+        combined
+    }
+}
+
+impl Hittable for Csg {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        for span in self.hit_spans(ray, Interval::new(interval.min, interval.max)) {
+            for hit_result in [span.enter, span.exit].into_iter().flatten() {
+                if interval.surrounds(hit_result.t) {
+                    return Some(hit_result);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn hit_spans(&self, ray: &Ray, interval: Interval) -> Vec<HitSpan> {
+        let left_spans = self
+            .left
+            .hit_spans(ray, Interval::new(interval.min, interval.max));
+        let right_spans = self.right.hit_spans(ray, interval);
+
+        self.combine_spans(&left_spans, &right_spans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::{
+        planar::AxisAlignedBox,
+        ray::{Sphere, SurfaceAttributes},
+    };
+
+    use super::*;
+
+    fn unit_sphere() -> Arc<dyn Hittable + Sync + Send> {
+        Arc::new(Sphere {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material_id: 1,
+            surface: SurfaceAttributes::default(),
+        })
+    }
+
+    #[test]
+    fn test_csg_sphere_minus_box() {
+        let half_space_box = Arc::new(AxisAlignedBox::new(
+            Vec3::new(0.0, -2.0, -2.0),
+            Vec3::new(2.0, 2.0, 2.0),
+            2,
+            SurfaceAttributes::default(),
+        ));
+        let csg = Csg::new(CsgOperation::Difference, unit_sphere(), half_space_box);
+
+        // The +x half is cut away, the first surface is the box face at x = 0
+        let ray = Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let hit_result = csg.hit(&ray, Interval::new(0.0001, f32::INFINITY)).unwrap();
+        assert!((hit_result.t - 5.0).abs() < 1e-4);
+        assert!((hit_result.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-4);
+        assert_eq!(hit_result.front_face, Some(true));
+        assert_eq!(hit_result.material_id, 2);
+
+        // From the other side the sphere is hit as usual
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit_result = csg.hit(&ray, Interval::new(0.0001, f32::INFINITY)).unwrap();
+        assert!((hit_result.t - 4.0).abs() < 1e-4);
+        assert_eq!(hit_result.material_id, 1);
+    }
+
+    #[test]
+    fn test_csg_intersection_and_inside_ray() {
+        let shifted_sphere = Arc::new(Sphere {
+            center: Vec3::new(1.0, 0.0, 0.0),
+            radius: 1.0,
+            material_id: 2,
+            surface: SurfaceAttributes::default(),
+        });
+        let csg = Csg::new(CsgOperation::Intersection, unit_sphere(), shifted_sphere);
+
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit_result = csg.hit(&ray, Interval::new(0.0001, f32::INFINITY)).unwrap();
+        assert!((hit_result.t - 5.0).abs() < 1e-4);
+        assert_eq!(hit_result.material_id, 2);
+
+        // Starting inside the lens the next surface is an exit
+        let ray = Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit_result = csg.hit(&ray, Interval::new(0.0001, f32::INFINITY)).unwrap();
+        assert!((hit_result.t - 0.5).abs() < 1e-4);
+        assert_eq!(hit_result.front_face, Some(false));
+        assert!((hit_result.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-4);
+    }
+}
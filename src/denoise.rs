@@ -0,0 +1,282 @@
+use glam::Vec3;
+
+use crate::{
+    aov::{AovBuffers, AovPixel},
+    color::Color,
+    color_space::ColorSpace,
+    framebuffer::Framebuffer,
+};
+
+/// B3 spline weights of the a-trous kernel, indexed by the tap distance 0, 1 and 2.
+const ATROUS_KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Smallest albedo divided out, keeps black surfaces from blowing up.
+const ALBEDO_EPSILON: f32 = 0.01;
+
+/// Edge avoiding a-trous wavelet filter guided by the AOV feature buffers.
+/// Each iteration doubles the gap between taps. Luminance differences are measured in
+/// standard deviations of the pixel noise, so converged pixels are left alone.
+#[derive(Clone)]
+pub struct Denoiser {
+    pub iterations: u32, // 5 iterations reach about 64 pixels
+    pub sigma_luminance: f32,
+    pub sigma_normal: f32, // Exponent on the cosine between normals
+    pub sigma_depth: f32,  // Relative to the center depth
+    pub sigma_albedo: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.1,
+        }
+    }
+
+    /// Filters the linear render in place. The albedo is divided out before filtering and
+    /// multiplied back after, so only the lighting is smoothed and textures stay sharp.
+    pub fn denoise(&self, framebuffer: &mut Framebuffer, aovs: &AovBuffers) {
+        assert_eq!(framebuffer.width, aovs.width);
+        assert_eq!(framebuffer.height, aovs.height);
+
+        let mut colors: Vec<Color> = Vec::with_capacity(framebuffer.pixels.len());
+        let mut variances: Vec<f32> = Vec::with_capacity(framebuffer.pixels.len());
+        for (i, aov) in aovs.pixels.iter().enumerate() {
+            let albedo = guide_albedo(aov);
+            colors.push(divide_color(framebuffer.pixels[i], albedo));
+            let albedo_luminance = framebuffer.luminance(albedo);
+            variances.push(framebuffer.variance[i] / (albedo_luminance * albedo_luminance));
+        }
+
+        for iteration in 0..self.iterations {
+            let step: i32 = 1 << iteration;
+            (colors, variances) =
+                self.atrous_pass(&colors, &variances, aovs, step, framebuffer.color_space);
+        }
+
+        for (i, aov) in aovs.pixels.iter().enumerate() {
+            let albedo = guide_albedo(aov);
+            let color = colors[i];
+            framebuffer.pixels[i] = Color::new(
+                color.red * albedo.red,
+                color.green * albedo.green,
+                color.blue * albedo.blue,
+                1.0,
+            );
+            let albedo_luminance = framebuffer.luminance(albedo);
+            framebuffer.variance[i] = variances[i] * albedo_luminance * albedo_luminance;
+        }
+    }
+
+    fn atrous_pass(
+        &self,
+        colors: &[Color],
+        variances: &[f32],
+        aovs: &AovBuffers,
+        step: i32,
+        color_space: ColorSpace,
+    ) -> (Vec<Color>, Vec<f32>) {
+        let (width, height) = (aovs.width, aovs.height);
+        let mut out_colors: Vec<Color> = Vec::with_capacity(colors.len());
+        let mut out_variances: Vec<f32> = Vec::with_capacity(colors.len());
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = (x + y * width) as usize;
+                let aov_p = &aovs.pixels[p];
+                let luminance_p = color_space.luminance(colors[p]);
+                let luminance_scale = self.sigma_luminance * variances[p].max(0.0).sqrt() + 1e-4;
+
+                let mut sum_color: Color = Color::new(0.0, 0.0, 0.0, 0.0);
+                let mut sum_weight: f32 = 0.0;
+                let mut sum_variance: f32 = 0.0;
+                for dy in -2..=2_i32 {
+                    for dx in -2..=2_i32 {
+                        let qx = x + dx * step;
+                        let qy = y + dy * step;
+                        if qx < 0 || qx >= width || qy < 0 || qy >= height {
+                            continue;
+                        }
+                        let q = (qx + qy * width) as usize;
+                        let aov_q = &aovs.pixels[q];
+
+                        let kernel = ATROUS_KERNEL[dx.unsigned_abs() as usize]
+                            * ATROUS_KERNEL[dy.unsigned_abs() as usize];
+                        let weight_luminance = (-(luminance_p - color_space.luminance(colors[q]))
+                            .abs()
+                            / luminance_scale)
+                            .exp();
+                        let weight =
+                            kernel * weight_luminance * self.feature_weight(aov_p, aov_q, step);
+                        if weight.is_nan() || weight <= 0.0 {
+                            continue;
+                        }
+
+                        sum_color += colors[q] * weight;
+                        sum_weight += weight;
+                        sum_variance += weight * weight * variances[q];
+                    }
+                }
+
+                // A zero or NaN normal can reject every tap, the center included
+                if sum_weight <= 0.0 {
+                    out_colors.push(colors[p]);
+                    out_variances.push(variances[p]);
+                    continue;
+                }
+                out_colors.push(sum_color / sum_weight);
+                out_variances.push(sum_variance / (sum_weight * sum_weight));
+            }
+        }
+
+        (out_colors, out_variances)
+    }
+
+    /// Edge stopping weight from the normal, depth and albedo guides.
+    fn feature_weight(&self, aov_p: &AovPixel, aov_q: &AovPixel, step: i32) -> f32 {
+        let hit_p = aov_p.coverage > 0.0;
+        let hit_q = aov_q.coverage > 0.0;
+        if hit_p != hit_q {
+            return 0.0;
+        }
+        if !hit_p {
+            // Both see the background
+            return 1.0;
+        }
+
+        let weight_normal = aov_p
+            .normal
+            .dot(aov_q.normal)
+            .max(0.0)
+            .powf(self.sigma_normal);
+        let depth_scale = self.sigma_depth * aov_p.depth.abs() * step as f32 + 1e-4;
+        let weight_depth = (-(aov_p.depth - aov_q.depth).abs() / depth_scale).exp();
+        let albedo_difference = color_to_vec3(aov_p.albedo) - color_to_vec3(aov_q.albedo);
+        let weight_albedo =
+            (-albedo_difference.length_squared() / (self.sigma_albedo * self.sigma_albedo)).exp();
+
+        weight_normal * weight_depth * weight_albedo
+    }
+}
+
+fn color_to_vec3(color: Color) -> Vec3 {
+    Vec3::new(color.red, color.green, color.blue)
+}
+
+/// Albedo the render is divided by, white where nothing was hit.
+fn guide_albedo(aov: &AovPixel) -> Color {
+    if aov.coverage <= 0.0 {
+        return Color::new(1.0, 1.0, 1.0, 1.0);
+    }
+    Color::new(
+        aov.albedo.red.max(ALBEDO_EPSILON),
+        aov.albedo.green.max(ALBEDO_EPSILON),
+        aov.albedo.blue.max(ALBEDO_EPSILON),
+        1.0,
+    )
+}
+
+fn divide_color(color: Color, albedo: Color) -> Color {
+    Color::new(
+        color.red / albedo.red,
+        color.green / albedo.green,
+        color.blue / albedo.blue,
+        1.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::random::rand_range;
+
+    use super::*;
+
+    const SIZE: i32 = 32;
+
+    fn flat_aovs(normal_at: impl Fn(i32) -> Vec3) -> AovBuffers {
+        let mut pixels: Vec<AovPixel> = Vec::new();
+        for _y in 0..SIZE {
+            for x in 0..SIZE {
+                pixels.push(AovPixel {
+                    depth: 5.0,
+                    normal: normal_at(x),
+                    albedo: Color::new(0.5, 0.5, 0.5, 1.0),
+                    position: Vec3::ZERO,
+                    material_id: 1,
+                    primitive_id: 0,
+                    coverage: 1.0,
+                    occlusion: 1.0,
+                });
+            }
+        }
+        AovBuffers {
+            width: SIZE,
+            height: SIZE,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn test_denoise_reduces_noise_and_keeps_mean() {
+        let aovs = flat_aovs(|_x| Vec3::Z);
+        let mut framebuffer = Framebuffer::new(SIZE, SIZE);
+        for i in 0..framebuffer.pixels.len() {
+            let value = 0.5 + rand_range(-0.25..0.25);
+            framebuffer.pixels[i] = Color::new(value, value, value, 1.0);
+            // Variance of a uniform distribution of width 0.5
+            framebuffer.variance[i] = 0.25 / 12.0;
+        }
+
+        let stats = |framebuffer: &Framebuffer| {
+            let n = framebuffer.pixels.len() as f32;
+            let mean = framebuffer.pixels.iter().map(|c| c.red).sum::<f32>() / n;
+            let variance = framebuffer
+                .pixels
+                .iter()
+                .map(|c| (c.red - mean) * (c.red - mean))
+                .sum::<f32>()
+                / n;
+            (mean, variance)
+        };
+        let (mean_before, variance_before) = stats(&framebuffer);
+        Denoiser::new().denoise(&mut framebuffer, &aovs);
+        let (mean_after, variance_after) = stats(&framebuffer);
+
+        assert!((mean_after - mean_before).abs() < 0.02);
+        assert!(variance_after < variance_before * 0.1);
+    }
+
+    #[test]
+    fn test_denoise_keeps_normal_edges() {
+        let mut aovs = flat_aovs(|x| if x < SIZE / 2 { Vec3::Z } else { Vec3::X });
+        // Normals that cancelled out while averaging the samples
+        aovs.pixels[3].normal = Vec3::ZERO;
+        let mut framebuffer = Framebuffer::new(SIZE, SIZE);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let value = if x < SIZE / 2 { 0.5 } else { 0.0 };
+                let i = framebuffer.index(x, y);
+                framebuffer.pixels[i] = Color::new(value, value, value, 1.0);
+                // Large enough that only the normals keep the sides apart
+                framebuffer.variance[i] = 1.0;
+            }
+        }
+
+        Denoiser::new().denoise(&mut framebuffer, &aovs);
+        assert!((framebuffer.get(SIZE / 2 - 1, SIZE / 2).red - 0.5).abs() < 1e-3);
+        assert!(framebuffer.get(SIZE / 2, SIZE / 2).red < 1e-3);
+        assert!((framebuffer.pixels[3].red - 0.5).abs() < 1e-3);
+        assert!(framebuffer
+            .variance
+            .iter()
+            .all(|variance| variance.is_finite()));
+    }
+}
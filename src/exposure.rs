@@ -0,0 +1,152 @@
+use crate::{blackbody::MAX_LUMINOUS_EFFICACY, framebuffer::Framebuffer};
+
+/// Reflected light meter calibration constant, 12.5 is common among camera makers.
+const METER_CALIBRATION: f32 = 12.5;
+/// Headroom of the sensor above the meter reading, from the ISO 12232 saturation based speed.
+const SATURATION_HEADROOM: f32 = 1.2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExposureMode {
+    /// Render values are written as they are, the lens uses `Camera::defocus_angle`.
+    #[default]
+    Off,
+    /// Exposure from the f-stop, shutter time and ISO.
+    Manual,
+    /// Exposure from the average log luminance of the frame, like a camera's light meter.
+    Auto,
+}
+
+/// Physical camera settings. Outside `ExposureMode::Off` the f-stop also sets the aperture
+/// the lens samples, and rays are spread over the shutter time so moving instances blur.
+/// Render values are treated as watts, see `Blackbody`, so a luminance of 1 is 683 nits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    pub mode: ExposureMode,
+    pub f_stop: f32,        // N, focal length over aperture diameter
+    pub shutter_time: f32,  // Seconds
+    pub iso: f32,           // Sensor sensitivity, 100 is the reference for EV100
+    pub compensation: f32,  // EV, positive brightens the image
+    pub sensor_height: f32, // World units, 0.024 is a 35 mm full frame sensor in meters
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Exposure {
+    pub fn new() -> Self {
+        // Sunny 16
+        Self {
+            mode: ExposureMode::Off,
+            f_stop: 16.0,
+            shutter_time: 1.0 / 100.0,
+            iso: 100.0,
+            compensation: 0.0,
+            sensor_height: 0.024,
+        }
+    }
+
+    /// Exposure value of the settings at ISO 100.
+    pub fn ev100(&self) -> f32 {
+        (self.f_stop * self.f_stop / self.shutter_time * 100.0 / self.iso).log2()
+    }
+
+    /// EV100 that exposes a scene of this average luminance (in nits) to middle gray.
+    pub fn ev100_from_luminance(average_luminance: f32) -> f32 {
+        (average_luminance * 100.0 / METER_CALIBRATION).log2()
+    }
+
+    /// Focal length that gives the vertical `fov` (deg) on the sensor.
+    pub fn focal_length(&self, fov: f32) -> f32 {
+        0.5 * self.sensor_height / (0.5 * fov.to_radians()).tan()
+    }
+
+    /// Diameter of the lens opening for the vertical `fov` (deg).
+    pub fn aperture_diameter(&self, fov: f32) -> f32 {
+        self.focal_length(fov) / self.f_stop
+    }
+
+    /// Factor the linear render is multiplied by, maps the luminance the sensor saturates at to 1.
+    pub fn scale(&self, framebuffer: &Framebuffer) -> f32 {
+        let ev100 = match self.mode {
+            ExposureMode::Off => return 1.0,
+            ExposureMode::Manual => self.ev100(),
+            ExposureMode::Auto => Self::ev100_from_luminance(
+                average_log_luminance(framebuffer) * MAX_LUMINOUS_EFFICACY,
+            ),
+        } - self.compensation;
+        let max_luminance = SATURATION_HEADROOM * 2.0_f32.powf(ev100);
+        MAX_LUMINOUS_EFFICACY / max_luminance
+    }
+
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        let scale = self.scale(framebuffer);
+        if scale == 1.0 {
+            return;
+        }
+        for pixel in framebuffer.pixels.iter_mut() {
+            *pixel = *pixel * scale;
+            pixel.alpha = 1.0;
+        }
+    }
+}
+
+/// Geometric mean of the pixel luminances, robust to a few very bright pixels.
+pub fn average_log_luminance(framebuffer: &Framebuffer) -> f32 {
+    // Keeps black pixels from pulling the mean to zero
+    const DELTA: f32 = 1e-4;
+    if framebuffer.pixels.is_empty() {
+        return 0.0;
+    }
+    let sum_log: f32 = framebuffer
+        .pixels
+        .iter()
+        .map(|pixel| (DELTA + framebuffer.luminance(*pixel).max(0.0)).ln())
+        .sum();
+    (sum_log / framebuffer.pixels.len() as f32).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Color;
+
+    use super::*;
+
+    #[test]
+    fn test_manual_exposure() {
+        let mut exposure = Exposure::new();
+        exposure.mode = ExposureMode::Manual;
+        // Sunny 16 is close to EV100 15
+        assert!((exposure.ev100() - 14.64).abs() < 0.01);
+
+        // One stop wider aperture doubles the exposure
+        let framebuffer = Framebuffer::new(1, 1);
+        let base = exposure.scale(&framebuffer);
+        exposure.f_stop /= 2.0_f32.sqrt();
+        assert!((exposure.scale(&framebuffer) / base - 2.0).abs() < 1e-3);
+        exposure.compensation = -1.0;
+        assert!((exposure.scale(&framebuffer) / base - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_auto_exposure_is_scale_invariant() {
+        let mut framebuffer = Framebuffer::new(4, 4);
+        for (i, pixel) in framebuffer.pixels.iter_mut().enumerate() {
+            *pixel = Color::new(0.1 * i as f32, 0.2, 0.05 * i as f32, 1.0);
+        }
+        let mut exposure = Exposure::new();
+        exposure.mode = ExposureMode::Auto;
+
+        let mut exposed = framebuffer.clone();
+        exposure.apply(&mut exposed);
+
+        let mut brighter = framebuffer.clone();
+        for pixel in brighter.pixels.iter_mut() {
+            *pixel = *pixel * 100.0;
+        }
+        exposure.apply(&mut brighter);
+        assert!((average_log_luminance(&brighter) - average_log_luminance(&exposed)).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,152 @@
+use glam::Vec2;
+
+/// Pixel reconstruction filter, samples are weighted by it in every pixel within `radius`.
+/// Radii are in pixels and the filters are separable in x and y.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReconstructionFilter {
+    /// Each sample only counts for its own pixel, a plain average.
+    #[default]
+    Box,
+    Tent {
+        radius: f32,
+    },
+    /// Shifted down so it reaches zero at `radius`, `sigma` of radius / 3 is common.
+    Gaussian {
+        radius: f32,
+        sigma: f32,
+    },
+    /// Cubic with negative lobes, B = C = 1/3 is the recommended balance of blur and ringing.
+    MitchellNetravali {
+        radius: f32,
+        b: f32,
+        c: f32,
+    },
+    /// Sinc windowed by a sinc stretched over `radius`, sharpest of the filters but rings.
+    Lanczos {
+        radius: f32,
+    },
+}
+
+impl ReconstructionFilter {
+    pub fn radius(&self) -> f32 {
+        match *self {
+            ReconstructionFilter::Box => 0.5,
+            ReconstructionFilter::Tent { radius }
+            | ReconstructionFilter::Gaussian { radius, .. }
+            | ReconstructionFilter::MitchellNetravali { radius, .. }
+            | ReconstructionFilter::Lanczos { radius } => radius,
+        }
+    }
+
+    /// Weight of a sample at `offset` pixels from a pixel center.
+    pub fn evaluate(&self, offset: Vec2) -> f32 {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+
+        match *self {
+            ReconstructionFilter::Box => 1.0,
+            ReconstructionFilter::Tent { radius } => (1.0 - x / radius).max(0.0),
+            ReconstructionFilter::Gaussian { radius, sigma } => {
+                let gaussian = |d: f32| (-d * d / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            ReconstructionFilter::MitchellNetravali { radius, b, c } => {
+                // The cubic is defined over [-2, 2]
+                let x = 2.0 * x / radius;
+                let x2 = x * x;
+                let x3 = x2 * x;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else if x < 2.0 {
+                    ((-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    0.0
+                }
+            }
+            ReconstructionFilter::Lanczos { radius } => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+/// Normalized sinc, sin(pi x) / (pi x).
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    let pi_x = std::f32::consts::PI * x;
+    pi_x.sin() / pi_x
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{color::Color, framebuffer::Film};
+
+    use super::*;
+
+    #[test]
+    fn test_filter_shapes() {
+        let tent = ReconstructionFilter::Tent { radius: 1.5 };
+        assert_eq!(tent.evaluate(Vec2::ZERO), 1.0);
+        assert_eq!(tent.evaluate(Vec2::new(1.5, 0.0)), 0.0);
+
+        let mitchell = ReconstructionFilter::MitchellNetravali {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        };
+        assert!((mitchell.evaluate_1d(0.0) - 8.0 / 9.0).abs() < 1e-5);
+        // Negative lobe between 1 and 2 pixels at this radius
+        assert!(mitchell.evaluate_1d(1.5) < 0.0);
+
+        let lanczos = ReconstructionFilter::Lanczos { radius: 3.0 };
+        assert!(lanczos.evaluate_1d(1.0).abs() < 1e-5);
+        assert!(lanczos.evaluate_1d(3.5) == 0.0);
+    }
+
+    #[test]
+    fn test_film_splat_normalizes_weights() {
+        let filters = [
+            ReconstructionFilter::Box,
+            ReconstructionFilter::Gaussian {
+                radius: 2.0,
+                sigma: 0.66,
+            },
+            ReconstructionFilter::MitchellNetravali {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+        ];
+        for filter in filters {
+            let mut film = Film::new(8, 8, filter);
+            for y in 0..8 {
+                for x in 0..8 {
+                    for offset in [Vec2::new(-0.25, 0.1), Vec2::new(0.3, -0.4)] {
+                        film.splat(x, y, offset, Color::new(0.7, 0.7, 0.7, 1.0));
+                    }
+                }
+            }
+
+            // A flat image stays flat, also at the borders where fewer samples overlap
+            for y in 0..8 {
+                for x in 0..8 {
+                    let color = film.resolve_pixel(x, y).unwrap();
+                    assert!((color.red - 0.7).abs() < 1e-4);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use glam::Vec2;
+
+use crate::{color::Color, color_space::ColorSpace, filter::ReconstructionFilter};
+
+/// Luminance of a linear sRGB color, see ColorSpace::luminance for other primaries.
+pub fn luminance(color: Color) -> f32 {
+    0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue
+}
+
+/// Linear radiance of a render before tone mapping, row major with the top row first.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<Color>,
+    pub variance: Vec<f32>, // Variance of each pixel's mean luminance, zero with one sample
+    pub color_space: ColorSpace, // Primaries of the pixels, the world's working space
+}
+
+impl Framebuffer {
+    pub fn new(width: i32, height: i32) -> Self {
+        let num_pixels = (width * height).max(0) as usize;
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new(0.0, 0.0, 0.0, 1.0); num_pixels],
+            variance: vec![0.0; num_pixels],
+            color_space: ColorSpace::LinearSrgb,
+        }
+    }
+
+    pub fn index(&self, x: i32, y: i32) -> usize {
+        (x + y * self.width) as usize
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Color {
+        self.pixels[self.index(x, y)]
+    }
+
+    /// Luminance of a color in the primaries of this framebuffer.
+    pub fn luminance(&self, color: Color) -> f32 {
+        self.color_space.luminance(color)
+    }
+}
+
+/// Weighted sums of splatted samples, resolved into a `Framebuffer` after rendering.
+/// Every sample adds to all pixels within the filter radius of where it landed.
+#[derive(Clone)]
+pub struct Film {
+    pub width: i32,
+    pub height: i32,
+    pub filter: ReconstructionFilter,
+    sum_color: Vec<Color>,
+    sum_weight: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(width: i32, height: i32, filter: ReconstructionFilter) -> Self {
+        let num_pixels = (width * height).max(0) as usize;
+        Self {
+            width,
+            height,
+            filter,
+            sum_color: vec![Color::new(0.0, 0.0, 0.0, 0.0); num_pixels],
+            sum_weight: vec![0.0; num_pixels],
+        }
+    }
+
+    /// Adds a sample taken `offset` pixels from the center of pixel (x, y).
+    pub fn splat(&mut self, x: i32, y: i32, offset: Vec2, color: Color) {
+        let radius = self.filter.radius();
+        let min_x = ((offset.x - radius).ceil() as i32 + x).max(0);
+        let max_x = ((offset.x + radius).floor() as i32 + x).min(self.width - 1);
+        let min_y = ((offset.y - radius).ceil() as i32 + y).max(0);
+        let max_y = ((offset.y + radius).floor() as i32 + y).min(self.height - 1);
+
+        for py in min_y..=max_y {
+            for px in min_x..=max_x {
+                let to_center = Vec2::new((px - x) as f32, (py - y) as f32) - offset;
+                let weight = self.filter.evaluate(to_center);
+                if weight == 0.0 {
+                    continue;
+                }
+                let i = (px + py * self.width) as usize;
+                self.sum_color[i] += color * weight;
+                self.sum_weight[i] += weight;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Film) {
+        for i in 0..self.sum_color.len() {
+            self.sum_color[i] += other.sum_color[i];
+            self.sum_weight[i] += other.sum_weight[i];
+        }
+    }
+
+    /// Filtered color of a pixel, `None` if the weights cancel out.
+    pub fn resolve_pixel(&self, x: i32, y: i32) -> Option<Color> {
+        let i = (x + y * self.width) as usize;
+        if self.sum_weight[i].abs() < 1e-6 {
+            return None;
+        }
+        let color = self.sum_color[i] / self.sum_weight[i];
+        Some(Color::new(color.red, color.green, color.blue, 1.0))
+    }
+
+    /// Overwrites the pixels of `framebuffer` that received samples.
+    /// Negative lobes can ring below zero, those values are clamped.
+    pub fn resolve_into(&self, framebuffer: &mut Framebuffer) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if let Some(color) = self.resolve_pixel(x, y) {
+                    let i = framebuffer.index(x, y);
+                    framebuffer.pixels[i] = Color::new(
+                        color.red.max(0.0),
+                        color.green.max(0.0),
+                        color.blue.max(0.0),
+                        1.0,
+                    );
+                }
+            }
+        }
+    }
+}
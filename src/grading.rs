@@ -0,0 +1,303 @@
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    sync::Arc,
+};
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LutInterpolation {
+    /// Blends the 8 corners of the lattice cell.
+    Trilinear,
+    /// Blends the 4 corners of the tetrahedron around the gray axis, keeps neutrals neutral.
+    #[default]
+    Tetrahedral,
+}
+
+/// 3D lookup table in the Adobe / Resolve .cube format, applied to encoded colors.
+pub struct Lut3d {
+    pub title: String,
+    pub size: usize,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    pub table: Vec<[f32; 3]>, // size^3 entries, red changes fastest
+}
+
+impl Lut3d {
+    pub fn load(path: &str) -> Result<Self, io::Error> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read(reader: impl BufRead) -> Result<Self, io::Error> {
+        let mut title = String::new();
+        let mut size: usize = 0;
+        let mut domain_min: [f32; 3] = [0.0; 3];
+        let mut domain_max: [f32; 3] = [1.0; 3];
+        let mut table: Vec<[f32; 3]> = Vec::new();
+
+        let parse_triple = |values: &[&str]| -> Result<[f32; 3], io::Error> {
+            if values.len() != 3 {
+                return Err(invalid_data("expected three values"));
+            }
+            let mut triple = [0.0; 3];
+            for (target, value) in triple.iter_mut().zip(values) {
+                *target = value
+                    .parse::<f32>()
+                    .map_err(|_| invalid_data("invalid number in LUT"))?;
+            }
+            Ok(triple)
+        };
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens[0] {
+                "TITLE" => title = line["TITLE".len()..].trim().trim_matches('"').to_string(),
+                "LUT_3D_SIZE" => {
+                    size = tokens
+                        .get(1)
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .filter(|size| (2..=256).contains(size))
+                        .ok_or_else(|| invalid_data("invalid LUT_3D_SIZE"))?;
+                }
+                "LUT_1D_SIZE" => return Err(invalid_data("1D LUTs are not supported")),
+                "DOMAIN_MIN" => domain_min = parse_triple(&tokens[1..])?,
+                "DOMAIN_MAX" => domain_max = parse_triple(&tokens[1..])?,
+                // Other keywords from newer versions of the format do not change the table
+                keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+                _ => table.push(parse_triple(&tokens)?),
+            }
+        }
+
+        if size == 0 {
+            return Err(invalid_data("missing LUT_3D_SIZE"));
+        }
+        if table.len() != size * size * size {
+            return Err(invalid_data("LUT has the wrong number of entries"));
+        }
+        // Not greater also covers NaN bounds, sample divides by the range
+        if (0..3).any(|c| domain_max[c].partial_cmp(&domain_min[c]) != Some(Ordering::Greater)) {
+            return Err(invalid_data("DOMAIN_MAX must be above DOMAIN_MIN"));
+        }
+        Ok(Self {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[r + g * self.size + b * self.size * self.size]
+    }
+
+    pub fn sample(&self, color: [f32; 3], interpolation: LutInterpolation) -> [f32; 3] {
+        // Lattice coordinates, the cell's lower corner and the position within it
+        let last = (self.size - 1) as f32;
+        let mut base = [0usize; 3];
+        let mut f = [0.0f32; 3];
+        for c in 0..3 {
+            let range = self.domain_max[c] - self.domain_min[c];
+            let t = ((color[c] - self.domain_min[c]) / range).clamp(0.0, 1.0) * last;
+            base[c] = (t.floor() as usize).min(self.size - 2);
+            f[c] = t - base[c] as f32;
+        }
+        let corner =
+            |dr: usize, dg: usize, db: usize| self.entry(base[0] + dr, base[1] + dg, base[2] + db);
+        let mix = |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t);
+
+        match interpolation {
+            LutInterpolation::Trilinear => {
+                let (fr, fg, fb) = (f[0], f[1], f[2]);
+                let c00 = mix(corner(0, 0, 0), corner(1, 0, 0), fr);
+                let c10 = mix(corner(0, 1, 0), corner(1, 1, 0), fr);
+                let c01 = mix(corner(0, 0, 1), corner(1, 0, 1), fr);
+                let c11 = mix(corner(0, 1, 1), corner(1, 1, 1), fr);
+                mix(mix(c00, c10, fg), mix(c01, c11, fg), fb)
+            }
+            LutInterpolation::Tetrahedral => {
+                let (fr, fg, fb) = (f[0], f[1], f[2]);
+                // Walk from the black corner to the white one along the largest fractions first
+                let path: [((usize, usize, usize), f32); 3] = if fr > fg {
+                    if fg > fb {
+                        [((1, 0, 0), fr), ((1, 1, 0), fg), ((1, 1, 1), fb)]
+                    } else if fr > fb {
+                        [((1, 0, 0), fr), ((1, 0, 1), fb), ((1, 1, 1), fg)]
+                    } else {
+                        [((0, 0, 1), fb), ((1, 0, 1), fr), ((1, 1, 1), fg)]
+                    }
+                } else if fb > fg {
+                    [((0, 0, 1), fb), ((0, 1, 1), fg), ((1, 1, 1), fr)]
+                } else if fb > fr {
+                    [((0, 1, 0), fg), ((0, 1, 1), fb), ((1, 1, 1), fr)]
+                } else {
+                    [((0, 1, 0), fg), ((1, 1, 0), fr), ((1, 1, 1), fb)]
+                };
+
+                let mut previous = corner(0, 0, 0);
+                let mut result = previous;
+                for ((dr, dg, db), weight) in path {
+                    let next = corner(dr, dg, db);
+                    for c in 0..3 {
+                        result[c] += (next[c] - previous[c]) * weight;
+                    }
+                    previous = next;
+                }
+                result
+            }
+        }
+    }
+}
+
+/// Grading of the encoded image before it is quantized to 8 bits. Lift, gamma and gain
+/// are per channel, then saturation, then the LUT.
+#[derive(Clone)]
+pub struct ColorGrade {
+    pub lift: [f32; 3],  // Raises the blacks, 0 leaves them
+    pub gamma: [f32; 3], // Midtones, above 1 brightens
+    pub gain: [f32; 3],  // Scales the whites, 1 leaves them
+    pub saturation: f32, // 0 is gray, 1 leaves the colors
+    pub lut: Option<Arc<Lut3d>>,
+    pub interpolation: LutInterpolation,
+}
+
+impl Default for ColorGrade {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ColorGrade {
+    pub fn new() -> Self {
+        Self {
+            lift: [0.0; 3],
+            gamma: [1.0; 3],
+            gain: [1.0; 3],
+            saturation: 1.0,
+            lut: None,
+            interpolation: LutInterpolation::Tetrahedral,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.lift == [0.0; 3]
+            && self.gamma == [1.0; 3]
+            && self.gain == [1.0; 3]
+            && self.saturation == 1.0
+            && self.lut.is_none()
+    }
+
+    /// Grades an encoded color in [0, 1].
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        if self.is_identity() {
+            return color;
+        }
+
+        let mut graded = [0, 1, 2].map(|c| {
+            let lifted = self.gain[c] * (color[c] + self.lift[c] * (1.0 - color[c]));
+            lifted.max(0.0).powf(1.0 / self.gamma[c].max(1e-4))
+        });
+
+        if self.saturation != 1.0 {
+            // Rec.709 luma of the encoded values
+            let luma = 0.2126 * graded[0] + 0.7152 * graded[1] + 0.0722 * graded[2];
+            graded = graded.map(|value| luma + (value - luma) * self.saturation);
+        }
+
+        let graded = graded.map(|value| value.clamp(0.0, 1.0));
+        match &self.lut {
+            Some(lut) => lut
+                .sample(graded, self.interpolation)
+                .map(|value| value.clamp(0.0, 1.0)),
+            None => graded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Table that maps every color to itself.
+    fn identity_lut(size: usize) -> Lut3d {
+        let mut table: Vec<[f32; 3]> = Vec::with_capacity(size * size * size);
+        let value = |i: usize| i as f32 / (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    table.push([value(r), value(g), value(b)]);
+                }
+            }
+        }
+        Lut3d {
+            title: String::new(),
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table,
+        }
+    }
+
+    #[test]
+    fn test_cube_lut() {
+        let cube = "# Swaps red and blue\nTITLE \"swap\"\nLUT_3D_SIZE 2\n\
+            DOMAIN_MIN 0 0 0\nDOMAIN_MAX 1 1 1\n\
+            0 0 0\n0 0 1\n0 1 0\n0 1 1\n1 0 0\n1 0 1\n1 1 0\n1 1 1\n";
+        let lut = Lut3d::read(cube.as_bytes()).unwrap();
+        assert_eq!(lut.title, "swap");
+        let color = [0.8, 0.3, 0.1];
+        for interpolation in [LutInterpolation::Trilinear, LutInterpolation::Tetrahedral] {
+            let swapped = lut.sample(color, interpolation);
+            assert!((swapped[0] - 0.1).abs() < 1e-5 && (swapped[2] - 0.8).abs() < 1e-5);
+
+            let identity = identity_lut(17).sample(color, interpolation);
+            for c in 0..3 {
+                assert!((identity[c] - color[c]).abs() < 1e-5);
+            }
+        }
+
+        assert!(Lut3d::read("LUT_3D_SIZE 2\n0 0 0\n".as_bytes()).is_err());
+        assert!(Lut3d::read("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n".as_bytes()).is_err());
+        for domain in [
+            "DOMAIN_MAX 1 0 1",
+            "DOMAIN_MIN 0 2 0",
+            "DOMAIN_MAX 1 NaN 1",
+            "DOMAIN_MIN NaN 0 0",
+        ] {
+            let cube = cube.replace("DOMAIN_MAX 1 1 1", domain);
+            assert!(Lut3d::read(cube.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_lift_gamma_gain_saturation() {
+        let mut grade = ColorGrade::new();
+        assert_eq!(grade.apply([0.2, 0.5, 0.9]), [0.2, 0.5, 0.9]);
+
+        grade.lift = [0.1; 3];
+        assert!((grade.apply([0.0; 3])[0] - 0.1).abs() < 1e-5);
+        assert!((grade.apply([1.0; 3])[0] - 1.0).abs() < 1e-5);
+
+        grade.lift = [0.0; 3];
+        grade.gain = [0.5, 1.0, 1.0];
+        grade.gamma = [1.0, 2.0, 1.0];
+        let graded = grade.apply([1.0, 0.25, 0.5]);
+        assert!((graded[0] - 0.5).abs() < 1e-5 && (graded[1] - 0.5).abs() < 1e-5);
+
+        let gray = ColorGrade {
+            saturation: 0.0,
+            ..ColorGrade::new()
+        }
+        .apply([0.8, 0.3, 0.1]);
+        assert!((gray[0] - gray[1]).abs() < 1e-5 && (gray[1] - gray[2]).abs() < 1e-5);
+    }
+}
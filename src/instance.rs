@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use glam::{Mat3, Mat4, Vec3};
+
+use crate::{
+    interval::Interval,
+    ray::{HitResult, Hittable, Ray},
+};
+
+/// A shared hittable placed in the world with an affine transform.
+/// Many instances can point at the same object, each only paying for its transform.
+#[derive(Clone)]
+pub struct Instance {
+    pub object: Arc<dyn Hittable + Sync + Send>,
+    pub velocity: Vec3, // World units per second, moves the instance while the shutter is open
+    object_to_world: Mat4,
+    world_to_object: Mat4,
+    normal_to_world: Mat3,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable + Sync + Send>, object_to_world: Mat4) -> Self {
+        let world_to_object = object_to_world.inverse();
+        let normal_to_world = Mat3::from_mat4(world_to_object).transpose();
+
+        Self {
+            object,
+            velocity: Vec3::ZERO,
+            object_to_world,
+            world_to_object,
+            normal_to_world,
+        }
+    }
+
+    pub fn from_translation(object: Arc<dyn Hittable + Sync + Send>, translation: Vec3) -> Self {
+        Self::new(object, Mat4::from_translation(translation))
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        self.object_to_world
+    }
+
+    /// Ray in object space, moved back by how far the instance traveled at the ray's time.
+    fn object_ray(&self, ray: &Ray, object_direction: Vec3) -> Ray {
+        let origin = ray.origin - self.velocity * ray.time;
+        let mut object_ray: Ray = Ray::new(
+            self.world_to_object.transform_point3(origin),
+            object_direction,
+        );
+        object_ray.time = ray.time;
+        object_ray
+    }
+
+    pub fn set_transform(&mut self, object_to_world: Mat4) {
+        let velocity = self.velocity;
+        *self = Self::new(self.object.clone(), object_to_world);
+        self.velocity = velocity;
+    }
+}
+
+impl Hittable for Instance {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let object_direction = self.world_to_object.transform_vector3(ray.direction);
+        let direction_scale = object_direction.length();
+        if direction_scale == 0.0 {
+            return None;
+        }
+
+        // Ray::new normalizes, so distances along the object ray are scaled by direction_scale
+        let object_ray: Ray = self.object_ray(ray, object_direction);
+        let object_interval: Interval = Interval {
+            min: interval.min * direction_scale,
+            max: interval.max * direction_scale,
+        };
+
+        let mut hit_result: HitResult = self.object.hit(&object_ray, object_interval)?;
+        hit_result.location =
+            self.object_to_world.transform_point3(hit_result.location) + self.velocity * ray.time;
+        hit_result.normal = (self.normal_to_world * hit_result.normal).normalize();
+        hit_result.t /= direction_scale;
+
+        return Some(hit_result);
+    }
+
+    fn transmittance(&self, ray: &Ray, interval: Interval) -> f32 {
+        let object_direction = self.world_to_object.transform_vector3(ray.direction);
+        let direction_scale = object_direction.length();
+        if direction_scale == 0.0 {
+            return 1.0;
+        }
+
+        let object_ray: Ray = self.object_ray(ray, object_direction);
+        self.object.transmittance(
+            &object_ray,
+            Interval {
+                min: interval.min * direction_scale,
+                max: interval.max * direction_scale,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Quat;
+
+    use crate::ray::{Sphere, SurfaceAttributes};
+
+    use super::*;
+
+    fn unit_sphere() -> Arc<dyn Hittable + Sync + Send> {
+        Arc::new(Sphere {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material_id: 0,
+            surface: SurfaceAttributes::default(),
+        })
+    }
+
+    #[test]
+    fn test_instance_scaled_translated_sphere() {
+        let transform = Mat4::from_scale_rotation_translation(
+            Vec3::new(2.0, 2.0, 2.0),
+            Quat::from_rotation_y(0.7),
+            Vec3::new(0.0, 0.0, -10.0),
+        );
+        let instance = Instance::new(unit_sphere(), transform);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let hit_result = instance
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        assert!((hit_result.t - 8.0).abs() < 1e-4);
+        assert!((hit_result.location - Vec3::new(0.0, 0.0, -8.0)).length() < 1e-4);
+        assert!((hit_result.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4);
+        assert_eq!(hit_result.front_face, Some(true));
+    }
+
+    #[test]
+    fn test_instance_interval_in_world_units() {
+        let instance = Instance::new(unit_sphere(), Mat4::from_scale(Vec3::splat(4.0)));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert!(instance.hit(&ray, Interval::new(0.0001, 5.0)).is_none());
+        let hit_result = instance.hit(&ray, Interval::new(0.0001, 7.0)).unwrap();
+        assert!((hit_result.t - 6.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_instance_moves_with_ray_time() {
+        let mut instance = Instance::from_translation(unit_sphere(), Vec3::new(0.0, 0.0, -10.0));
+        instance.velocity = Vec3::new(0.0, 0.0, 100.0);
+        let mut ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        ray.time = 0.05;
+
+        let hit_result = instance
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        assert!((hit_result.t - 4.0).abs() < 1e-4);
+        assert!((hit_result.location - Vec3::new(0.0, 0.0, -4.0)).length() < 1e-4);
+    }
+}
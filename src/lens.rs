@@ -0,0 +1,442 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+};
+
+use glam::{Vec2, Vec3};
+
+use crate::{math::math::solve_quadratic, ray::Ray};
+
+/// Prescription tables are in millimeters, the world in meters.
+const MILLIMETERS: f32 = 0.001;
+/// Radial film intervals the exit pupil is bounded over.
+const NUM_PUPIL_BOUNDS: usize = 64;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// One spherical interface of a lens, or the aperture stop when `curvature_radius` is 0.
+/// `thickness` and `ior` describe the gap behind it, towards the film.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensElement {
+    pub curvature_radius: f32,
+    pub thickness: f32,
+    pub ior: f32, // 0 for the aperture stop, 1 for air
+    pub aperture_radius: f32,
+}
+
+/// Stack of spherical elements from a lens prescription, traced between the film and the scene.
+/// In lens space the film is at z = 0 and the elements towards +z, front element last.
+#[derive(Clone, Debug, Default)]
+pub struct LensSystem {
+    pub elements: Vec<LensElement>, // Front to back, as in the prescription
+    film_size: Vec2,
+    exit_pupil_bounds: Vec<[Vec2; 2]>, // Min and max on the rear element plane per film radius
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> Self {
+        Self {
+            elements,
+            film_size: Vec2::ZERO,
+            exit_pupil_bounds: Vec::new(),
+        }
+    }
+
+    /// Prescription table, one element per line front to back: curvature radius, thickness,
+    /// ior and aperture diameter in millimeters. Lines starting with # are comments.
+    pub fn load(path: &str) -> Result<Self, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read(&mut reader)
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, io::Error> {
+        let mut elements: Vec<LensElement> = Vec::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values: Vec<f32> = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid_data("invalid number in lens prescription"))?;
+            if values.len() != 4 {
+                return Err(invalid_data("lens elements need 4 columns"));
+            }
+            elements.push(LensElement {
+                curvature_radius: values[0] * MILLIMETERS,
+                thickness: values[1] * MILLIMETERS,
+                ior: values[2],
+                aperture_radius: values[3] * MILLIMETERS * 0.5,
+            });
+        }
+
+        if elements.is_empty() {
+            return Err(invalid_data("lens prescription has no elements"));
+        }
+        Ok(Self::new(elements))
+    }
+
+    /// Distance from the film to the rear element, the last thickness of the table.
+    pub fn rear_z(&self) -> f32 {
+        self.elements
+            .last()
+            .map_or(0.0, |element| element.thickness)
+    }
+
+    pub fn front_z(&self) -> f32 {
+        self.elements.iter().map(|element| element.thickness).sum()
+    }
+
+    fn rear_radius(&self) -> f32 {
+        self.elements
+            .last()
+            .map_or(0.0, |element| element.aperture_radius)
+    }
+
+    /// Moves the rear element so objects at `focus_distance` from the film are sharp, then
+    /// bounds the exit pupil for a film of `film_size`. Both in world units.
+    pub fn prepare(&mut self, film_size: Vec2, focus_distance: f32) {
+        self.film_size = film_size;
+        if let Some(thickness) = self.focus_thickness(focus_distance) {
+            if let Some(rear) = self.elements.last_mut() {
+                rear.thickness = thickness;
+            }
+        }
+
+        let film_radius = 0.5 * film_size.length();
+        self.exit_pupil_bounds = (0..NUM_PUPIL_BOUNDS)
+            .map(|i| {
+                let r0 = i as f32 / NUM_PUPIL_BOUNDS as f32 * film_radius;
+                let r1 = (i + 1) as f32 / NUM_PUPIL_BOUNDS as f32 * film_radius;
+                self.bound_exit_pupil(r0, r1)
+            })
+            .collect();
+    }
+
+    /// Ray leaving the front element for a point on the film, in lens space with +z towards
+    /// the scene, and its weight. `None` when an element or the stop blocks it.
+    /// The weight is cos^4 times the area of the pupil bounds the ray was sampled in, relative
+    /// to the bounds at the film center, so rays from the center straight through weigh 1.
+    pub fn generate_ray(&self, film_point: Vec2, u: Vec2) -> Option<(Ray, f32)> {
+        let (rear_point, bounds_area) = self.sample_exit_pupil(film_point, u)?;
+        let film_point = Vec3::new(film_point.x, film_point.y, 0.0);
+        let film_ray: Ray = Ray::new(film_point, rear_point - film_point);
+        let cos_theta = film_ray.direction.z;
+        let scene_ray = self.trace_from_film(&film_ray)?;
+
+        let center_area = bounds_area_of(self.exit_pupil_bounds[0]);
+        if center_area <= 0.0 {
+            return None;
+        }
+        Some((scene_ray, cos_theta.powi(4) * bounds_area / center_area))
+    }
+
+    /// Point on the rear element plane and the area of the bounds it was picked in.
+    fn sample_exit_pupil(&self, film_point: Vec2, u: Vec2) -> Option<(Vec3, f32)> {
+        if self.exit_pupil_bounds.is_empty() {
+            return None;
+        }
+        let film_radius = film_point.length();
+        let index =
+            (film_radius / (0.5 * self.film_size.length()) * NUM_PUPIL_BOUNDS as f32) as usize;
+        let [min, max] = self.exit_pupil_bounds[index.min(NUM_PUPIL_BOUNDS - 1)];
+        if min.x > max.x {
+            return None;
+        }
+        let p = min + (max - min) * u;
+
+        // The bounds were found along +x, rotate them to the film point
+        let (sin_theta, cos_theta) = if film_radius > 0.0 {
+            (film_point.y / film_radius, film_point.x / film_radius)
+        } else {
+            (0.0, 1.0)
+        };
+        let point = Vec3::new(
+            cos_theta * p.x - sin_theta * p.y,
+            sin_theta * p.x + cos_theta * p.y,
+            self.rear_z(),
+        );
+        Some((point, bounds_area_of([min, max])))
+    }
+
+    /// Box on the rear element plane that lets light through to film points at radius r0 to r1.
+    fn bound_exit_pupil(&self, r0: f32, r1: f32) -> [Vec2; 2] {
+        const GRID: usize = 48;
+        let rear_z = self.rear_z();
+        let extent = 1.5 * self.rear_radius();
+        let mut min = Vec2::splat(f32::INFINITY);
+        let mut max = Vec2::splat(f32::NEG_INFINITY);
+        for i in 0..GRID * GRID {
+            let lerp = |t: f32, a: f32, b: f32| a + (b - a) * t;
+            let film_x = lerp((i as f32 + 0.5) / (GRID * GRID) as f32, r0, r1);
+            let rear = Vec2::new(
+                lerp(((i % GRID) as f32 + 0.5) / GRID as f32, -extent, extent),
+                lerp(((i / GRID) as f32 + 0.5) / GRID as f32, -extent, extent),
+            );
+            let film_point = Vec3::new(film_x, 0.0, 0.0);
+            let film_ray: Ray = Ray::new(film_point, rear.extend(rear_z) - film_point);
+            if self.trace_from_film(&film_ray).is_some() {
+                min = min.min(rear);
+                max = max.max(rear);
+            }
+        }
+
+        // Pad by one grid cell, the grid can miss the edges
+        let cell = Vec2::splat(2.0 * extent / GRID as f32);
+        [min - cell, max + cell]
+    }
+
+    /// Traces a ray from the film through the elements, back to front.
+    pub fn trace_from_film(&self, ray: &Ray) -> Option<Ray> {
+        let mut origin = ray.origin;
+        let mut direction = ray.direction;
+        let mut element_z = 0.0;
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            element_z += element.thickness;
+            let (t, normal) = intersect_element(element, element_z, origin, direction)?;
+            origin += direction * t;
+            if origin.x * origin.x + origin.y * origin.y
+                > element.aperture_radius * element.aperture_radius
+            {
+                return None;
+            }
+
+            if element.curvature_radius != 0.0 {
+                let ior_from = element.ior;
+                let ior_to = if i > 0 && self.elements[i - 1].ior != 0.0 {
+                    self.elements[i - 1].ior
+                } else {
+                    1.0
+                };
+                direction = refract(direction, normal, ior_from / ior_to)?;
+            }
+        }
+        Some(Ray::new(origin, direction))
+    }
+
+    /// Traces a ray from the scene through the elements, front to back.
+    pub fn trace_from_scene(&self, ray: &Ray) -> Option<Ray> {
+        let mut origin = ray.origin;
+        let mut direction = ray.direction;
+        let mut element_z = self.front_z();
+        for i in 0..self.elements.len() {
+            let element = &self.elements[i];
+            let (t, normal) = intersect_element(element, element_z, origin, direction)?;
+            origin += direction * t;
+            if origin.x * origin.x + origin.y * origin.y
+                > element.aperture_radius * element.aperture_radius
+            {
+                return None;
+            }
+
+            if element.curvature_radius != 0.0 {
+                let ior_from = if i == 0 || self.elements[i - 1].ior == 0.0 {
+                    1.0
+                } else {
+                    self.elements[i - 1].ior
+                };
+                let ior_to = if element.ior != 0.0 { element.ior } else { 1.0 };
+                direction = refract(direction, normal, ior_from / ior_to)?;
+            }
+            element_z -= element.thickness;
+        }
+        Some(Ray::new(origin, direction))
+    }
+
+    /// Rear element distance that focuses at `focus_distance`, from a thick lens approximation.
+    fn focus_thickness(&self, focus_distance: f32) -> Option<f32> {
+        let ([principal_scene, principal_film], [focal_scene, _]) = self.cardinal_points()?;
+        let focal_length = focal_scene - principal_scene;
+        let z = -focus_distance;
+        let c = (principal_film - z - principal_scene)
+            * (principal_film - z - 4.0 * focal_length - principal_scene);
+        if c < 0.0 {
+            return None;
+        }
+        let delta = 0.5 * (principal_film - z + principal_scene - c.sqrt());
+        Some(self.rear_z() + delta)
+    }
+
+    /// Principal planes and focal points of the scene and film sides. Their z is negated,
+    /// so the scene is towards -z as in the thick lens equations.
+    fn cardinal_points(&self) -> Option<([f32; 2], [f32; 2])> {
+        // Rays parallel to the axis, close enough to it for the paraxial approximation
+        let x = 0.001 * self.rear_radius().max(MILLIMETERS);
+        let scene_ray: Ray = Ray::new(Vec3::new(x, 0.0, self.front_z() + 1.0), Vec3::NEG_Z);
+        let from_scene = self.trace_from_scene(&scene_ray)?;
+        let film_ray: Ray = Ray::new(Vec3::new(x, 0.0, self.rear_z() - 1.0), Vec3::Z);
+        let from_film = self.trace_from_film(&film_ray)?;
+
+        let cardinal = |ray_in: &Ray, ray_out: &Ray| -> Option<(f32, f32)> {
+            if ray_out.direction.x == 0.0 {
+                return None;
+            }
+            let t_focal = -ray_out.origin.x / ray_out.direction.x;
+            let t_principal = (ray_in.origin.x - ray_out.origin.x) / ray_out.direction.x;
+            Some((ray_out.at(t_principal).z, ray_out.at(t_focal).z))
+        };
+        let (principal_scene, focal_scene) = cardinal(&scene_ray, &from_scene)?;
+        let (principal_film, focal_film) = cardinal(&film_ray, &from_film)?;
+        Some((
+            [-principal_scene, -principal_film],
+            [-focal_scene, -focal_film],
+        ))
+    }
+}
+
+/// Area of a box given by its min and max corners, zero when it is empty.
+fn bounds_area_of([min, max]: [Vec2; 2]) -> f32 {
+    let size = (max - min).max(Vec2::ZERO);
+    size.x * size.y
+}
+
+/// Hit of a ray with a spherical element or the plane of the stop, at `element_z`.
+/// The normal faces against the ray.
+fn intersect_element(
+    element: &LensElement,
+    element_z: f32,
+    origin: Vec3,
+    direction: Vec3,
+) -> Option<(f32, Vec3)> {
+    if element.curvature_radius == 0.0 {
+        if direction.z == 0.0 {
+            return None;
+        }
+        let t = (element_z - origin.z) / direction.z;
+        return Some((t, Vec3::new(0.0, 0.0, -direction.z.signum())));
+    }
+
+    // Lens space has the scene towards +z, the radius sign follows the prescription
+    // where positive radii have their center behind the surface
+    let radius = element.curvature_radius;
+    let center = Vec3::new(0.0, 0.0, element_z - radius);
+    let oc = origin - center;
+    let (roots, num_roots) = solve_quadratic([
+        (oc.length_squared() - radius * radius) as f64,
+        2.0 * direction.dot(oc) as f64,
+        direction.length_squared() as f64,
+    ]);
+    if num_roots == 0 {
+        return None;
+    }
+    let (t0, t1) = (roots[0] as f32, roots[num_roots - 1] as f32);
+    let use_closer = (direction.z < 0.0) ^ (radius < 0.0);
+    let t = if use_closer { t0.min(t1) } else { t0.max(t1) };
+    if t < 0.0 {
+        return None;
+    }
+
+    let normal = (oc + direction * t).normalize();
+    let normal = if normal.dot(direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    };
+    Some((t, normal))
+}
+
+/// Refraction of a unit direction through a surface with the normal facing against it,
+/// `eta` is the ior ratio from the incoming to the outgoing side. `None` on total internal reflection.
+fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = -normal.dot(direction);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((direction * eta + normal * (eta * cos_i - cos_t)).normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 50 mm f/2 double Gauss, US patent 2,673,491.
+    const DOUBLE_GAUSS: &str = "# radius thickness ior aperture
+        29.475 3.76 1.67 25.2
+        84.83 0.12 1 25.2
+        19.275 4.025 1.67 23
+        40.77 3.275 1.699 23
+        12.75 5.705 1 18
+        0 4.5 0 17.1
+        -14.495 1.18 1.603 17
+        40.77 6.065 1.658 20
+        -20.385 0.19 1 20
+        437.065 3.22 1.717 20
+        -39.73 0 1 20";
+
+    fn double_gauss() -> LensSystem {
+        LensSystem::read(&mut DOUBLE_GAUSS.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_lens_focal_length() {
+        let lens = double_gauss();
+        assert_eq!(lens.elements.len(), 11);
+        let ([principal_scene, _], [focal_scene, _]) = lens.cardinal_points().unwrap();
+        let focal_length = focal_scene - principal_scene;
+        assert!((focal_length - 0.05).abs() < 0.003);
+    }
+
+    #[test]
+    fn test_lens_focuses_at_distance() {
+        let mut lens = double_gauss();
+        let focus_distance = 2.0;
+        lens.prepare(Vec2::new(0.036, 0.024), focus_distance);
+
+        // Rays from the film center through the pupil meet on the axis at the focus distance
+        let mut num_rays = 0;
+        for i in 0..8 {
+            let u = Vec2::new((i as f32 + 0.5) / 8.0, 0.5);
+            let Some((ray, weight)) = lens.generate_ray(Vec2::ZERO, u) else {
+                continue;
+            };
+            assert!(weight > 0.8);
+            if ray.direction.x.abs() < 1e-6 {
+                continue;
+            }
+            // Wide open the edge of the pupil focuses a little closer, spherical aberration
+            let t = -ray.origin.x / ray.direction.x;
+            let z = ray.at(t).z;
+            assert!((z - focus_distance).abs() / focus_distance < 0.1);
+            num_rays += 1;
+        }
+        assert!(num_rays >= 4);
+    }
+
+    #[test]
+    fn test_lens_weight_falls_off_to_the_corner() {
+        let mut lens = double_gauss();
+        let film_size = Vec2::new(0.036, 0.024);
+        lens.prepare(film_size, 2.0);
+
+        // Mean weight over the pupil, blocked rays count as black
+        let mean_weight = |film_point: Vec2| {
+            let mut sum = 0.0;
+            for i in 0..16 {
+                for j in 0..16 {
+                    let u = Vec2::new((i as f32 + 0.5) / 16.0, (j as f32 + 0.5) / 16.0);
+                    if let Some((_ray, weight)) = lens.generate_ray(film_point, u) {
+                        sum += weight;
+                    }
+                }
+            }
+            sum / 256.0
+        };
+        let center = mean_weight(Vec2::ZERO);
+        let corner = mean_weight(film_size * 0.49);
+        assert!(center > 0.5 && center <= 1.0);
+        assert!(corner < 0.8 * center);
+
+        // The smaller bounds at the corner scale the weight down, not only the cos^4
+        let corner_bounds = *lens.exit_pupil_bounds.last().unwrap();
+        assert!(bounds_area_of(corner_bounds) < bounds_area_of(lens.exit_pupil_bounds[0]));
+    }
+}
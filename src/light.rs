@@ -0,0 +1,517 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{
+    color::Color,
+    interval::Interval,
+    material::evaluate,
+    math::math::deg_to_rad,
+    ray::{HitResult, HittableList, Ray},
+    sampler::next_2d,
+    warp::{
+        concentric_disk_pdf, local_to_world, spherical_triangle_area, spherical_triangle_pdf,
+        square_to_concentric_disk, square_to_spherical_triangle, square_to_uniform_cone,
+        square_to_uniform_triangle, uniform_cone_pdf, uniform_triangle_pdf,
+    },
+};
+
+/// Incident light at a shading point from one light sample.
+pub struct LightSample {
+    pub direction: Vec3, // Unit vector from the shading point towards the light
+    pub distance: f32,
+    pub radiance: Color, // Irradiance arriving on a surface facing the light
+}
+
+pub trait Light {
+    fn sample(&self, location: Vec3) -> Option<LightSample>;
+    fn clone_dyn(&self) -> Box<dyn Light + Sync + Send>;
+}
+
+impl Clone for Box<dyn Light + Sync + Send> {
+    fn clone(&self) -> Self {
+        self.clone_dyn()
+    }
+}
+
+/// Light emitted equally in all directions, falling off with the inverse square distance.
+#[derive(Clone)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Color, // Radiant intensity, watts per steradian
+}
+
+impl Light for PointLight {
+    fn clone_dyn(&self) -> Box<dyn Light + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn sample(&self, location: Vec3) -> Option<LightSample> {
+        let to_light = self.position - location;
+        let distance_sq = to_light.length_squared();
+        if distance_sq <= 0.0 {
+            return None;
+        }
+        let distance = distance_sq.sqrt();
+
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity / distance_sq,
+        })
+    }
+}
+
+/// Point light restricted to a cone, fading out over the outer `edge_softness` fraction of the angle.
+#[derive(Clone)]
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub intensity: Color,
+    pub cone_angle: f32, // Deg, half angle of the cone
+    pub edge_softness: f32,
+}
+
+impl SpotLight {
+    fn cone_falloff(&self, cos_theta: f32) -> f32 {
+        let cos_outer = (deg_to_rad(self.cone_angle as f64) as f32).cos();
+        let inner_angle = self.cone_angle * (1.0 - self.edge_softness.clamp(0.0, 1.0));
+        let cos_inner = (deg_to_rad(inner_angle as f64) as f32).cos();
+        if cos_theta <= cos_outer {
+            return 0.0;
+        }
+        if cos_theta >= cos_inner {
+            return 1.0;
+        }
+
+        let x = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Light for SpotLight {
+    fn clone_dyn(&self) -> Box<dyn Light + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn sample(&self, location: Vec3) -> Option<LightSample> {
+        let to_light = self.position - location;
+        let distance_sq = to_light.length_squared();
+        if distance_sq <= 0.0 {
+            return None;
+        }
+        let distance = distance_sq.sqrt();
+        let direction = to_light / distance;
+
+        let falloff = self.cone_falloff((-direction).dot(self.direction.normalize()));
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity * (falloff / distance_sq),
+        })
+    }
+}
+
+/// Distant light such as the sun, `direction` is where the light travels towards.
+/// A non zero `angular_diameter` spreads the samples over a disk and softens shadows.
+#[derive(Clone)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Color,
+    pub angular_diameter: f32, // Deg, about 0.53 for the sun
+}
+
+impl Light for DirectionalLight {
+    fn clone_dyn(&self) -> Box<dyn Light + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn sample(&self, _location: Vec3) -> Option<LightSample> {
+        let to_light = -self.direction.normalize();
+        if self.angular_diameter <= 0.0 {
+            return Some(LightSample {
+                direction: to_light,
+                distance: f32::INFINITY,
+                radiance: self.irradiance,
+            });
+        }
+
+        // The irradiance spread evenly over the disk's solid angle, divided by the sample's pdf
+        let cos_max = (deg_to_rad(self.angular_diameter as f64 * 0.5) as f32).cos();
+        let solid_angle = 2.0 * PI * (1.0 - cos_max);
+        let local = square_to_uniform_cone(next_2d(), cos_max);
+        let pdf = uniform_cone_pdf(local, cos_max);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: local_to_world(local, to_light),
+            distance: f32::INFINITY,
+            radiance: self.irradiance / solid_angle / pdf,
+        })
+    }
+}
+
+/// Disk emitting `radiance` from the side `normal` points to. Like the punctual lights it has
+/// no geometry, pair it with an emissive `Disk` only if camera rays should see it.
+#[derive(Clone)]
+pub struct DiskLight {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub radiance: Color,
+}
+
+impl Light for DiskLight {
+    fn clone_dyn(&self) -> Box<dyn Light + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn sample(&self, location: Vec3) -> Option<LightSample> {
+        let normal = self.normal.normalize();
+        let disk = square_to_concentric_disk(next_2d());
+        let point =
+            self.center + local_to_world(Vec3::new(disk.x, disk.y, 0.0), normal) * self.radius;
+
+        let to_light = point - location;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = to_light / distance;
+        let cos_light = -direction.dot(normal);
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        // Pdf over the disk's area, turned into a pdf over the solid angle it covers
+        let area_pdf = concentric_disk_pdf(disk) / (self.radius * self.radius);
+        let pdf = area_pdf * distance * distance / cos_light;
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.radiance / pdf,
+        })
+    }
+}
+
+/// Below this solid angle the spherical triangle math loses precision, above it the
+/// triangle covers most of the view and the area is sampled instead.
+const MIN_SPHERICAL_TRIANGLE_AREA: f32 = 3e-4;
+const MAX_SPHERICAL_TRIANGLE_AREA: f32 = 6.22;
+
+/// Triangle emitting `radiance` from both sides, sampled uniformly over the solid angle it
+/// covers as seen from the shading point. Has no geometry, like `DiskLight`.
+#[derive(Clone)]
+pub struct TriangleLight {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+    pub radiance: Color,
+}
+
+impl TriangleLight {
+    /// Fallback for triangles too small or too large to sample by solid angle.
+    fn sample_area(&self, location: Vec3, normal: Vec3, area: f32) -> Option<LightSample> {
+        let barycentric = square_to_uniform_triangle(next_2d());
+        let point = self.a * barycentric.x
+            + self.b * barycentric.y
+            + self.c * (1.0 - barycentric.x - barycentric.y);
+
+        let to_light = point - location;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = to_light / distance;
+        let cos_light = direction.dot(normal).abs();
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        let pdf = uniform_triangle_pdf(area) * distance * distance / cos_light;
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.radiance / pdf,
+        })
+    }
+}
+
+impl Light for TriangleLight {
+    fn clone_dyn(&self) -> Box<dyn Light + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn sample(&self, location: Vec3) -> Option<LightSample> {
+        let cross = (self.b - self.a).cross(self.c - self.a);
+        let area = 0.5 * cross.length();
+        if area <= 0.0 {
+            return None;
+        }
+        let normal = cross.normalize();
+
+        let a = (self.a - location).normalize_or_zero();
+        let b = (self.b - location).normalize_or_zero();
+        let c = (self.c - location).normalize_or_zero();
+        let solid_angle = spherical_triangle_area(a, b, c);
+        if !(MIN_SPHERICAL_TRIANGLE_AREA..MAX_SPHERICAL_TRIANGLE_AREA).contains(&solid_angle) {
+            return self.sample_area(location, normal, area);
+        }
+
+        let direction = square_to_spherical_triangle(next_2d(), a, b, c);
+        // Where the direction meets the triangle's plane
+        let cos_plane = direction.dot(normal);
+        if cos_plane == 0.0 {
+            return None;
+        }
+        let distance = (self.a - location).dot(normal) / cos_plane;
+        if distance <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.radiance / spherical_triangle_pdf(a, b, c),
+        })
+    }
+}
+
+pub struct LightList {
+    pub list: Vec<Box<dyn Light + Sync + Send>>,
+}
+
+impl Clone for LightList {
+    fn clone(&self) -> Self {
+        let mut copy_list: Vec<Box<dyn Light + Sync + Send>> = Vec::new();
+        for li in self.list.iter() {
+            copy_list.push(li.clone_dyn());
+        }
+        Self { list: copy_list }
+    }
+}
+
+impl Default for LightList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self { list: Vec::new() }
+    }
+
+    pub fn add_light(&mut self, light: Box<dyn Light + Sync + Send>) {
+        self.list.push(light);
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+}
+
+/// Light from every punctual light in the world reaching the hit point, attenuated by the
+/// transmittance of whatever lies in between. Zero for materials with only delta lobes.
+pub fn direct_lighting(world: &HittableList, ray: &Ray, hit_result: &HitResult) -> Color {
+    let mut direct: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+    for light in world.lights.list.iter() {
+        let Some(light_sample) = light.sample(hit_result.location) else {
+            continue;
+        };
+
+        let bsdf = evaluate(ray, hit_result, light_sample.direction);
+        if bsdf.red <= 0.0 && bsdf.green <= 0.0 && bsdf.blue <= 0.0 {
+            continue;
+        }
+
+        const SHADOW_EPSILON: f32 = 0.0001;
+        let mut shadow_ray: Ray = Ray::new(hit_result.location, light_sample.direction);
+        shadow_ray.time = ray.time;
+        let transmittance = world.transmittance(
+            &shadow_ray,
+            Interval {
+                min: SHADOW_EPSILON,
+                max: light_sample.distance - SHADOW_EPSILON,
+            },
+        );
+        if transmittance <= 0.0 {
+            continue;
+        }
+
+        let radiance = world
+            .working_space
+            .convert_linear_srgb(light_sample.radiance);
+        direct += bsdf * radiance * transmittance;
+    }
+
+    direct
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        material::MATERIAL_LAMBERTIAN,
+        ray::{Plane, Sphere, SurfaceAttributes},
+    };
+
+    use super::*;
+
+    fn ground_world() -> HittableList {
+        let mut world = HittableList::new();
+        world.add_hittable(Box::new(Plane {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            material_id: MATERIAL_LAMBERTIAN,
+            surface: SurfaceAttributes {
+                albedo: Color::new(1.0, 1.0, 1.0, 1.0),
+                ..Default::default()
+            },
+        }));
+        world
+    }
+
+    fn ground_direct(world: &HittableList, x: f32) -> f32 {
+        let ray = Ray::new(Vec3::new(x, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit_result = world
+            .hit_all(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        direct_lighting(world, &ray, &hit_result).red
+    }
+
+    /// Average of `ground_direct` for lights sampled at random.
+    fn mean_ground_direct(world: &HittableList, x: f32) -> f32 {
+        const NUM_SAMPLES: usize = 20000;
+        (0..NUM_SAMPLES)
+            .map(|_| ground_direct(world, x))
+            .sum::<f32>()
+            / NUM_SAMPLES as f32
+    }
+
+    #[test]
+    fn test_point_light_inverse_square_and_shadow() {
+        let mut world = ground_world();
+        world.add_light(Box::new(PointLight {
+            position: Vec3::new(0.0, 2.0, 0.0),
+            intensity: Color::new(4.0, 4.0, 4.0, 1.0),
+        }));
+
+        // Straight below: albedo / pi * 4 / 2^2
+        let expected = 1.0 / std::f32::consts::PI;
+        assert!((ground_direct(&world, 0.0) - expected).abs() < 1e-4);
+
+        world.add_hittable(Box::new(Sphere {
+            center: Vec3::new(0.0, 1.0, 0.0),
+            radius: 0.5,
+            material_id: MATERIAL_LAMBERTIAN,
+            surface: SurfaceAttributes::default(),
+        }));
+        let ray = Ray::new(Vec3::new(0.0, 0.1, 2.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit_result = world
+            .hit_all(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        let shadowed = Ray::new(Vec3::new(0.0, 0.1, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let shadowed_hit = world
+            .hit_all(&shadowed, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        assert!(direct_lighting(&world, &ray, &hit_result).red > 0.0);
+        assert_eq!(direct_lighting(&world, &shadowed, &shadowed_hit).red, 0.0);
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let mut world = ground_world();
+        world.add_light(Box::new(SpotLight {
+            position: Vec3::new(0.0, 2.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            intensity: Color::new(4.0, 4.0, 4.0, 1.0),
+            cone_angle: 30.0,
+            edge_softness: 0.5,
+        }));
+
+        assert!((ground_direct(&world, 0.0) - 1.0 / std::f32::consts::PI).abs() < 1e-4);
+        // tan(30 deg) * 2 is about 1.15, outside the cone everything is dark
+        assert_eq!(ground_direct(&world, 1.3), 0.0);
+        let edge = ground_direct(&world, 1.0);
+        assert!(edge > 0.0 && edge < 1.0 / std::f32::consts::PI);
+    }
+
+    #[test]
+    fn test_directional_light_disk() {
+        let mut world = ground_world();
+        world.add_light(Box::new(DirectionalLight {
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            irradiance: Color::new(1.0, 1.0, 1.0, 1.0),
+            angular_diameter: 10.0,
+        }));
+
+        // Directions off the axis land at a slight angle, (1 + cos 5 deg) / 2 of the irradiance
+        let expected = (1.0 + deg_to_rad(5.0).cos() as f32) / 2.0 / std::f32::consts::PI;
+        assert!((mean_ground_direct(&world, 0.0) - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_disk_light_on_axis() {
+        let mut world = ground_world();
+        world.add_light(Box::new(DiskLight {
+            center: Vec3::new(0.0, 2.0, 0.0),
+            normal: Vec3::new(0.0, -1.0, 0.0),
+            radius: 1.0,
+            radiance: Color::new(1.0, 1.0, 1.0, 1.0),
+        }));
+
+        // Irradiance pi * r^2 / (r^2 + h^2) times albedo / pi
+        assert!((mean_ground_direct(&world, 0.0) - 0.2).abs() < 2e-3);
+
+        let mut facing_away = ground_world();
+        facing_away.add_light(Box::new(DiskLight {
+            center: Vec3::new(0.0, 2.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            radius: 1.0,
+            radiance: Color::new(1.0, 1.0, 1.0, 1.0),
+        }));
+        assert_eq!(ground_direct(&facing_away, 0.0), 0.0);
+    }
+
+    /// Lambert's closed form irradiance from a polygon of radiance 1 onto an upward facing
+    /// surface at the origin, times albedo / pi.
+    fn polygon_direct(vertices: &[Vec3]) -> f32 {
+        let mut sum = 0.0;
+        for i in 0..vertices.len() {
+            let v0 = vertices[i].normalize();
+            let v1 = vertices[(i + 1) % vertices.len()].normalize();
+            let angle = v0.dot(v1).clamp(-1.0, 1.0).acos();
+            sum += angle * v0.cross(v1).normalize().y;
+        }
+        sum.abs() / 2.0 / std::f32::consts::PI
+    }
+
+    #[test]
+    fn test_triangle_light_matches_lamberts_formula() {
+        let vertices = [
+            Vec3::new(-1.0, 2.0, -1.0),
+            Vec3::new(1.0, 2.0, -1.0),
+            Vec3::new(0.0, 2.0, 1.5),
+        ];
+        // Sampled by solid angle, then shrunk until it is sampled over its area
+        for scale in [1.0, 0.01] {
+            let [a, b, c] = vertices.map(|v| Vec3::new(v.x * scale, v.y, v.z * scale));
+            let mut world = ground_world();
+            world.add_light(Box::new(TriangleLight {
+                a,
+                b,
+                c,
+                radiance: Color::new(1.0, 1.0, 1.0, 1.0),
+            }));
+
+            let expected = polygon_direct(&[a, b, c]);
+            assert!((mean_ground_direct(&world, 0.0) - expected).abs() < 1e-2 * expected);
+        }
+    }
+}
@@ -1,12 +1,48 @@
-use std::{fs::File, io::Write, ops::Mul, process::Output, sync::Arc, thread::Thread};
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    fs::{self, File},
+    io::{self, Write},
+    ops::Mul,
+    process::Output,
+    str::FromStr,
+    sync::Arc,
+    thread::Thread,
+};
 
+use animation::{CameraAnimation, CameraKeyframe, FrameSequence, Interpolation};
+use blackbody::{Blackbody, LightPower};
+use bokeh::{ApertureMask, ApertureShape};
 use camera::Camera;
-use glam::Vec3;
+use color_space::{ColorSpace, OutputTransform};
+use csg::{Csg, CsgOperation};
+use denoise::Denoiser;
+use exposure::ExposureMode;
+use filter::ReconstructionFilter;
+use glam::{Mat4, Quat, Vec2, Vec3};
+use grading::{ColorGrade, Lut3d, LutInterpolation};
+use instance::Instance;
+use lens::LensSystem;
+use light::{DiskLight, PointLight, SpotLight, TriangleLight};
 use material::*;
+use medium::{ConstantMedium, Fog, PhaseFunction};
+use planar::{AxisAlignedBox, BoundedPlane, Disk, Quad};
+use post_process::{Bloom, Glare, PostProcess};
+use quadric::{Capsule, Cone, Cylinder, Torus};
 use rand::{rngs::ThreadRng, Rng};
 use random::*;
 use ray::SurfaceAttributes;
-use renderer::render;
+use renderer::{render, RenderConfig};
+use sampler::{
+    BlueNoiseSampler, HaltonSampler, IndependentSampler, SobolSampler, StratifiedSampler,
+};
+use sdf::{
+    SdfBox, SdfFn, SdfHittable, SdfRepetition, SdfRoundBox, SdfSmoothSubtraction, SdfSmoothUnion,
+    SdfSphere, SdfTorus, SdfTwist,
+};
+use sky::PreethamSky;
+use stereo::{StereoLayout, StereoRig};
+use video::{GifEncoder, Y4mWriter};
+use volume::{HeterogeneousVolume, VoxelGrid};
 
 use crate::{
     color::*,
@@ -16,16 +52,43 @@ use crate::{
     ray::*,
 };
 
+mod animation;
+mod aov;
+mod blackbody;
+mod bokeh;
 mod camera;
 mod color;
+mod color_space;
+mod csg;
+mod denoise;
+mod exposure;
+mod filter;
+mod framebuffer;
+mod grading;
+mod instance;
 mod interval;
+mod lens;
+mod light;
 mod material;
 mod math;
+mod medium;
+mod planar;
+mod png;
+mod post_process;
 mod progress_bar;
+mod quadric;
 mod random;
 mod ray;
 mod renderer;
 mod ringbuffer;
+mod sampler;
+mod sdf;
+mod sky;
+mod spectrum;
+mod stereo;
+mod video;
+mod volume;
+mod warp;
 
 /* TODO:
 camera direction
@@ -51,6 +114,7 @@ fn setup_world0(world: &mut HittableList) {
             albedo: rand_albedo,
             emissive: rand_emissve,
             ir: 1.5,
+            ..Default::default()
         };
 
         random_surfaces.push(rand_surface);
@@ -64,7 +128,13 @@ fn setup_world0(world: &mut HittableList) {
         rand_position.y = radius;
 
         let rand_surface_index: usize = rand_range(0..random_surfaces.len());
-        let rand_material_id: i32 = rand_range(0..MATERIAL_NUM);
+        // Media need a boundary to fill, the spheres only get surface materials
+        let rand_material_id: i32 = loop {
+            let material_id: i32 = rand_range(0..MATERIAL_NUM);
+            if material_id != MATERIAL_VOLUME {
+                break material_id;
+            }
+        };
 
         let rand_sphere: Sphere = Sphere {
             center: rand_position,
@@ -92,6 +162,7 @@ fn setup_world0(world: &mut HittableList) {
             albedo: Color::new(0.5, 0.5, 0.5, 1.0),
             emissive: Color::new(0.0, 0.0, 0.0, 1.0),
             ir: 1.0,
+            ..Default::default()
         },
     });
 
@@ -107,12 +178,14 @@ fn setup_world1(world: &mut HittableList) {
         albedo: Color::new(1.0, 0.0, 0.0, 1.0),
         emissive: Color::new(0.0, 0.0, 0.0, 1.0),
         ir: 1.5,
+        ..Default::default()
     };
 
     let surface2: SurfaceAttributes = SurfaceAttributes {
         albedo: Color::new(0.5, 0.5, 0.5, 1.0),
         emissive: Color::new(0.0, 0.0, 0.0, 1.0),
         ir: 1.5,
+        ..Default::default()
     };
     surfaces.push(surface);
     surfaces.push(surface2);
@@ -157,6 +230,7 @@ fn setup_world1(world: &mut HittableList) {
             albedo: Color::new(0.5, 0.5, 0.5, 1.0),
             emissive: Color::new(0.0, 0.0, 0.0, 1.0),
             ir: 1.0,
+            ..Default::default()
         },
     });
 
@@ -166,6 +240,586 @@ fn setup_world1(world: &mut HittableList) {
     }
 }
 
+/// Ball of gas, densest and hottest in the middle.
+fn glowing_ball() -> VoxelGrid {
+    const RESOLUTION: usize = 16;
+    let mut density: Vec<f32> = Vec::with_capacity(RESOLUTION * RESOLUTION * RESOLUTION);
+    let mut emission: Vec<Vec3> = Vec::with_capacity(density.capacity());
+    for z in 0..RESOLUTION {
+        for y in 0..RESOLUTION {
+            for x in 0..RESOLUTION {
+                let p = (Vec3::new(x as f32, y as f32, z as f32) + 0.5) / RESOLUTION as f32;
+                let falloff = (1.0 - (p - 0.5).length() * 2.0).max(0.0);
+                density.push(falloff);
+                emission.push(Vec3::new(1.0, 0.4, 0.1) * falloff * falloff);
+            }
+        }
+    }
+    VoxelGrid::new([RESOLUTION; 3], density, Some(emission)).unwrap()
+}
+
+/// The primitives, media and lights the spheres scene doesn't show, under a daylight sky.
+/// `gas` replaces the glowing ball in the front row.
+fn setup_world2(world: &mut HittableList, gas: Option<VoxelGrid>) {
+    let surface = |albedo: Color| SurfaceAttributes {
+        albedo,
+        emissive: Color::new(0.0, 0.0, 0.0, 1.0),
+        ir: 1.5,
+        ..Default::default()
+    };
+    let gray = surface(Color::new(0.5, 0.5, 0.5, 1.0));
+
+    world.add_hittable(Box::new(BoundedPlane {
+        center: Vec3::new(0.0, 0.0, 0.0),
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        up: Vec3::new(0.0, 0.0, 1.0),
+        size: Vec2::new(40.0, 40.0),
+        material_id: MATERIAL_LAMBERTIAN,
+        surface: gray,
+    }));
+    let wall = Quad::new(
+        Vec3::new(-10.0, 0.0, 6.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 6.0, 0.0),
+        MATERIAL_LAMBERTIAN,
+        surface(Color::new(0.6, 0.55, 0.5, 1.0)),
+    );
+    // Round mirror hung just in front of the wall
+    world.add_hittable(Box::new(Disk {
+        center: wall.q + (wall.u + wall.v) * 0.5 - wall.normal() * 0.01 + Vec3::new(0.0, 1.0, 0.0),
+        normal: wall.normal(),
+        radius: 1.5,
+        material_id: MATERIAL_METAL,
+        surface: surface(Color::new(0.9, 0.9, 0.9, 1.0)),
+    }));
+    world.add_hittable(Box::new(wall));
+
+    // Back row: quadrics and a rotated box
+    let blue = surface(Color::new(0.2, 0.3, 0.8, 1.0));
+    world.add_hittable(Box::new(Cylinder {
+        center: Vec3::new(-6.0, 0.0, 3.0),
+        radius: 0.8,
+        height: 2.0,
+        capped: true,
+        material_id: MATERIAL_LAMBERTIAN,
+        surface: blue,
+    }));
+    world.add_hittable(Box::new(Cone {
+        center: Vec3::new(-3.0, 0.0, 3.0),
+        radius: 0.9,
+        height: 2.0,
+        capped: true,
+        material_id: MATERIAL_METAL,
+        surface: gray,
+    }));
+    world.add_hittable(Box::new(Capsule {
+        a: Vec3::new(-0.8, 0.5, 3.0),
+        b: Vec3::new(0.8, 1.5, 3.0),
+        radius: 0.5,
+        material_id: MATERIAL_LAMBERTIAN,
+        surface: surface(Color::new(0.8, 0.3, 0.2, 1.0)),
+    }));
+    world.add_hittable(Box::new(Torus {
+        center: Vec3::new(3.0, 0.4, 3.0),
+        major_radius: 0.9,
+        minor_radius: 0.4,
+        material_id: MATERIAL_DIELECTRIC,
+        surface: surface(Color::new(0.9, 0.9, 1.0, 1.0)),
+    }));
+    world.add_hittable(Box::new(Instance::new(
+        Arc::new(AxisAlignedBox::new(
+            Vec3::new(-0.7, 0.0, -0.7),
+            Vec3::new(0.7, 2.4, 0.7),
+            MATERIAL_LAMBERTIAN,
+            surface(Color::new(0.3, 0.7, 0.3, 1.0)),
+        )),
+        Mat4::from_translation(Vec3::new(6.0, 0.0, 3.0)) * Mat4::from_rotation_y(0.5),
+    )));
+
+    // Middle row: a pierced sphere with a box cut out and distance fields
+    let pierced = Csg::new(
+        CsgOperation::Union,
+        Arc::new(Sphere {
+            center: Vec3::new(-5.0, 1.0, 0.0),
+            radius: 1.0,
+            material_id: MATERIAL_LAMBERTIAN,
+            surface: gray,
+        }),
+        Arc::new(Cylinder {
+            center: Vec3::new(-5.0, 0.0, 0.0),
+            radius: 0.3,
+            height: 2.4,
+            capped: true,
+            material_id: MATERIAL_LAMBERTIAN,
+            surface: blue,
+        }),
+    );
+    world.add_hittable(Box::new(Csg::new(
+        CsgOperation::Difference,
+        Arc::new(pierced),
+        Arc::new(AxisAlignedBox::new(
+            Vec3::new(-5.0, 1.0, -1.5),
+            Vec3::new(-3.5, 2.5, 0.0),
+            MATERIAL_LAMBERTIAN,
+            gray,
+        )),
+    )));
+
+    let twisted = SdfTwist {
+        sdf: Arc::new(SdfSmoothUnion {
+            a: Arc::new(SdfRoundBox {
+                center: Vec3::new(0.0, 1.0, 0.0),
+                half_extents: Vec3::new(0.4, 0.9, 0.4),
+                radius: 0.1,
+            }),
+            b: Arc::new(SdfTorus {
+                center: Vec3::new(0.0, 1.0, 0.0),
+                major_radius: 0.8,
+                minor_radius: 0.15,
+            }),
+            k: 0.2,
+        }),
+        amount: 0.8,
+    };
+    let mut twisted = SdfHittable::new(
+        Arc::new(twisted),
+        Vec3::new(-1.2, 0.0, -1.2),
+        Vec3::new(1.2, 2.2, 1.2),
+        MATERIAL_METAL,
+        surface(Color::new(0.9, 0.7, 0.3, 1.0)),
+    );
+    twisted.step_scale = 0.5;
+    world.add_hittable(Box::new(Instance::from_translation(
+        Arc::new(twisted),
+        Vec3::new(-1.5, 0.0, 0.0),
+    )));
+
+    let holes = SdfSmoothSubtraction {
+        a: Arc::new(SdfRepetition {
+            sdf: Arc::new(SdfSphere {
+                center: Vec3::ZERO,
+                radius: 0.2,
+            }),
+            period: Vec3::new(0.5, 0.5, 0.5),
+        }),
+        b: Arc::new(SdfBox {
+            center: Vec3::new(1.5, 0.75, 0.0),
+            half_extents: Vec3::new(0.7, 0.7, 0.7),
+        }),
+        k: 0.05,
+    };
+    world.add_hittable(Box::new(SdfHittable::new(
+        Arc::new(holes),
+        Vec3::new(0.7, 0.0, -0.8),
+        Vec3::new(2.3, 1.6, 0.8),
+        MATERIAL_LAMBERTIAN,
+        surface(Color::new(0.8, 0.8, 0.8, 1.0)),
+    )));
+
+    let gyroid = SdfFn(|p: Vec3| {
+        let q = p * 6.0;
+        let value = q.x.sin() * q.y.cos() + q.y.sin() * q.z.cos() + q.z.sin() * q.x.cos();
+        value.abs() / 6.0 - 0.02
+    });
+    let mut gyroid = SdfHittable::new(
+        Arc::new(gyroid),
+        Vec3::new(3.5, 0.0, -0.7),
+        Vec3::new(4.9, 1.4, 0.7),
+        MATERIAL_LAMBERTIAN,
+        surface(Color::new(0.7, 0.2, 0.6, 1.0)),
+    );
+    gyroid.step_scale = 0.5;
+    world.add_hittable(Box::new(gyroid));
+
+    // Front row: a sphere squashed into a drifting ellipsoid, a glass lens, smoke, gas and lights
+    let mut ellipsoid = Instance::from_translation(
+        Arc::new(Sphere {
+            center: Vec3::new(0.0, 1.0, 0.0),
+            radius: 1.0,
+            material_id: MATERIAL_METAL,
+            surface: surface(Color::new(0.8, 0.8, 0.9, 1.0)),
+        }),
+        Vec3::new(-6.0, 0.0, -3.0),
+    );
+    ellipsoid.set_transform(ellipsoid.transform() * Mat4::from_scale(Vec3::new(1.2, 0.6, 0.8)));
+    ellipsoid.velocity = Vec3::new(0.5, 0.0, 0.0);
+    world.add_hittable(Box::new(ellipsoid));
+
+    let glass = surface(Color::new(1.0, 1.0, 1.0, 1.0));
+    let lens_side = |z: f32| -> Arc<dyn Hittable + Sync + Send> {
+        Arc::new(Sphere {
+            center: Vec3::new(5.5, 0.8, z),
+            radius: 1.2,
+            material_id: MATERIAL_DIELECTRIC,
+            surface: glass,
+        })
+    };
+    world.add_hittable(Box::new(Csg::new(
+        CsgOperation::Intersection,
+        lens_side(-3.4),
+        lens_side(-1.6),
+    )));
+
+    let smoke = SurfaceAttributes {
+        phase: PhaseFunction::HenyeyGreenstein { g: 0.6 },
+        ..surface(Color::new(0.9, 0.9, 0.9, 1.0))
+    };
+    world.add_hittable(Box::new(ConstantMedium {
+        boundary: Arc::new(Sphere {
+            center: Vec3::new(-3.0, 0.8, -3.0),
+            radius: 0.8,
+            material_id: MATERIAL_VOLUME,
+            surface: smoke,
+        }),
+        density: 2.0,
+        surface: smoke,
+    }));
+
+    let grid = gas.unwrap_or_else(glowing_ball);
+    let gas = HeterogeneousVolume::new(
+        Arc::new(grid),
+        Mat4::from_translation(Vec3::new(0.0, 0.0, -4.0)) * Mat4::from_scale(Vec3::splat(1.6)),
+        4.0,
+        smoke,
+    );
+    // The gas only glows on surfaces whose paths happen to hit it, a light in its middle stands
+    // in for that glow when lights are sampled
+    world.add_light(Box::new(PointLight {
+        position: gas.grid_to_world().transform_point3(Vec3::splat(0.5)),
+        intensity: Color::new(10.0, 4.0, 1.0, 1.0),
+    }));
+    world.add_hittable(Box::new(gas));
+    world.add_light(Box::new(SpotLight {
+        position: Vec3::new(-6.0, 5.0, -2.0),
+        direction: Vec3::new(0.5, -1.0, 0.5),
+        intensity: Color::new(20.0, 18.0, 15.0, 1.0),
+        cone_angle: 25.0,
+        edge_softness: 0.3,
+    }));
+    // Soft box over the middle row and a warm panel leaning in from the left
+    world.add_light(Box::new(DiskLight {
+        center: Vec3::new(0.0, 7.0, -1.0),
+        normal: Vec3::new(0.0, -1.0, 0.0),
+        radius: 1.5,
+        radiance: Color::new(1.5, 1.5, 1.6, 1.0),
+    }));
+    world.add_light(Box::new(TriangleLight {
+        a: Vec3::new(-9.0, 0.0, -3.0),
+        b: Vec3::new(-9.0, 0.0, 1.0),
+        c: Vec3::new(-8.0, 4.0, -1.0),
+        radiance: Color::new(1.2, 0.8, 0.5, 1.0),
+    }));
+
+    // Tungsten lamp on the floor under a cool white bulb
+    let lamp = Blackbody::new(2700.0, LightPower::Watts(60.0));
+    world.add_hittable(Box::new(Disk {
+        center: Vec3::new(3.0, 0.01, -3.0),
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        radius: 0.5,
+        material_id: MATERIAL_LAMBERTIAN,
+        surface: SurfaceAttributes {
+            emissive: lamp.radiance(PI * 0.5 * 0.5),
+            ..gray
+        },
+    }));
+    world.add_light(Box::new(PointLight {
+        position: Vec3::new(3.0, 2.0, -3.0),
+        intensity: Blackbody::new(4000.0, LightPower::Lumens(800.0)).intensity(),
+    }));
+
+    world.add_hittable(Box::new(Fog {
+        density: 0.005,
+        max_distance: 100.0,
+        surface: surface(Color::new(0.8, 0.85, 0.9, 1.0)),
+    }));
+    // Dimmer than the default so the scene is not blown out without exposure
+    let mut sky = PreethamSky::new(35.0, 120.0, 3.0);
+    sky.radiance_scale = 0.03;
+    world.set_sky(sky);
+}
+
+const USAGE: &str = "\
+Options:
+  --scene SCENE          spheres or showcase
+  --volume PATH          Voxel grid for the showcase's gas
+  --volume-size XxYxZ    Resolution of a headerless --volume of little endian floats
+  --save-volume PATH     Write the showcase's gas as a voxel grid file
+  --output PATH          Image to write, PPM or PNG by extension
+  --width N              Image width in pixels
+  --samples N            Samples per pixel
+  --filter FILTER        box, tent, gaussian, mitchell or lanczos
+  --aovs                 Also save the AOVs next to the render
+  --denoise              Filter the render guided by the AOVs
+  --sampler SAMPLER      independent, stratified, halton, sobol or blue-noise
+  --spectral             Trace hero wavelengths instead of RGB
+  --working-space SPACE  srgb, acescg or rec2020
+  --encode TRANSFORM     srgb, p3 or pq
+  --exposure MODE        off, manual or auto
+  --aperture SHAPE       circle, a blade count, or a PGM mask
+  --cat-eye AMOUNT       Barrel vignetting at the image corners, 0 is off
+  --lens PATH            Trace through a lens prescription instead of the thin lens
+  --glare                Streaks from a polygonal aperture
+  --vignette AMOUNT      Darkening of the image corners, 0 is off
+  --lut PATH             Grade with a .cube 3D LUT
+  --trilinear            Trilinear instead of tetrahedral LUT interpolation
+  --stereo LAYOUT        Both eyes in one image, side-by-side, over-under or omni
+  --frames FIRST-LAST    Numbered images of the camera orbiting the scene, frame 1 is at 0 s
+  --frame-rate FPS       Frames per second of the orbit, 24 by default
+  --video PATH           The --frames orbit as one .y4m or .gif file instead of images";
+
+enum Scene {
+    Spheres,  // Random spheres around three big ones
+    Showcase, // See setup_world2
+}
+
+/// What main renders.
+enum OutputMode {
+    Image,
+    Stereo(StereoRig),
+    Frames,
+    Video(String), // Path of a .y4m or .gif
+}
+
+/// Everything the command line can change, the defaults are the render main always made.
+struct Settings {
+    camera: Camera,
+    config: RenderConfig,
+    working_space: ColorSpace,
+    render_file_path: String,
+    output: OutputMode,
+    sequence: FrameSequence, // Frames rendered by the animated outputs
+    scene: Scene,
+    volume: Option<VoxelGrid>, // See setup_world2
+}
+
+fn invalid_argument(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, io::Error> {
+    value
+        .as_deref()
+        .and_then(|value| value.parse::<T>().ok())
+        .ok_or_else(|| invalid_argument(format!("{} needs a valid value", flag)))
+}
+
+fn parse_arguments(
+    mut arguments: impl Iterator<Item = String>,
+    settings: &mut Settings,
+) -> Result<(), io::Error> {
+    let camera = &mut settings.camera;
+    let config = &mut settings.config;
+    let mut sampler = None;
+    let mut glare = false;
+    let mut volume_path: Option<String> = None;
+    let mut volume_size: Option<[usize; 3]> = None;
+    let mut save_volume_path: Option<String> = None;
+
+    while let Some(flag) = arguments.next() {
+        match flag.as_str() {
+            "--scene" => {
+                let scene: String = parse_value(&flag, arguments.next())?;
+                settings.scene = match scene.as_str() {
+                    "spheres" => Scene::Spheres,
+                    "showcase" => Scene::Showcase,
+                    _ => return Err(invalid_argument(format!("unknown scene {}", scene))),
+                };
+            }
+            "--volume" => volume_path = Some(parse_value(&flag, arguments.next())?),
+            "--volume-size" => {
+                let size: String = parse_value(&flag, arguments.next())?;
+                let axes: Vec<usize> = size
+                    .split('x')
+                    .filter_map(|axis| axis.parse().ok())
+                    .collect();
+                let axes: [usize; 3] = axes
+                    .try_into()
+                    .map_err(|_| invalid_argument(format!("invalid volume size {}", size)))?;
+                volume_size = Some(axes);
+            }
+            "--save-volume" => save_volume_path = Some(parse_value(&flag, arguments.next())?),
+            "--output" => settings.render_file_path = parse_value(&flag, arguments.next())?,
+            "--width" => camera.image_width = parse_value(&flag, arguments.next())?,
+            "--samples" => camera.samples_per_pixel = parse_value(&flag, arguments.next())?,
+            "--filter" => {
+                let filter: String = parse_value(&flag, arguments.next())?;
+                camera.filter = match filter.as_str() {
+                    "box" => ReconstructionFilter::Box,
+                    "tent" => ReconstructionFilter::Tent { radius: 1.0 },
+                    "gaussian" => ReconstructionFilter::Gaussian {
+                        radius: 1.5,
+                        sigma: 0.5,
+                    },
+                    "mitchell" => ReconstructionFilter::MitchellNetravali {
+                        radius: 2.0,
+                        b: 1.0 / 3.0,
+                        c: 1.0 / 3.0,
+                    },
+                    "lanczos" => ReconstructionFilter::Lanczos { radius: 3.0 },
+                    _ => return Err(invalid_argument(format!("unknown filter {}", filter))),
+                };
+            }
+            "--aovs" => config.write_aovs = true,
+            "--denoise" => config.denoiser = Some(Denoiser::new()),
+            "--sampler" => sampler = Some(parse_value::<String>(&flag, arguments.next())?),
+            "--spectral" => camera.spectral = true,
+            "--working-space" => {
+                let space: String = parse_value(&flag, arguments.next())?;
+                settings.working_space = match space.as_str() {
+                    "srgb" => ColorSpace::LinearSrgb,
+                    "acescg" => ColorSpace::AcesCg,
+                    "rec2020" => ColorSpace::Rec2020,
+                    _ => return Err(invalid_argument(format!("unknown color space {}", space))),
+                };
+            }
+            "--encode" => {
+                let output: String = parse_value(&flag, arguments.next())?;
+                config.output = match output.as_str() {
+                    "srgb" => OutputTransform::Srgb,
+                    "p3" => OutputTransform::DisplayP3,
+                    "pq" => OutputTransform::Rec2020Pq {
+                        paper_white_nits: 203.0,
+                    },
+                    _ => return Err(invalid_argument(format!("unknown encoding {}", output))),
+                };
+            }
+            "--exposure" => {
+                let mode: String = parse_value(&flag, arguments.next())?;
+                camera.exposure.mode = match mode.as_str() {
+                    "off" => ExposureMode::Off,
+                    "manual" => ExposureMode::Manual,
+                    "auto" => ExposureMode::Auto,
+                    _ => return Err(invalid_argument(format!("unknown exposure {}", mode))),
+                };
+            }
+            "--aperture" => {
+                let shape: String = parse_value(&flag, arguments.next())?;
+                camera.aperture.shape = match shape.as_str() {
+                    "circle" => ApertureShape::Circle,
+                    _ => match shape.parse::<u32>() {
+                        Ok(blades) => ApertureShape::Polygon {
+                            blades,
+                            rotation: 0.0,
+                        },
+                        Err(_) => ApertureShape::Mask(Arc::new(ApertureMask::load(&shape)?)),
+                    },
+                };
+            }
+            "--cat-eye" => camera.aperture.cat_eye = parse_value(&flag, arguments.next())?,
+            "--lens" => {
+                let path: String = parse_value(&flag, arguments.next())?;
+                camera.lens = Some(LensSystem::load(&path)?);
+            }
+            "--glare" => glare = true,
+            "--vignette" => config.post_process.vignette = parse_value(&flag, arguments.next())?,
+            "--lut" => {
+                let path: String = parse_value(&flag, arguments.next())?;
+                let lut = Lut3d::load(&path)?;
+                println!("Grading with LUT \"{}\"", lut.title);
+                config.grade.lut = Some(Arc::new(lut));
+            }
+            "--trilinear" => config.grade.interpolation = LutInterpolation::Trilinear,
+            "--stereo" => {
+                let layout: String = parse_value(&flag, arguments.next())?;
+                let mut rig = StereoRig::new();
+                match layout.as_str() {
+                    "side-by-side" => rig.layout = StereoLayout::SideBySide,
+                    "over-under" => rig.layout = StereoLayout::OverUnder,
+                    // 360 degree eyes are stacked like most players expect
+                    "omni" => {
+                        rig.layout = StereoLayout::OverUnder;
+                        rig.omnidirectional = true;
+                    }
+                    _ => return Err(invalid_argument(format!("unknown layout {}", layout))),
+                }
+                settings.output = OutputMode::Stereo(rig);
+            }
+            "--frames" => {
+                let range: String = parse_value(&flag, arguments.next())?;
+                let frames = range
+                    .split_once('-')
+                    .and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)))
+                    .filter(|(first, last)| first <= last)
+                    .ok_or_else(|| invalid_argument(format!("invalid frame range {}", range)))?;
+                (settings.sequence.start_frame, settings.sequence.end_frame) = frames;
+                if !matches!(settings.output, OutputMode::Video(_)) {
+                    settings.output = OutputMode::Frames;
+                }
+            }
+            "--video" => {
+                let path: String = parse_value(&flag, arguments.next())?;
+                if !path.ends_with(".y4m") && !path.ends_with(".gif") {
+                    return Err(invalid_argument(format!("{} is not a .y4m or .gif", path)));
+                }
+                settings.output = OutputMode::Video(path);
+            }
+            "--frame-rate" => settings.sequence.frame_rate = parse_value(&flag, arguments.next())?,
+            _ => return Err(invalid_argument(format!("unknown option {}", flag))),
+        }
+    }
+
+    settings.volume = match (volume_path, volume_size) {
+        (Some(path), Some(resolution)) => Some(VoxelGrid::from_raw(&fs::read(path)?, resolution)?),
+        (Some(path), None) => Some(VoxelGrid::load(&path)?),
+        (None, Some(_)) => {
+            return Err(invalid_argument(
+                "--volume-size needs a --volume".to_string(),
+            ))
+        }
+        (None, None) => None,
+    };
+    if let Some(path) = save_volume_path {
+        settings
+            .volume
+            .get_or_insert_with(glowing_ball)
+            .save(&path)?;
+    }
+
+    // Built once all flags are in, the stratified grid depends on the final sample count
+    if let Some(sampler) = sampler {
+        camera.sampler = match sampler.as_str() {
+            "independent" => Arc::new(IndependentSampler {}),
+            "stratified" => Arc::new(StratifiedSampler {
+                samples_per_pixel: camera.samples_per_pixel.max(1) as u32,
+            }),
+            "halton" => Arc::new(HaltonSampler {}),
+            "sobol" => Arc::new(SobolSampler {}),
+            "blue-noise" => Arc::new(BlueNoiseSampler::new()),
+            _ => return Err(invalid_argument(format!("unknown sampler {}", sampler))),
+        };
+    }
+
+    if glare {
+        // Streaks reach a tenth of the image across
+        let length = 0.1 * camera.image_width as f32;
+        config.post_process.glare = Glare::from_aperture(&camera.aperture.shape, length, 0.05);
+        if config.post_process.glare.is_none() {
+            return Err(invalid_argument(
+                "--glare needs a polygonal --aperture".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Quarter turn around `target` over `duration` seconds, starting where the camera is.
+fn orbit_animation(camera: &Camera, target: Vec3, duration: f32) -> CameraAnimation {
+    const KEYFRAMES: usize = 4;
+    let mut animation = CameraAnimation::new(Interpolation::CatmullRom);
+    let offset = camera.position - target;
+    for i in 0..=KEYFRAMES {
+        let s = i as f32 / KEYFRAMES as f32;
+        animation.add_keyframe(CameraKeyframe {
+            time: s * duration,
+            position: target + Quat::from_rotation_y(s * FRAC_PI_2) * offset,
+            look_at: target,
+            fov: camera.fov,
+            focus_dist: camera.focus_dist,
+        });
+    }
+    animation
+}
+
 fn main() {
     let rt = tokio::runtime::Runtime::new().unwrap();
     let _rt_guard = rt.enter();
@@ -176,20 +830,99 @@ fn main() {
     camera.fov = 40.0;
     camera.samples_per_pixel = 10;
     camera.max_ray_per_pixel = 10;
-    camera.position = Vec3::new(-30.0, 6.0, -20.0);
-    let look_at_position = Vec3::new(0.0, 0.0, 0.0);
-    camera.look_at(look_at_position, Vec3::new(0.0, 1.0, 0.0));
-
     camera.defocus_angle = 0.6 * 0.5;
-    camera.focus_dist = (camera.position - look_at_position).length();
 
-    let mut world0: HittableList = HittableList::new();
-    setup_world0(&mut world0);
-    let mut world1: HittableList = HittableList::new();
-    setup_world1(&mut world1);
-    world0.merge(world1);
+    let mut settings = Settings {
+        camera,
+        config: RenderConfig {
+            write_aovs: false,
+            denoiser: None,
+            output: OutputTransform::Srgb,
+            post_process: PostProcess {
+                bloom: Some(Bloom::new()),
+                ..PostProcess::new()
+            },
+            grade: ColorGrade::new(),
+        },
+        working_space: ColorSpace::LinearSrgb,
+        render_file_path: "../img/render_test.ppm".to_string(),
+        output: OutputMode::Image,
+        sequence: FrameSequence::new(),
+        scene: Scene::Spheres,
+        volume: None,
+    };
+    if let Err(error) = parse_arguments(std::env::args().skip(1), &mut settings) {
+        eprintln!("{}\n{}", error, USAGE);
+        std::process::exit(2);
+    }
 
-    let mut world = world0;
-    let render_file_path = "../img/render_test.ppm";
-    render(&mut world, &mut camera, render_file_path).unwrap();
+    let mut world: HittableList = HittableList::new();
+    let look_at_position = match settings.scene {
+        Scene::Spheres => {
+            setup_world0(&mut world);
+            let mut world1: HittableList = HittableList::new();
+            setup_world1(&mut world1);
+            world.merge(world1);
+            settings.camera.position = Vec3::new(-30.0, 6.0, -20.0);
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+        Scene::Showcase => {
+            setup_world2(&mut world, settings.volume.take());
+            settings.camera.position = Vec3::new(0.0, 4.0, -14.0);
+            Vec3::new(0.0, 1.0, 0.0)
+        }
+    };
+    let camera = &mut settings.camera;
+    camera.look_at(look_at_position, Vec3::new(0.0, 1.0, 0.0));
+    camera.focus_dist = (camera.position - look_at_position).length();
+
+    world.working_space = settings.working_space;
+    let config = &settings.config;
+    let render_file_path = &settings.render_file_path;
+    match &settings.output {
+        OutputMode::Image => {
+            render(&world, &mut settings.camera, config, render_file_path).unwrap();
+        }
+        OutputMode::Stereo(rig) => {
+            rig.render(&world, &settings.camera, config, render_file_path)
+                .unwrap();
+        }
+        OutputMode::Frames => {
+            let sequence = &settings.sequence;
+            let duration = sequence.frame_time(sequence.end_frame);
+            let animation = orbit_animation(&settings.camera, look_at_position, duration);
+            sequence
+                .render(
+                    &world,
+                    &settings.camera,
+                    &animation,
+                    config,
+                    render_file_path,
+                )
+                .unwrap();
+        }
+        OutputMode::Video(video_path) => {
+            let sequence = &settings.sequence;
+            let duration = sequence.frame_time(sequence.end_frame);
+            let animation = orbit_animation(&settings.camera, look_at_position, duration);
+            settings.camera.initialize();
+            let (width, height) = settings.camera.get_image_xy();
+            let (width, height) = (width as usize, height as usize);
+            let camera = &settings.camera;
+            if video_path.ends_with(".gif") {
+                let mut video =
+                    GifEncoder::create(video_path, width, height, sequence.frame_rate, true)
+                        .unwrap();
+                sequence
+                    .render_video(&world, camera, &animation, config, &mut video)
+                    .unwrap();
+            } else {
+                let mut video =
+                    Y4mWriter::create(video_path, width, height, sequence.frame_rate).unwrap();
+                sequence
+                    .render_video(&world, camera, &animation, config, &mut video)
+                    .unwrap();
+            }
+        }
+    }
 }
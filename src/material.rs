@@ -1,17 +1,23 @@
+use glam::Vec3;
 use palette::white_point::E;
 
 use crate::{
     color::Color,
-    math::math::{near_zero_vec3, reflect, refract, schlick, unit_vector},
-    random::*,
+    math::math::{near_zero_vec3, reflect, refract, schlick},
     ray::{HitResult, Ray},
+    sampler::{next_1d, next_2d},
+    warp::{
+        cosine_hemisphere_pdf, local_to_world, square_to_cosine_hemisphere,
+        square_to_uniform_sphere, world_to_local,
+    },
 };
 
 pub const MATERIAL_DEFAULT: i32 = 0;
 pub const MATERIAL_LAMBERTIAN: i32 = 1;
 pub const MATERIAL_METAL: i32 = 2;
 pub const MATERIAL_DIELECTRIC: i32 = 3;
-pub const MATERIAL_NUM: i32 = 4;
+pub const MATERIAL_VOLUME: i32 = 4; // Assigned by participating media, scatters by the phase function
+pub const MATERIAL_NUM: i32 = 5;
 pub const EMISSIVE_OFF: bool = false;
 
 pub fn scatter(
@@ -32,7 +38,8 @@ pub fn scatter(
     };
 
     if material_id == MATERIAL_LAMBERTIAN || material_id == MATERIAL_DEFAULT {
-        let mut scatter_direction = hit_result.normal + rand_unit_vector();
+        let mut scatter_direction =
+            local_to_world(square_to_cosine_hemisphere(next_2d()), hit_result.normal);
         if near_zero_vec3(scatter_direction) {
             scatter_direction = hit_result.normal;
         }
@@ -44,7 +51,7 @@ pub fn scatter(
         let reflected = reflect(ray.direction.normalize(), hit_result.normal);
         *scattered_ray = Ray::new(
             hit_result.location,
-            reflected + fuzz_amount * rand_unit_vector(),
+            reflected + fuzz_amount * square_to_uniform_sphere(next_2d()),
         );
         *diffuse = surface_albedo;
         return scattered_ray.direction.dot(hit_result.normal) > 0.0;
@@ -60,7 +67,7 @@ pub fn scatter(
         let cos_theta = (-unit_direction.dot(hit_result.normal)).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
-        let direction = if cannot_refract || schlick(cos_theta, refraction_ratio) > rand() {
+        let direction = if cannot_refract || schlick(cos_theta, refraction_ratio) > next_1d() {
             reflect(unit_direction, hit_result.normal)
         } else {
             let refracted = refract(unit_direction, hit_result.normal, refraction_ratio);
@@ -71,7 +78,40 @@ pub fn scatter(
         *diffuse = Color::new(1.0, 1.0, 1.0, 1.0);
         *scattered_ray = Ray::new(hit_result.location, direction);
         return true;
+    } else if material_id == MATERIAL_VOLUME {
+        let direction = hit_result
+            .surface
+            .phase
+            .sample_with(ray.direction, next_2d());
+        *scattered_ray = Ray::new(hit_result.location, direction);
+        *diffuse = surface_albedo;
+        return true;
     }
 
     return false;
 }
+
+/// Materials that scatter over a range of directions, these get lights sampled directly.
+pub fn has_diffuse_lobe(material_id: i32) -> bool {
+    material_id == MATERIAL_LAMBERTIAN
+        || material_id == MATERIAL_DEFAULT
+        || material_id == MATERIAL_VOLUME
+}
+
+/// BSDF (or phase function) times cosine for light arriving from `direction`, used for direct lighting.
+/// Specular materials only scatter into a single direction and always return black.
+pub fn evaluate(ray: &Ray, hit_result: &HitResult, direction: Vec3) -> Color {
+    let material_id = hit_result.material_id;
+    let surface_albedo = hit_result.surface.albedo;
+
+    if material_id == MATERIAL_LAMBERTIAN || material_id == MATERIAL_DEFAULT {
+        // Albedo / pi times the cosine, which is exactly the cosine weighted pdf
+        return surface_albedo
+            * cosine_hemisphere_pdf(world_to_local(direction, hit_result.normal));
+    } else if material_id == MATERIAL_VOLUME {
+        let cos_theta = ray.direction.normalize().dot(direction);
+        return surface_albedo * hit_result.surface.phase.evaluate(cos_theta);
+    }
+
+    Color::new(0.0, 0.0, 0.0, 1.0)
+}
@@ -38,13 +38,158 @@ pub mod math {
         vec[0].abs() < s && vec[1].abs() < s && vec[2].abs() < s
     }
 
-    pub fn unit_vector(x: Vec3) -> Vec3 {
-        x.normalize()
-    }
-
     pub fn schlick(cosine: f32, refraction_index: f32) -> f32 {
         let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
         r0 = r0 * r0;
         return r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0);
     }
+
+    const SOLVER_EPSILON: f64 = 1e-9;
+
+    fn is_zero(x: f64) -> bool {
+        x.abs() < SOLVER_EPSILON
+    }
+
+    /// Real roots of c[2]x^2 + c[1]x + c[0], returned as (roots, count).
+    pub fn solve_quadratic(c: [f64; 3]) -> ([f64; 2], usize) {
+        let mut roots = [0.0; 2];
+        if is_zero(c[2]) {
+            if is_zero(c[1]) {
+                return (roots, 0);
+            }
+            roots[0] = -c[0] / c[1];
+            return (roots, 1);
+        }
+
+        let p = c[1] / (2.0 * c[2]);
+        let q = c[0] / c[2];
+        let discriminant = p * p - q;
+        if is_zero(discriminant) {
+            roots[0] = -p;
+            return (roots, 1);
+        } else if discriminant < 0.0 {
+            return (roots, 0);
+        }
+
+        let discriminant_sqrt = discriminant.sqrt();
+        roots[0] = discriminant_sqrt - p;
+        roots[1] = -discriminant_sqrt - p;
+        (roots, 2)
+    }
+
+    /// Real roots of c[3]x^3 + c[2]x^2 + c[1]x + c[0] (Cardano), returned as (roots, count).
+    pub fn solve_cubic(c: [f64; 4]) -> ([f64; 3], usize) {
+        let mut roots = [0.0; 3];
+        let a = c[2] / c[3];
+        let b = c[1] / c[3];
+        let cc = c[0] / c[3];
+
+        // Substitute x = y - a/3 to get y^3 + 3py + 2q = 0
+        let sq_a = a * a;
+        let p = (-sq_a / 3.0 + b) / 3.0;
+        let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+        let cb_p = p * p * p;
+        let discriminant = q * q + cb_p;
+
+        let num_roots = if is_zero(discriminant) {
+            if is_zero(q) {
+                roots[0] = 0.0;
+                1
+            } else {
+                let u = (-q).cbrt();
+                roots[0] = 2.0 * u;
+                roots[1] = -u;
+                2
+            }
+        } else if discriminant < 0.0 {
+            let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+            let t = 2.0 * (-p).sqrt();
+            roots[0] = t * phi.cos();
+            roots[1] = -t * (phi + std::f64::consts::FRAC_PI_3).cos();
+            roots[2] = -t * (phi - std::f64::consts::FRAC_PI_3).cos();
+            3
+        } else {
+            let discriminant_sqrt = discriminant.sqrt();
+            let u = (discriminant_sqrt - q).cbrt();
+            let v = -(discriminant_sqrt + q).cbrt();
+            roots[0] = u + v;
+            1
+        };
+
+        for root in roots.iter_mut().take(num_roots) {
+            *root -= a / 3.0;
+        }
+        (roots, num_roots)
+    }
+
+    /// Real roots of c[4]x^4 + ... + c[0] (Ferrari), polished with Newton iterations.
+    pub fn solve_quartic(c: [f64; 5]) -> ([f64; 4], usize) {
+        let mut roots = [0.0; 4];
+        let a = c[3] / c[4];
+        let b = c[2] / c[4];
+        let cc = c[1] / c[4];
+        let d = c[0] / c[4];
+
+        // Substitute x = y - a/4 to get y^4 + py^2 + qy + r = 0
+        let sq_a = a * a;
+        let p = -3.0 / 8.0 * sq_a + b;
+        let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+        let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+
+        let num_roots: usize = if is_zero(r) {
+            // y(y^3 + py + q) = 0
+            let (cubic_roots, num_cubic_roots) = solve_cubic([q, p, 0.0, 1.0]);
+            roots[..num_cubic_roots].copy_from_slice(&cubic_roots[..num_cubic_roots]);
+            roots[num_cubic_roots] = 0.0;
+            num_cubic_roots + 1
+        } else {
+            // Resolvent cubic, any real root z splits the quartic into two quadratics
+            let (cubic_roots, _) = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0]);
+            let z = cubic_roots[0];
+
+            let mut u = z * z - r;
+            let mut v = 2.0 * z - p;
+            if is_zero(u) {
+                u = 0.0;
+            } else if u > 0.0 {
+                u = u.sqrt();
+            } else {
+                return (roots, 0);
+            }
+            if is_zero(v) {
+                v = 0.0;
+            } else if v > 0.0 {
+                v = v.sqrt();
+            } else {
+                return (roots, 0);
+            }
+
+            let (first_roots, num_first) =
+                solve_quadratic([z - u, if q < 0.0 { -v } else { v }, 1.0]);
+            let (second_roots, num_second) =
+                solve_quadratic([z + u, if q < 0.0 { v } else { -v }, 1.0]);
+            roots[..num_first].copy_from_slice(&first_roots[..num_first]);
+            roots[num_first..num_first + num_second].copy_from_slice(&second_roots[..num_second]);
+            num_first + num_second
+        };
+
+        for root in roots.iter_mut().take(num_roots) {
+            *root -= a / 4.0;
+        }
+
+        // The closed form loses precision for nearly tangent rays, a few Newton steps recover it
+        const NEWTON_ITERATIONS: usize = 2;
+        for root in roots.iter_mut().take(num_roots) {
+            for _i in 0..NEWTON_ITERATIONS {
+                let x = *root;
+                let f = (((c[4] * x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+                let df = ((4.0 * c[4] * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+                if df.abs() > SOLVER_EPSILON {
+                    *root = x - f / df;
+                }
+            }
+        }
+
+        (roots, num_roots)
+    }
 }
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+
+use crate::{
+    interval::Interval,
+    material::MATERIAL_VOLUME,
+    random::rand,
+    ray::{HitResult, HitSpan, Hittable, Ray, SurfaceAttributes},
+    warp::uniform_sphere_pdf,
+};
+
+/// Distribution of scattering directions inside a medium.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub enum PhaseFunction {
+    #[default]
+    Isotropic,
+    /// `g` in (-1, 1), positive scatters forward and negative backward.
+    HenyeyGreenstein { g: f32 },
+}
+
+impl PhaseFunction {
+    /// Phase function value, also the pdf of `sample`, for the angle between the propagation
+    /// direction and the scattered direction.
+    pub fn evaluate(&self, cos_theta: f32) -> f32 {
+        match *self {
+            PhaseFunction::Isotropic => uniform_sphere_pdf(),
+            PhaseFunction::HenyeyGreenstein { g } => {
+                let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+                uniform_sphere_pdf() * (1.0 - g * g) / (denominator * denominator.sqrt())
+            }
+        }
+    }
+
+    /// Samples a scattered direction for a ray travelling along `direction`.
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        self.sample_with(direction, Vec2::new(rand::<f32>(), rand::<f32>()))
+    }
+
+    pub fn sample_with(&self, direction: Vec3, u: Vec2) -> Vec3 {
+        let cos_theta = match *self {
+            PhaseFunction::HenyeyGreenstein { g } if g.abs() > 1e-3 => {
+                let sq_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+                ((1.0 + g * g - sq_term * sq_term) / (2.0 * g)).clamp(-1.0, 1.0)
+            }
+            _ => 1.0 - 2.0 * u.x,
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u.y;
+
+        let forward = direction.normalize();
+        let (tangent, bitangent) = forward.any_orthonormal_pair();
+        tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + forward * cos_theta
+    }
+}
+
+/// Samples the free flight distance through a medium with extinction `density`.
+fn sample_distance(density: f32) -> f32 {
+    -(1.0 - rand::<f32>()).ln() / density
+}
+
+pub fn medium_hit_result(ray: &Ray, t: f32, surface: SurfaceAttributes) -> HitResult {
+    HitResult {
+        location: ray.at(t),
+        normal: -ray.direction,
+        t,
+        uv: Vec2::ZERO,
+        front_face: Some(true),
+        material_id: MATERIAL_VOLUME,
+        surface,
+    }
+}
+
+/// Homogeneous medium filling a closed `boundary`.
+/// `surface.albedo` is the single scattering albedo, `surface.emissive` makes the volume glow.
+#[derive(Clone)]
+pub struct ConstantMedium {
+    pub boundary: Arc<dyn Hittable + Sync + Send>,
+    pub density: f32, // Extinction coefficient per world unit
+    pub surface: SurfaceAttributes,
+}
+
+impl Hittable for ConstantMedium {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        if self.density <= 0.0 {
+            return None;
+        }
+
+        let spans = self
+            .boundary
+            .hit_spans(ray, Interval::new(interval.min, interval.max));
+        for span in spans {
+            let t_enter = span.enter_t().max(interval.min);
+            let t_exit = span.exit_t().min(interval.max);
+            if t_enter >= t_exit {
+                continue;
+            }
+
+            // Exponential flights are memoryless, a miss in one span does not bias the next
+            let t = t_enter + sample_distance(self.density);
+            if t < t_exit {
+                return Some(medium_hit_result(ray, t, self.surface));
+            }
+        }
+
+        None
+    }
+
+    fn transmittance(&self, ray: &Ray, interval: Interval) -> f32 {
+        let mut distance_inside = 0.0;
+        for span in self
+            .boundary
+            .hit_spans(ray, Interval::new(interval.min, interval.max))
+        {
+            let t_enter = span.enter_t().max(interval.min);
+            let t_exit = span.exit_t().min(interval.max);
+            distance_inside += (t_exit - t_enter).max(0.0);
+        }
+
+        (-self.density * distance_inside).exp()
+    }
+}
+
+/// Homogeneous fog filling the whole scene, add it to the `HittableList` like any object.
+/// Rays leaving the scene travel through `max_distance` of fog before reaching the sky.
+#[derive(Clone)]
+pub struct Fog {
+    pub density: f32,
+    pub max_distance: f32,
+    pub surface: SurfaceAttributes,
+}
+
+impl Hittable for Fog {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        if self.density <= 0.0 {
+            return None;
+        }
+
+        let t = interval.min + sample_distance(self.density);
+        if t >= interval.max.min(self.max_distance) {
+            return None;
+        }
+
+        Some(medium_hit_result(ray, t, self.surface))
+    }
+
+    fn transmittance(&self, _ray: &Ray, interval: Interval) -> f32 {
+        let distance = (interval.max.min(self.max_distance) - interval.min).max(0.0);
+        (-self.density * distance).exp()
+    }
+
+    fn hit_spans(&self, _ray: &Ray, _interval: Interval) -> Vec<HitSpan> {
+        // Fog has no boundary to enter or exit
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ray::Sphere;
+
+    use super::*;
+
+    #[test]
+    fn test_henyey_greenstein_mean_cosine() {
+        const SAMPLES: i32 = 20000;
+        for g in [-0.6, 0.0, 0.3, 0.8] {
+            let phase = PhaseFunction::HenyeyGreenstein { g };
+            let mut sum_cos = 0.0;
+            for _i in 0..SAMPLES {
+                sum_cos += phase.sample(Vec3::Z).dot(Vec3::Z);
+            }
+            assert!((sum_cos / SAMPLES as f32 - g).abs() < 0.03);
+        }
+    }
+
+    #[test]
+    fn test_constant_medium_stays_inside_boundary() {
+        let medium = ConstantMedium {
+            boundary: Arc::new(Sphere {
+                center: Vec3::new(0.0, 0.0, -5.0),
+                radius: 1.0,
+                material_id: 0,
+                surface: SurfaceAttributes::default(),
+            }),
+            density: 2.0,
+            surface: SurfaceAttributes::default(),
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let mut num_hits = 0;
+        for _i in 0..1000 {
+            if let Some(hit_result) = medium.hit(&ray, Interval::new(0.0001, f32::INFINITY)) {
+                assert!(hit_result.t > 4.0 && hit_result.t < 6.0);
+                assert_eq!(hit_result.material_id, MATERIAL_VOLUME);
+                num_hits += 1;
+            }
+        }
+
+        // Transmittance through 2 units at density 2 is exp(-4), almost every ray scatters
+        assert!(num_hits > 950);
+    }
+}
@@ -0,0 +1,268 @@
+use glam::{Vec2, Vec3};
+
+use crate::{
+    interval::Interval,
+    ray::{HitResult, Hittable, Ray, SurfaceAttributes},
+};
+
+const PARALLEL_EPSILON: f32 = 0.0001;
+
+/// Intersects the ray with the plane through `point` with unit `normal`, returns t if inside the interval.
+fn hit_plane_t(ray: &Ray, point: Vec3, normal: Vec3, interval: &Interval) -> Option<f32> {
+    let denominator = normal.dot(ray.direction);
+    if denominator.abs() < PARALLEL_EPSILON {
+        return None;
+    }
+
+    let t = (point - ray.origin).dot(normal) / denominator;
+    if !interval.surrounds(t) {
+        return None;
+    }
+
+    Some(t)
+}
+
+/// Parallelogram spanned by `u` and `v` from the corner `q`, uv runs from 0 to 1 along each edge.
+#[derive(Clone)]
+pub struct Quad {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material_id: i32, surface: SurfaceAttributes) -> Self {
+        Self {
+            q,
+            u,
+            v,
+            material_id,
+            surface,
+        }
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.u.cross(self.v).normalize()
+    }
+}
+
+impl Hittable for Quad {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let n = self.u.cross(self.v);
+        let normal = n.normalize();
+        let t = hit_plane_t(ray, self.q, normal, &interval)?;
+
+        // Planar coordinates of the hit point in the (u, v) basis
+        let w = n / n.dot(n);
+        let planar_hit = ray.at(t) - self.q;
+        let alpha = w.dot(planar_hit.cross(self.v));
+        let beta = w.dot(self.u.cross(planar_hit));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let mut hit_result: HitResult = HitResult {
+            location: ray.at(t),
+            normal,
+            t,
+            uv: Vec2::new(alpha, beta),
+            front_face: None,
+            material_id: self.material_id,
+            surface: self.surface,
+        };
+        hit_result.set_face_normal(ray, normal);
+
+        return Some(hit_result);
+    }
+}
+
+/// Axis aligned box built from six outward facing quads.
+#[derive(Clone)]
+pub struct AxisAlignedBox {
+    pub sides: [Quad; 6],
+}
+
+impl AxisAlignedBox {
+    pub fn new(a: Vec3, b: Vec3, material_id: i32, surface: SurfaceAttributes) -> Self {
+        let min = a.min(b);
+        let max = a.max(b);
+
+        let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+        let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+        let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+
+        let side = |q: Vec3, u: Vec3, v: Vec3| Quad::new(q, u, v, material_id, surface);
+        let sides: [Quad; 6] = [
+            side(Vec3::new(min.x, min.y, max.z), dx, dy),  // front
+            side(Vec3::new(max.x, min.y, max.z), -dz, dy), // right
+            side(Vec3::new(max.x, min.y, min.z), -dx, dy), // back
+            side(Vec3::new(min.x, min.y, min.z), dz, dy),  // left
+            side(Vec3::new(min.x, max.y, max.z), dx, -dz), // top
+            side(Vec3::new(min.x, min.y, min.z), dx, dz),  // bottom
+        ];
+
+        Self { sides }
+    }
+}
+
+impl Hittable for AxisAlignedBox {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let mut closest_so_far = interval.max;
+        let mut closest_hit: Option<HitResult> = None;
+        for side in self.sides.iter() {
+            if let Some(hit_result) = side.hit(
+                ray,
+                Interval {
+                    min: interval.min,
+                    max: closest_so_far,
+                },
+            ) {
+                closest_so_far = hit_result.t;
+                closest_hit = Some(hit_result);
+            }
+        }
+
+        closest_hit
+    }
+}
+
+/// Flat disk, uv is (angle / 2pi, distance from center / radius).
+#[derive(Clone)]
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl Hittable for Disk {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let normal = self.normal.normalize();
+        let t = hit_plane_t(ray, self.center, normal, &interval)?;
+
+        let offset = ray.at(t) - self.center;
+        let distance = offset.length();
+        if distance > self.radius {
+            return None;
+        }
+
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        let phi = offset.dot(bitangent).atan2(offset.dot(tangent)) + std::f32::consts::PI;
+
+        let mut hit_result: HitResult = HitResult {
+            location: ray.at(t),
+            normal,
+            t,
+            uv: Vec2::new(phi / (2.0 * std::f32::consts::PI), distance / self.radius),
+            front_face: None,
+            material_id: self.material_id,
+            surface: self.surface,
+        };
+        hit_result.set_face_normal(ray, normal);
+
+        return Some(hit_result);
+    }
+}
+
+/// Finite version of `Plane`, a `size` rectangle centered on `center` with its v axis along `up`.
+#[derive(Clone)]
+pub struct BoundedPlane {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub up: Vec3,
+    pub size: Vec2,
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl BoundedPlane {
+    pub fn to_quad(&self) -> Quad {
+        let normal = self.normal.normalize();
+        let v_dir = (self.up - normal * self.up.dot(normal)).normalize();
+        let u_dir = v_dir.cross(normal);
+        let u = u_dir * self.size.x;
+        let v = v_dir * self.size.y;
+
+        Quad::new(
+            self.center - (u + v) * 0.5,
+            u,
+            v,
+            self.material_id,
+            self.surface,
+        )
+    }
+}
+
+impl Hittable for BoundedPlane {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        self.to_quad().hit(ray, interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quad_hit_uv() {
+        let quad = Quad::new(
+            Vec3::new(-1.0, -1.0, -5.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 4.0, 0.0),
+            1,
+            SurfaceAttributes::default(),
+        );
+        let ray = Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let hit_result = quad
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        assert!((hit_result.t - 5.0).abs() < 1e-5);
+        assert!((hit_result.uv - Vec2::new(0.75, 0.25)).length() < 1e-5);
+        assert_eq!(hit_result.front_face, Some(true));
+
+        let miss_ray = Ray::new(Vec3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(quad
+            .hit(&miss_ray, Interval::new(0.0001, f32::INFINITY))
+            .is_none());
+    }
+
+    #[test]
+    fn test_box_outward_normals() {
+        let aa_box = AxisAlignedBox::new(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            1,
+            SurfaceAttributes::default(),
+        );
+        let directions = [Vec3::X, Vec3::Y, Vec3::Z, -Vec3::X, -Vec3::Y, -Vec3::Z];
+        for direction in directions {
+            let ray = Ray::new(direction * 5.0, -direction);
+            let hit_result = aa_box
+                .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+                .unwrap();
+            assert!((hit_result.t - 4.0).abs() < 1e-5);
+            assert!((hit_result.normal - direction).length() < 1e-5);
+            assert_eq!(hit_result.front_face, Some(true));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{self, Write};
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// CRC-32 of PNG chunks, the reflected ISO 3309 polynomial.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Checksum closing a zlib stream.
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Zlib stream of stored deflate blocks, larger than the data but needs no compressor.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(is_final as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<(), io::Error> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(&[kind.as_slice(), data].concat());
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// RGB PNG of `pixels`, row major with the top row first, with 8 or 16 bits per channel.
+/// `cicp` goes into a cICP chunk naming the primaries, transfer function, matrix and range
+/// as H.273 code points, so viewers can show PQ and P3 without guessing.
+pub fn write_png<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[[u16; 3]],
+    bit_depth: u8,
+    cicp: [u8; 4],
+) -> Result<(), io::Error> {
+    if pixels.len() != width * height {
+        return Err(invalid_data("pixel count does not match the image size"));
+    }
+    if bit_depth != 8 && bit_depth != 16 {
+        return Err(invalid_data("PNG bit depth must be 8 or 16"));
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Truecolor, deflate, no filtering and no interlacing
+    header.extend_from_slice(&[bit_depth, 2, 0, 0, 0]);
+
+    // Every row starts with filter type 0, samples are big endian
+    let mut scanlines = Vec::with_capacity(height * (1 + width * 3 * bit_depth as usize / 8));
+    for row in pixels.chunks(width.max(1)).take(height) {
+        scanlines.push(0);
+        for &value in row.iter().flatten() {
+            if bit_depth == 16 {
+                scanlines.extend_from_slice(&value.to_be_bytes());
+            } else {
+                scanlines.push(value.min(255) as u8);
+            }
+        }
+    }
+
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_chunk(writer, b"IHDR", &header)?;
+    write_chunk(writer, b"cICP", &cicp)?;
+    write_chunk(writer, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(writer, b"IEND", &[])?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type and data of each chunk after checking its CRC.
+    fn read_chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let mut chunks = Vec::new();
+        let mut offset = 8;
+        while offset < png.len() {
+            let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = png[offset + 4..offset + 8].try_into().unwrap();
+            let data = png[offset + 8..offset + 8 + length].to_vec();
+            let crc = u32::from_be_bytes(
+                png[offset + 8 + length..offset + 12 + length]
+                    .try_into()
+                    .unwrap(),
+            );
+            assert_eq!(crc, crc32(&png[offset + 4..offset + 8 + length]));
+            chunks.push((kind, data));
+            offset += 12 + length;
+        }
+        chunks
+    }
+
+    /// Inverse of zlib_stored.
+    fn inflate_stored(stream: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut offset = 2;
+        loop {
+            let is_final = stream[offset] & 1 == 1;
+            assert_eq!(stream[offset] >> 1, 0);
+            let length = u16::from_le_bytes([stream[offset + 1], stream[offset + 2]]);
+            let inverse = u16::from_le_bytes([stream[offset + 3], stream[offset + 4]]);
+            assert_eq!(length, !inverse);
+            data.extend_from_slice(&stream[offset + 5..offset + 5 + length as usize]);
+            offset += 5 + length as usize;
+            if is_final {
+                break;
+            }
+        }
+        assert_eq!(stream[offset..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_png_chunks_and_pixels() {
+        // Reference values of the IEND chunk and of "Wikipedia"
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        // Wide enough that the image data spans several stored blocks
+        let (width, height) = (300, 40);
+        let pixels: Vec<[u16; 3]> = (0..width * height)
+            .map(|i| [i as u16, (i * 7) as u16, 65535 - i as u16])
+            .collect();
+        let mut png = Vec::new();
+        write_png(&mut png, width, height, &pixels, 16, [9, 16, 0, 1]).unwrap();
+
+        let chunks = read_chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"cICP", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1[8..], [16, 2, 0, 0, 0]);
+        assert_eq!(chunks[1].1, [9, 16, 0, 1]);
+
+        let scanlines = inflate_stored(&chunks[2].1);
+        let row_size = 1 + width * 6;
+        assert_eq!(scanlines.len(), height * row_size);
+        let row = &scanlines[row_size..2 * row_size];
+        assert_eq!(row[0], 0);
+        let first = pixels[width];
+        assert_eq!(
+            row[1..7],
+            [
+                first[0].to_be_bytes(),
+                first[1].to_be_bytes(),
+                first[2].to_be_bytes()
+            ]
+            .concat()
+        );
+
+        let mut small = Vec::new();
+        write_png(
+            &mut small,
+            2,
+            1,
+            &[[255, 0, 0], [0, 0, 255]],
+            8,
+            [1, 13, 0, 1],
+        )
+        .unwrap();
+        let chunks = read_chunks(&small);
+        assert_eq!(inflate_stored(&chunks[2].1), [0, 255, 0, 0, 0, 0, 255]);
+        assert!(write_png(&mut Vec::new(), 2, 2, &[[0; 3]; 3], 8, [1, 13, 0, 1]).is_err());
+    }
+}
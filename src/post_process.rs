@@ -0,0 +1,351 @@
+use std::f32::consts::PI;
+
+use crate::{
+    bokeh::ApertureShape, color::Color, color_space::ColorSpace, framebuffer::Framebuffer,
+};
+
+/// Binomial approximation of a Gaussian, separable, indexed by the tap distance 0, 1 and 2.
+const GAUSSIAN_KERNEL: [f32; 3] = [6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+/// Part of a color above `threshold` luminance, with a quadratic `knee` wide ramp into it
+/// so the cut off does not show.
+fn bright_pass(color: Color, color_space: ColorSpace, threshold: f32, knee: f32) -> Color {
+    let y = color_space.luminance(color);
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0, 1.0);
+    }
+    let soft = (y - threshold + knee).clamp(0.0, 2.0 * knee);
+    let soft = if knee > 0.0 {
+        soft * soft / (4.0 * knee)
+    } else {
+        0.0
+    };
+    let contribution = soft.max(y - threshold).max(0.0) / y;
+    let mut bright = color * contribution;
+    bright.alpha = 1.0;
+    bright
+}
+
+fn bright_pass_image(framebuffer: &Framebuffer, threshold: f32, knee: f32) -> Framebuffer {
+    let mut bright = Framebuffer::new(framebuffer.width, framebuffer.height);
+    bright.color_space = framebuffer.color_space;
+    for (target, pixel) in bright.pixels.iter_mut().zip(framebuffer.pixels.iter()) {
+        *target = bright_pass(*pixel, framebuffer.color_space, threshold, knee);
+    }
+    bright
+}
+
+/// Adds the color of `light` to `framebuffer`, alpha is kept.
+fn add_light(framebuffer: &mut Framebuffer, light: &Framebuffer) {
+    for (pixel, added) in framebuffer.pixels.iter_mut().zip(light.pixels.iter()) {
+        pixel.red += added.red;
+        pixel.green += added.green;
+        pixel.blue += added.blue;
+    }
+}
+
+/// 5x5 Gaussian blur, edges are clamped.
+fn blur(image: &Framebuffer) -> Framebuffer {
+    let (width, height) = (image.width, image.height);
+    let pass = |source: &Framebuffer, dx: i32, dy: i32| {
+        let mut target = Framebuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Color::new(0.0, 0.0, 0.0, 0.0);
+                for tap in -2..=2_i32 {
+                    let weight = GAUSSIAN_KERNEL[tap.unsigned_abs() as usize];
+                    let sx = (x + tap * dx).clamp(0, width - 1);
+                    let sy = (y + tap * dy).clamp(0, height - 1);
+                    sum += source.get(sx, sy) * weight;
+                }
+                let index = target.index(x, y);
+                target.pixels[index] = sum;
+            }
+        }
+        target
+    };
+    pass(&pass(image, 1, 0), 0, 1)
+}
+
+/// Blurred and halved, the next level of the pyramid.
+fn downsample(image: &Framebuffer) -> Framebuffer {
+    let blurred = blur(image);
+    let mut half = Framebuffer::new((image.width / 2).max(1), (image.height / 2).max(1));
+    for y in 0..half.height {
+        for x in 0..half.width {
+            let index = half.index(x, y);
+            half.pixels[index] =
+                blurred.get((2 * x).min(image.width - 1), (2 * y).min(image.height - 1));
+        }
+    }
+    half
+}
+
+/// Bilinear lookup at `u`, `v` in [0, 1] over the whole image.
+fn sample_bilinear(image: &Framebuffer, u: f32, v: f32) -> Color {
+    let x = (u * image.width as f32 - 0.5).clamp(0.0, (image.width - 1) as f32);
+    let y = (v * image.height as f32 - 0.5).clamp(0.0, (image.height - 1) as f32);
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width - 1),
+        (y0 + 1).min(image.height - 1),
+    );
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let top = image.get(x0, y0) * (1.0 - tx) + image.get(x1, y0) * tx;
+    let bottom = image.get(x0, y1) * (1.0 - tx) + image.get(x1, y1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+/// Glow around highlights, the average of the bright pass blurred at every pyramid level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    pub threshold: f32, // Luminance where bloom starts, 1 is the white point after exposure
+    pub knee: f32,      // Width of the soft ramp below the threshold
+    pub intensity: f32, // Fraction of the bright light spread into the glow
+    pub levels: u32,    // Pyramid levels, each doubles the reach
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bloom {
+    pub fn new() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.2,
+            levels: 6,
+        }
+    }
+
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        let mut level = bright_pass_image(framebuffer, self.threshold, self.knee);
+        let mut pyramid: Vec<Framebuffer> = Vec::with_capacity(self.levels as usize);
+        for _i in 0..self.levels.max(1) {
+            level = downsample(&level);
+            pyramid.push(level.clone());
+        }
+
+        let scale = self.intensity / pyramid.len() as f32;
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                let mut glow = Color::new(0.0, 0.0, 0.0, 0.0);
+                for level in pyramid.iter() {
+                    glow += sample_bilinear(level, u, v);
+                }
+                let index = framebuffer.index(x, y);
+                let pixel = &mut framebuffer.pixels[index];
+                pixel.red += glow.red * scale;
+                pixel.green += glow.green * scale;
+                pixel.blue += glow.blue * scale;
+            }
+        }
+    }
+}
+
+/// Star shaped streaks from diffraction on the aperture blades.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glare {
+    pub streaks: u32,
+    pub rotation: f32, // Deg, direction of the first streak, 0 points right
+    pub length: f32,   // Pixels
+    pub threshold: f32,
+    pub intensity: f32, // Fraction of the bright light spread into the streaks
+}
+
+impl Glare {
+    /// Streaks of a polygonal aperture. Each blade edge diffracts perpendicular to itself,
+    /// even blade counts pair up, odd ones give twice as many streaks. Round apertures have none.
+    pub fn from_aperture(shape: &ApertureShape, length: f32, intensity: f32) -> Option<Self> {
+        let ApertureShape::Polygon { blades, rotation } = shape else {
+            return None;
+        };
+        let blades = (*blades).max(3);
+        let streaks = if blades % 2 == 0 { blades } else { 2 * blades };
+        // The first edge is between corners 0 and 1, its normal is half a blade turned
+        let edge_normal = rotation + 180.0 / blades as f32;
+        Some(Self {
+            streaks,
+            rotation: edge_normal,
+            length,
+            threshold: 1.0,
+            intensity,
+        })
+    }
+
+    /// Only the light of the streaks, without the image.
+    pub fn streak_light(&self, framebuffer: &Framebuffer) -> Framebuffer {
+        let bright = bright_pass_image(framebuffer, self.threshold, 0.0);
+        let length = self.length.max(1.0) as i32;
+
+        // Falloff along a streak, normalized so all streaks together carry `intensity`
+        let falloff = |k: i32| {
+            let t = k as f32 / length as f32;
+            (1.0 - t) * (1.0 - t)
+        };
+        let total: f32 = (1..=length).map(falloff).sum::<f32>() * self.streaks as f32;
+        let scale = self.intensity / total.max(1e-6);
+
+        let directions: Vec<(f32, f32)> = (0..self.streaks)
+            .map(|i| {
+                let angle = self.rotation.to_radians() + 2.0 * PI * i as f32 / self.streaks as f32;
+                // +y is down in the image
+                (angle.cos(), -angle.sin())
+            })
+            .collect();
+
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let mut streaks = Framebuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let source = bright.get(x, y);
+                if bright.luminance(source) <= 0.0 {
+                    continue;
+                }
+                for (dx, dy) in directions.iter() {
+                    for k in 1..=length {
+                        let tx = (x as f32 + dx * k as f32).round() as i32;
+                        let ty = (y as f32 + dy * k as f32).round() as i32;
+                        if tx < 0 || ty < 0 || tx >= width || ty >= height {
+                            break;
+                        }
+                        let index = streaks.index(tx, ty);
+                        streaks.pixels[index] += source * (falloff(k) * scale);
+                    }
+                }
+            }
+        }
+        streaks
+    }
+}
+
+/// Effects on the linear image after exposure, before the output transform.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PostProcess {
+    pub bloom: Option<Bloom>,
+    pub glare: Option<Glare>,
+    pub vignette: f32, // Darkening of the image corners, 0 is off and 1 makes them black
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        Self {
+            bloom: None,
+            glare: None,
+            vignette: 0.0,
+        }
+    }
+
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        if framebuffer.pixels.is_empty() {
+            return;
+        }
+        // Streaks come from the image without bloom, so the glow does not streak
+        let streaks = self.glare.map(|glare| glare.streak_light(framebuffer));
+        if let Some(bloom) = &self.bloom {
+            bloom.apply(framebuffer);
+        }
+        if let Some(streaks) = &streaks {
+            add_light(framebuffer, streaks);
+        }
+        if self.vignette > 0.0 {
+            self.apply_vignette(framebuffer);
+        }
+    }
+
+    fn apply_vignette(&self, framebuffer: &mut Framebuffer) {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let half_diagonal = 0.5 * (width as f32).hypot(height as f32);
+        for y in 0..height {
+            for x in 0..width {
+                let dx = x as f32 + 0.5 - 0.5 * width as f32;
+                let dy = y as f32 + 0.5 - 0.5 * height as f32;
+                let r = dx.hypot(dy) / half_diagonal;
+                let factor = (1.0 - self.vignette * r * r).max(0.0);
+                let index = framebuffer.index(x, y);
+                let pixel = &mut framebuffer.pixels[index];
+                pixel.red *= factor;
+                pixel.green *= factor;
+                pixel.blue *= factor;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot(width: i32, height: i32, brightness: f32) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(width, height);
+        for pixel in framebuffer.pixels.iter_mut() {
+            *pixel = Color::new(0.2, 0.2, 0.2, 1.0);
+        }
+        let center = framebuffer.index(width / 2, height / 2);
+        framebuffer.pixels[center] = Color::new(brightness, brightness, brightness, 1.0);
+        framebuffer
+    }
+
+    #[test]
+    fn test_bloom_spreads_highlights() {
+        // Nothing above the threshold leaves the image as is
+        let dim = spot(32, 32, 0.3);
+        let mut bloomed = dim.clone();
+        Bloom::new().apply(&mut bloomed);
+        assert_eq!(bloomed.pixels, dim.pixels);
+
+        let bright = spot(32, 32, 100.0);
+        let mut bloomed = bright.clone();
+        Bloom::new().apply(&mut bloomed);
+        let near = bloomed.get(18, 16).red - bright.get(18, 16).red;
+        let far = bloomed.get(30, 16).red - bright.get(30, 16).red;
+        assert!(near > far && far > 0.0);
+    }
+
+    #[test]
+    fn test_glare_and_vignette() {
+        let hexagon = ApertureShape::Polygon {
+            blades: 6,
+            rotation: 0.0,
+        };
+        let pentagon = ApertureShape::Polygon {
+            blades: 5,
+            rotation: 0.0,
+        };
+        assert_eq!(Glare::from_aperture(&hexagon, 8.0, 0.1).unwrap().streaks, 6);
+        assert_eq!(
+            Glare::from_aperture(&pentagon, 8.0, 0.1).unwrap().streaks,
+            10
+        );
+        assert!(Glare::from_aperture(&ApertureShape::Circle, 8.0, 0.1).is_none());
+
+        // Four streaks along the axes light the row but not the diagonal
+        let glare = Glare {
+            streaks: 4,
+            rotation: 0.0,
+            length: 8.0,
+            threshold: 1.0,
+            intensity: 0.5,
+        };
+        let light = glare.streak_light(&spot(17, 17, 100.0));
+        assert!(light.get(12, 8).red > 0.0 && light.get(8, 4).red > 0.0);
+        assert_eq!(light.get(12, 12).red, 0.0);
+        let total: f32 = light.pixels.iter().map(|p| p.red).sum();
+        assert!((total - 0.5 * 99.8).abs() < 0.5);
+
+        let mut framebuffer = spot(16, 16, 0.2);
+        PostProcess {
+            vignette: 0.5,
+            ..PostProcess::new()
+        }
+        .apply(&mut framebuffer);
+        assert!(framebuffer.get(0, 0).red < 0.12);
+        assert!(framebuffer.get(8, 8).red > 0.19);
+    }
+}
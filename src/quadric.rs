@@ -0,0 +1,443 @@
+use glam::{Vec2, Vec3};
+
+use crate::{
+    interval::Interval,
+    math::math::{solve_quadratic, solve_quartic},
+    ray::{HitResult, Hittable, Ray, SurfaceAttributes},
+};
+
+// Cylinders, cones and tori are modelled around the +y axis, rotate them with an `Instance`.
+
+const PARALLEL_EPSILON: f32 = 0.0001;
+
+struct Candidate {
+    t: f32,
+    outward_normal: Vec3,
+    uv: Vec2,
+}
+
+fn keep_nearest(nearest: &mut Option<Candidate>, candidate: Candidate) {
+    let is_nearer = match nearest {
+        Some(current) => candidate.t < current.t,
+        None => true,
+    };
+    if is_nearer {
+        *nearest = Some(candidate);
+    }
+}
+
+fn candidate_to_hit_result(
+    ray: &Ray,
+    candidate: Candidate,
+    material_id: i32,
+    surface: SurfaceAttributes,
+) -> HitResult {
+    let mut hit_result: HitResult = HitResult {
+        location: ray.at(candidate.t),
+        normal: candidate.outward_normal,
+        t: candidate.t,
+        uv: candidate.uv,
+        front_face: None,
+        material_id,
+        surface,
+    };
+    hit_result.set_face_normal(ray, candidate.outward_normal);
+    hit_result
+}
+
+/// Angle around the y axis mapped to [0, 1], same convention as `sphere_uv`.
+fn azimuth_u(p: Vec3) -> f32 {
+    ((-p.z).atan2(p.x) + std::f32::consts::PI) / (2.0 * std::f32::consts::PI)
+}
+
+/// Flat cap at height `y` facing `outward_normal`, uv is (azimuth, distance from axis / radius).
+fn hit_cap(
+    origin: Vec3,
+    direction: Vec3,
+    y: f32,
+    radius: f32,
+    outward_normal: Vec3,
+    interval: &Interval,
+) -> Option<Candidate> {
+    if direction.y.abs() < PARALLEL_EPSILON {
+        return None;
+    }
+    let t = (y - origin.y) / direction.y;
+    if !interval.surrounds(t) {
+        return None;
+    }
+    let p = origin + direction * t;
+    let distance = Vec2::new(p.x, p.z).length();
+    if distance > radius {
+        return None;
+    }
+
+    Some(Candidate {
+        t,
+        outward_normal,
+        uv: Vec2::new(azimuth_u(p), distance / radius),
+    })
+}
+
+/// Cylinder standing on `center` with its axis along +y.
+#[derive(Clone)]
+pub struct Cylinder {
+    pub center: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub capped: bool,
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl Hittable for Cylinder {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let mut nearest: Option<Candidate> = None;
+
+        let (roots, num_roots) = solve_quadratic([
+            (o.x * o.x + o.z * o.z - self.radius * self.radius) as f64,
+            (2.0 * (o.x * d.x + o.z * d.z)) as f64,
+            (d.x * d.x + d.z * d.z) as f64,
+        ]);
+        for root in roots.iter().take(num_roots) {
+            let t = *root as f32;
+            if !interval.surrounds(t) {
+                continue;
+            }
+            let p = o + d * t;
+            if p.y < 0.0 || p.y > self.height {
+                continue;
+            }
+            keep_nearest(
+                &mut nearest,
+                Candidate {
+                    t,
+                    outward_normal: Vec3::new(p.x, 0.0, p.z) / self.radius,
+                    uv: Vec2::new(azimuth_u(p), p.y / self.height),
+                },
+            );
+        }
+
+        if self.capped {
+            let caps = [(0.0, -Vec3::Y), (self.height, Vec3::Y)];
+            for (y, outward_normal) in caps {
+                if let Some(candidate) = hit_cap(o, d, y, self.radius, outward_normal, &interval) {
+                    keep_nearest(&mut nearest, candidate);
+                }
+            }
+        }
+
+        nearest.map(|candidate| {
+            candidate_to_hit_result(ray, candidate, self.material_id, self.surface)
+        })
+    }
+}
+
+/// Cone with its base disk on `center` and apex `height` above it along +y.
+#[derive(Clone)]
+pub struct Cone {
+    pub center: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub capped: bool,
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl Hittable for Cone {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let k = self.radius / self.height;
+        let k_sq = k * k;
+        let apex_dist = self.height - o.y;
+        let mut nearest: Option<Candidate> = None;
+
+        // x^2 + z^2 = k^2 (height - y)^2
+        let (roots, num_roots) = solve_quadratic([
+            (o.x * o.x + o.z * o.z - k_sq * apex_dist * apex_dist) as f64,
+            (2.0 * (o.x * d.x + o.z * d.z + k_sq * apex_dist * d.y)) as f64,
+            (d.x * d.x + d.z * d.z - k_sq * d.y * d.y) as f64,
+        ]);
+        for root in roots.iter().take(num_roots) {
+            let t = *root as f32;
+            if !interval.surrounds(t) {
+                continue;
+            }
+            let p = o + d * t;
+            if p.y < 0.0 || p.y > self.height {
+                continue;
+            }
+            let outward_normal = Vec3::new(p.x, k_sq * (self.height - p.y), p.z)
+                .try_normalize()
+                .unwrap_or(Vec3::Y);
+            keep_nearest(
+                &mut nearest,
+                Candidate {
+                    t,
+                    outward_normal,
+                    uv: Vec2::new(azimuth_u(p), p.y / self.height),
+                },
+            );
+        }
+
+        if self.capped {
+            if let Some(candidate) = hit_cap(o, d, 0.0, self.radius, -Vec3::Y, &interval) {
+                keep_nearest(&mut nearest, candidate);
+            }
+        }
+
+        nearest.map(|candidate| {
+            candidate_to_hit_result(ray, candidate, self.material_id, self.surface)
+        })
+    }
+}
+
+/// Segment from `a` to `b` swept by a sphere of `radius`, uv is (azimuth around the segment, position along it).
+#[derive(Clone)]
+pub struct Capsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: f32,
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl Capsule {
+    fn surface_candidate(&self, ray: &Ray, t: f32) -> Candidate {
+        let p = ray.at(t);
+        let ba = self.b - self.a;
+        let length = ba.length();
+        let axis = ba / length;
+        let along = (p - self.a).dot(axis);
+        let closest = self.a + axis * along.clamp(0.0, length);
+        let outward_normal = (p - closest) / self.radius;
+
+        let (tangent, bitangent) = axis.any_orthonormal_pair();
+        let phi = outward_normal
+            .dot(bitangent)
+            .atan2(outward_normal.dot(tangent))
+            + std::f32::consts::PI;
+
+        Candidate {
+            t,
+            outward_normal,
+            uv: Vec2::new(
+                phi / (2.0 * std::f32::consts::PI),
+                (along + self.radius) / (length + 2.0 * self.radius),
+            ),
+        }
+    }
+}
+
+impl Hittable for Capsule {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let d = ray.direction;
+        let ba = self.b - self.a;
+        let oa = ray.origin - self.a;
+        let baba = ba.dot(ba);
+        let bard = ba.dot(d);
+        let baoa = ba.dot(oa);
+        let r_sq = self.radius * self.radius;
+        let mut nearest: Option<Candidate> = None;
+
+        // Infinite cylinder around the segment, kept only between the end points
+        let (roots, num_roots) = solve_quadratic([
+            (baba * oa.dot(oa) - baoa * baoa - r_sq * baba) as f64,
+            (2.0 * (baba * d.dot(oa) - baoa * bard)) as f64,
+            (baba - bard * bard) as f64,
+        ]);
+        for root in roots.iter().take(num_roots) {
+            let t = *root as f32;
+            let y = baoa + t * bard;
+            if interval.surrounds(t) && y > 0.0 && y < baba {
+                keep_nearest(&mut nearest, self.surface_candidate(ray, t));
+            }
+        }
+
+        // Hemispherical caps, each only valid beyond its end of the segment
+        for (cap_center, is_a) in [(self.a, true), (self.b, false)] {
+            let oc = ray.origin - cap_center;
+            let (roots, num_roots) = solve_quadratic([
+                (oc.dot(oc) - r_sq) as f64,
+                (2.0 * d.dot(oc)) as f64,
+                d.dot(d) as f64,
+            ]);
+            for root in roots.iter().take(num_roots) {
+                let t = *root as f32;
+                let y = baoa + t * bard;
+                let on_cap = if is_a { y <= 0.0 } else { y >= baba };
+                if interval.surrounds(t) && on_cap {
+                    keep_nearest(&mut nearest, self.surface_candidate(ray, t));
+                }
+            }
+        }
+
+        nearest.map(|candidate| {
+            candidate_to_hit_result(ray, candidate, self.material_id, self.surface)
+        })
+    }
+}
+
+/// Torus around the +y axis through `center`, uv is (angle around y, angle around the tube).
+#[derive(Clone)]
+pub struct Torus {
+    pub center: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl Hittable for Torus {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let major_sq = (self.major_radius * self.major_radius) as f64;
+        let minor_sq = (self.minor_radius * self.minor_radius) as f64;
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+
+        // Start the quartic from the bounding sphere, large ray offsets make it badly conditioned
+        let bound_radius = self.major_radius + self.minor_radius;
+        let (bound_roots, num_bound_roots) = solve_quadratic([
+            (o.dot(o) - bound_radius * bound_radius) as f64,
+            (2.0 * o.dot(d)) as f64,
+            d.dot(d) as f64,
+        ]);
+        if num_bound_roots < 2 {
+            return None;
+        }
+        let t_shift = bound_roots[0].min(bound_roots[1]);
+        if bound_roots[0].max(bound_roots[1]) < interval.min as f64 || t_shift > interval.max as f64
+        {
+            return None;
+        }
+
+        let (ox, oy, oz) = (
+            o.x as f64 + t_shift * d.x as f64,
+            o.y as f64 + t_shift * d.y as f64,
+            o.z as f64 + t_shift * d.z as f64,
+        );
+        let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2)
+        let dd = dx * dx + dy * dy + dz * dz;
+        let beta = 2.0 * (ox * dx + oy * dy + oz * dz);
+        let gamma = ox * ox + oy * oy + oz * oz + major_sq - minor_sq;
+        let (roots, num_roots) = solve_quartic([
+            gamma * gamma - 4.0 * major_sq * (ox * ox + oz * oz),
+            2.0 * beta * gamma - 8.0 * major_sq * (ox * dx + oz * dz),
+            beta * beta + 2.0 * dd * gamma - 4.0 * major_sq * (dx * dx + dz * dz),
+            2.0 * dd * beta,
+            dd * dd,
+        ]);
+
+        let mut nearest: Option<Candidate> = None;
+        for root in roots.iter().take(num_roots) {
+            let t = (root + t_shift) as f32;
+            if !interval.surrounds(t) {
+                continue;
+            }
+            let p = o + d * t;
+            let radial = Vec2::new(p.x, p.z);
+            let radial_length = radial.length();
+            let ring_point = Vec3::new(p.x, 0.0, p.z) * (self.major_radius / radial_length);
+            let outward_normal = (p - ring_point).normalize();
+            let theta = p.y.atan2(radial_length - self.major_radius) + std::f32::consts::PI;
+
+            keep_nearest(
+                &mut nearest,
+                Candidate {
+                    t,
+                    outward_normal,
+                    uv: Vec2::new(azimuth_u(p), theta / (2.0 * std::f32::consts::PI)),
+                },
+            );
+        }
+
+        nearest.map(|candidate| {
+            candidate_to_hit_result(ray, candidate, self.material_id, self.surface)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve_quartic_four_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let (roots, num_roots) = solve_quartic([24.0, -50.0, 35.0, -10.0, 1.0]);
+        assert_eq!(num_roots, 4);
+        let mut sorted = roots;
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (root, expected) in sorted.iter().zip([1.0, 2.0, 3.0, 4.0]) {
+            assert!((root - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_torus_hit_outer_and_hole() {
+        let torus = Torus {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            major_radius: 2.0,
+            minor_radius: 0.5,
+            material_id: 0,
+            surface: SurfaceAttributes::default(),
+        };
+
+        let ray = Ray::new(Vec3::new(-100.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit_result = torus
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        assert!((hit_result.t - 97.5).abs() < 1e-3);
+        assert!((hit_result.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-3);
+
+        let hole_ray = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(torus
+            .hit(&hole_ray, Interval::new(0.0001, f32::INFINITY))
+            .is_none());
+    }
+
+    #[test]
+    fn test_cylinder_caps() {
+        let mut cylinder = Cylinder {
+            center: Vec3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            height: 2.0,
+            capped: true,
+            material_id: 0,
+            surface: SurfaceAttributes::default(),
+        };
+        let ray = Ray::new(Vec3::new(0.2, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let hit_result = cylinder
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        assert!((hit_result.t - 3.0).abs() < 1e-5);
+        assert_eq!(hit_result.front_face, Some(true));
+
+        cylinder.capped = false;
+        assert!(cylinder
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .is_none());
+    }
+}
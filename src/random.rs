@@ -7,8 +7,6 @@ use rand::{
     Rng,
 };
 
-use crate::math::math::unit_vector;
-
 pub fn rand<T>() -> T
 where
     Standard: Distribution<T>,
@@ -39,43 +37,3 @@ pub fn rand_vec3_range(min: f32, max: f32) -> Vec3 {
         rand_range(min..max),
     )
 }
-
-pub fn rand_sphere_vec3() -> Vec3 {
-    while true {
-        let p = rand_vec3_range(-1.0, 1.0);
-        if (p.length_squared() < 1.0) {
-            return p;
-        }
-    }
-
-    panic!();
-    return Vec3::new(0.0, 0.0, 0.0);
-}
-
-pub fn rand_disc_vec2() -> Vec2 {
-    while true {
-        let p = Vec2::new(rand_range(-1.0..1.0), rand_range(-1.0..1.0));
-        if (p.length_squared() < 1.0) {
-            return p;
-        }
-    }
-
-    panic!();
-    return Vec2::new(0.0, 0.0);
-}
-
-pub fn rand_unit_vector() -> Vec3 {
-    unit_vector(rand_sphere_vec3())
-}
-
-pub fn rand_on_hemisphere(normal: Vec3) -> Vec3 {
-    let on_unit_sphere = rand_unit_vector();
-
-    let hemisphere_unit_vector = if (on_unit_sphere.dot(normal) > 0.0) {
-        on_unit_sphere
-    } else {
-        -on_unit_sphere
-    };
-
-    hemisphere_unit_vector
-}
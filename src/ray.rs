@@ -1,14 +1,22 @@
 use std::{default, f32::INFINITY, mem::Discriminant, ops::DerefMut};
 
 use crate::interval::Interval;
-use glam::{Vec3, Vec4};
+use glam::{Vec2, Vec3, Vec4};
 
-use crate::color::Color;
+use crate::{
+    color::Color,
+    color_space::ColorSpace,
+    light::{Light, LightList},
+    medium::PhaseFunction,
+    sky::PreethamSky,
+    spectrum::Dispersion,
+};
 
 #[derive(Default)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub time: f32, // Seconds after the shutter opened, where moving instances are
 }
 
 impl Ray {
@@ -16,6 +24,7 @@ impl Ray {
         Self {
             origin: o,
             direction: dir.normalize(),
+            time: 0.0,
         }
     }
 
@@ -29,6 +38,8 @@ pub struct SurfaceAttributes {
     pub albedo: Color,
     pub emissive: Color,
     pub ir: f32,
+    pub phase: PhaseFunction,   // Only used by participating media
+    pub dispersion: Dispersion, // Wavelength dependent ir of dielectrics, only in spectral mode
 }
 
 #[derive(Clone, Copy, Default)]
@@ -36,6 +47,7 @@ pub struct HitResult {
     pub location: Vec3,
     pub normal: Vec3,
     pub t: f32,
+    pub uv: Vec2,
     pub front_face: Option<bool>,
     pub material_id: i32,
     pub surface: SurfaceAttributes,
@@ -52,9 +64,82 @@ impl HitResult {
     }
 }
 
+/// Part of a ray inside a closed object. `None` means the span starts or ends outside the queried interval.
+#[derive(Clone, Copy, Default)]
+pub struct HitSpan {
+    pub enter: Option<HitResult>,
+    pub exit: Option<HitResult>,
+}
+
+impl HitSpan {
+    pub fn enter_t(&self) -> f32 {
+        self.enter
+            .map_or(f32::NEG_INFINITY, |hit_result| hit_result.t)
+    }
+
+    pub fn exit_t(&self) -> f32 {
+        self.exit.map_or(f32::INFINITY, |hit_result| hit_result.t)
+    }
+}
+
+const HIT_SPAN_EPSILON: f32 = 0.0001;
+const HIT_SPAN_MAX_HITS: usize = 32;
+
 pub trait Hittable {
     fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult>;
     fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send>;
+
+    /// All entry/exit spans of the ray through the object, sorted by t.
+    /// The default walks successive `hit` calls and uses `front_face` to tell entries from exits,
+    /// which works for any closed object.
+    fn hit_spans(&self, ray: &Ray, interval: Interval) -> Vec<HitSpan> {
+        let mut spans: Vec<HitSpan> = Vec::new();
+        let mut enter: Option<HitResult> = None;
+        let mut inside = false;
+        let mut t_min = interval.min;
+
+        for _i in 0..HIT_SPAN_MAX_HITS {
+            let Some(hit_result) = self.hit(
+                ray,
+                Interval {
+                    min: t_min,
+                    max: interval.max,
+                },
+            ) else {
+                break;
+            };
+
+            if hit_result.front_face.unwrap_or(true) {
+                enter = Some(hit_result);
+                inside = true;
+            } else {
+                // An exit without a previous entry means the ray started inside
+                spans.push(HitSpan {
+                    enter: if inside { enter } else { None },
+                    exit: Some(hit_result),
+                });
+                enter = None;
+                inside = false;
+            }
+            t_min = hit_result.t + HIT_SPAN_EPSILON;
+        }
+
+        if inside {
+            spans.push(HitSpan { enter, exit: None });
+        }
+
+        spans
+    }
+
+    /// Fraction of light passing along the ray within the interval.
+    /// Surfaces are opaque, participating media override this with their attenuation.
+    fn transmittance(&self, ray: &Ray, interval: Interval) -> f32 {
+        if self.hit(ray, interval).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
 }
 
 impl Clone for Box<dyn Hittable> {
@@ -63,6 +148,16 @@ impl Clone for Box<dyn Hittable> {
     }
 }
 
+/// Maps a point on the unit sphere to (u, v) in [0, 1], u around the y axis and v from -y to +y.
+pub fn sphere_uv(p: Vec3) -> Vec2 {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + std::f32::consts::PI;
+    Vec2::new(
+        phi / (2.0 * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    )
+}
+
 #[derive(Clone)]
 pub struct Sphere {
     pub center: Vec3,
@@ -99,10 +194,12 @@ impl Hittable for Sphere {
             }
         }
 
+        let outward_normal: Vec3 = (ray.at(t) - self.center) / self.radius;
         let mut hit_result: HitResult = HitResult {
             location: ray.at(t),
-            normal: (ray.at(t) - self.center) / self.radius,
+            normal: outward_normal,
             t: t,
+            uv: sphere_uv(outward_normal),
             front_face: None,
             material_id: self.material_id,
             surface: self.surface,
@@ -145,6 +242,7 @@ impl Hittable for Plane {
             location: ray.at(t),
             normal: self.normal,
             t: t,
+            uv: Vec2::ZERO,
             front_face: None,
             material_id: self.material_id,
             surface: self.surface,
@@ -157,6 +255,9 @@ impl Hittable for Plane {
 
 pub struct HittableList {
     pub list: Vec<Box<dyn Hittable + Sync + Send>>,
+    pub lights: LightList,
+    pub sky: Option<PreethamSky>, // Replaces the gradient background, see set_sky
+    pub working_space: ColorSpace, // Space the scene's linear sRGB colors are rendered in
 }
 
 impl Clone for HittableList {
@@ -166,28 +267,59 @@ impl Clone for HittableList {
             let copy_hittable = li.clone_dyn();
             copy_list.push(copy_hittable);
         }
-        Self { list: copy_list }
+        Self {
+            list: copy_list,
+            lights: self.lights.clone(),
+            sky: self.sky.clone(),
+            working_space: self.working_space,
+        }
     }
 }
 
 impl HittableList {
     pub fn new() -> Self {
-        Self { list: Vec::new() }
+        Self {
+            list: Vec::new(),
+            lights: LightList::new(),
+            sky: None,
+            working_space: ColorSpace::LinearSrgb,
+        }
     }
 
     pub fn add_hittable(&mut self, hittable: Box<dyn Hittable + Sync + Send>) {
         self.list.push(hittable);
     }
 
+    pub fn add_light(&mut self, light: Box<dyn Light + Sync + Send>) {
+        self.lights.add_light(light);
+    }
+
+    /// Uses the sky as background and adds its sun and dome as lights, so diffuse surfaces sample
+    /// them directly.
+    pub fn set_sky(&mut self, sky: PreethamSky) {
+        self.add_light(Box::new(sky.sun_light()));
+        self.add_light(Box::new(sky.sky_light()));
+        self.sky = Some(sky);
+    }
+
     pub fn clear(&mut self) {
         self.list.clear();
+        self.lights.clear();
+        self.sky = None;
     }
 
     pub fn hit_all(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        self.hit_all_indexed(ray, interval)
+            .map(|(hit_result, _index)| hit_result)
+    }
+
+    /// Like `hit_all`, also returns the index in `list` of the object that was hit.
+    pub fn hit_all_indexed(&self, ray: &Ray, interval: Interval) -> Option<(HitResult, usize)> {
         let mut hit = false;
         let mut closest_so_far = interval.max;
         let mut hit_result = HitResult::default();
-        for object in self.list.iter() {
+        let mut hit_index: usize = 0;
+        for (index, object) in self.list.iter().enumerate() {
             if let Some(temp_hit_result) = object.hit(
                 ray,
                 Interval {
@@ -198,6 +330,7 @@ impl HittableList {
                 hit = true;
                 closest_so_far = temp_hit_result.t;
                 hit_result = temp_hit_result;
+                hit_index = index;
             }
         }
 
@@ -205,12 +338,30 @@ impl HittableList {
             return None;
         }
 
-        return Some(hit_result);
+        return Some((hit_result, hit_index));
+    }
+
+    pub fn transmittance(&self, ray: &Ray, interval: Interval) -> f32 {
+        let mut transmittance = 1.0;
+        for object in self.list.iter() {
+            transmittance *= object.transmittance(ray, Interval::new(interval.min, interval.max));
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+        }
+
+        transmittance
     }
 
     pub fn merge(&mut self, other: Self) {
         for other_hittable in other.list {
             self.add_hittable(other_hittable);
         }
+        for other_light in other.lights.list {
+            self.add_light(other_light);
+        }
+        if self.sky.is_none() {
+            self.sky = other.sky;
+        }
     }
 }
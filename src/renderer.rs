@@ -1,5 +1,6 @@
 use crate::{renderer::thread::Builder, ringbuffer::RingBuffer};
 use futures::{future::join_all, join, poll, stream, FutureExt, SinkExt, StreamExt};
+use glam::Vec2;
 use std::{
     borrow::{Borrow, BorrowMut},
     cell::RefCell,
@@ -20,29 +21,63 @@ use tokio::task::{self, yield_now};
 use palette::{Clamp, Srgba};
 
 use crate::{
+    aov::AovBuffers,
     camera::Camera,
     color::{color::color_to_u8_srgba, Color},
+    color_space::{ColorSpace, OutputTransform},
+    denoise::Denoiser,
+    framebuffer::{Film, Framebuffer},
+    grading::ColorGrade,
     interval::Interval,
-    material::scatter,
+    light::direct_lighting,
+    material::{has_diffuse_lobe, scatter, MATERIAL_DIELECTRIC},
+    png::write_png,
+    post_process::PostProcess,
     progress_bar::ProgressBar,
-    ray::{Hittable, HittableList, Ray},
+    ray::{HitResult, HittableList, Ray},
+    sampler::{begin_pixel_sample, end_pixel_sample, next_1d},
+    spectrum::{SampledSpectrum, SampledWavelengths},
 };
 
+/// Render options that are not part of the camera.
+#[derive(Clone, Default)]
+pub struct RenderConfig {
+    pub write_aovs: bool, // Also save first hit buffers next to the render, see AovBuffers::save
+    pub denoiser: Option<Denoiser>, // Filters the linear render guided by the AOVs
+    pub output: OutputTransform, // Encoding of the saved image, also named in its header
+    pub post_process: PostProcess, // Bloom, glare and vignette on the exposed linear image
+    pub grade: ColorGrade, // Lift, gamma, gain, saturation and LUT on the encoded image
+}
+
 pub fn render(
-    world: &mut HittableList,
+    world: &HittableList,
     camera: &mut Camera,
+    config: &RenderConfig,
     render_file_path: &str,
 ) -> Result<File, io::Error> {
-    camera.initialize();
+    let (framebuffer, aovs) = render_framebuffer(world, camera, config);
+    let render_file = save_framebuffer(&framebuffer, world, config, render_file_path)?;
 
-    let (image_width, image_height) = camera.get_image_xy();
+    if config.write_aovs {
+        if let Some(aovs) = &aovs {
+            aovs.save(render_file_path)?;
+        }
+    }
 
-    let time_start = SystemTime::now();
+    Ok(render_file)
+}
 
-    let mut image_ppm: String = String::new();
-    image_ppm += &format!("P3\n{} {}\n255\n", image_width, image_height).to_string();
+/// Linear image after the denoiser, exposure and post processing, with the AOVs when `config` needs them.
+pub fn render_framebuffer(
+    world: &HittableList,
+    camera: &mut Camera,
+    config: &RenderConfig,
+) -> (Framebuffer, Option<AovBuffers>) {
+    camera.initialize();
+
+    let time_start = SystemTime::now();
 
-    render_inner(world, camera, &mut image_ppm);
+    let mut framebuffer: Framebuffer = render_inner(world, camera);
     println!("Render finished!");
 
     let time_now = SystemTime::now();
@@ -51,6 +86,75 @@ pub fn render(
         .expect("Time went backwards");
     println!("Render took: {:?} seconds", since_the_epoch.as_secs_f32());
 
+    let aovs: Option<AovBuffers> = if config.write_aovs || config.denoiser.is_some() {
+        println!("Rendering AOVs...");
+        Some(AovBuffers::render(world, camera))
+    } else {
+        None
+    };
+
+    if let (Some(denoiser), Some(aovs)) = (&config.denoiser, &aovs) {
+        println!("Denoising...");
+        denoiser.denoise(&mut framebuffer, aovs);
+    }
+
+    camera.exposure.apply(&mut framebuffer);
+    config.post_process.apply(&mut framebuffer);
+
+    (framebuffer, aovs)
+}
+
+/// Encodes with the output transform of `config`, 16 bit for PQ. A path ending in `.png` gets
+/// a PNG tagged with the color space, anything else a PPM whose `# Color space:` comment is
+/// only for people reading the file.
+pub fn save_framebuffer(
+    framebuffer: &Framebuffer,
+    world: &HittableList,
+    config: &RenderConfig,
+    render_file_path: &str,
+) -> Result<File, io::Error> {
+    let (image_width, image_height) = (framebuffer.width, framebuffer.height);
+    let max_value = config.output.max_code_value();
+    if render_file_path.to_lowercase().ends_with(".png") {
+        let pixels: Vec<[u16; 3]> = framebuffer
+            .pixels
+            .iter()
+            .map(|pixel| quantize_color(*pixel, config.output, &config.grade, world.working_space))
+            .collect();
+        let bit_depth = if max_value > 255 { 16 } else { 8 };
+
+        println!("Saving to file {}...", render_file_path);
+        let mut render_file = File::create(render_file_path)?;
+        write_png(
+            &mut render_file,
+            image_width as usize,
+            image_height as usize,
+            &pixels,
+            bit_depth,
+            config.output.cicp(),
+        )?;
+        return Ok(render_file);
+    }
+
+    let mut image_ppm: String = String::new();
+    image_ppm += &format!(
+        "P3\n# Color space: {}\n{} {}\n{}\n",
+        config.output.name(),
+        image_width,
+        image_height,
+        max_value
+    )
+    .to_string();
+    for texel_color in framebuffer.pixels.iter() {
+        write_color(
+            &mut image_ppm,
+            *texel_color,
+            config.output,
+            &config.grade,
+            world.working_space,
+        );
+    }
+
     println!("Saving to file {}...", render_file_path);
     let mut render_file = File::create(render_file_path)?;
     render_file.write_all(image_ppm.as_bytes()).unwrap();
@@ -58,30 +162,104 @@ pub fn render(
     Ok(render_file)
 }
 
-fn render_inner_thread(world: &HittableList, camera: &Camera, x: i32, y: i32) -> Color {
-    let mut sum_texel_color: Color = Color::new(0.0, 0.0, 0.0, 1.0);
-    for _aa in 0..camera.samples_per_pixel {
-        let ray: Ray = camera.get_ray(x, y);
+/// 8 bit RGB pixels with the output transform of `config`, for video encoders.
+pub fn encode_framebuffer(
+    framebuffer: &Framebuffer,
+    world: &HittableList,
+    config: &RenderConfig,
+) -> Vec<[u8; 3]> {
+    framebuffer
+        .pixels
+        .iter()
+        .map(|pixel| encode_color(*pixel, config.output, &config.grade, world.working_space))
+        .collect()
+}
 
-        let texel_color: Color = ray_color(&ray, camera.max_ray_per_pixel, &world);
-        sum_texel_color += texel_color;
+/// Mean of a pixel's samples and the variance of that mean's luminance.
+#[derive(Clone, Copy)]
+pub struct PixelEstimate {
+    pub color: Color,
+    pub variance: f32,
+}
+
+impl PixelEstimate {
+    fn from_sums(
+        sum_color: Color,
+        sum_sq_luminance: f32,
+        num_samples: i32,
+        color_space: ColorSpace,
+    ) -> Self {
+        let n = num_samples.max(1) as f32;
+        let color = sum_color / n;
+        let variance = if num_samples > 1 {
+            let mean_luminance = color_space.luminance(color);
+            let sample_variance =
+                (sum_sq_luminance / n - mean_luminance * mean_luminance).max(0.0) * n / (n - 1.0);
+            sample_variance / n
+        } else {
+            0.0
+        };
+
+        Self { color, variance }
     }
+}
 
-    sum_texel_color
+/// Traces the samples of one pixel. Each sample is also handed to `splat` with its offset
+/// from the pixel center, for the reconstruction filter.
+fn render_inner_thread(
+    world: &HittableList,
+    camera: &Camera,
+    x: i32,
+    y: i32,
+    mut splat: impl FnMut(Vec2, Color),
+) -> PixelEstimate {
+    let mut sum_texel_color: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+    let mut sum_sq_luminance: f32 = 0.0;
+    for aa in 0..camera.samples_per_pixel {
+        begin_pixel_sample(&camera.sampler, x, y, aa as u32);
+        let offset: Vec2 = if camera.samples_per_pixel > 1 {
+            camera.pixel_sample_square()
+        } else {
+            Vec2::ZERO
+        };
+        // Rays the lens barrel blocks count as black
+        let texel_color: Color = match camera.get_ray_offset(x, y, offset) {
+            Some(camera_ray) if camera.spectral => {
+                spectral_ray_color(&camera_ray.ray, camera.max_ray_per_pixel, &world)
+                    * camera_ray.weight
+            }
+            Some(camera_ray) => {
+                ray_color(&camera_ray.ray, camera.max_ray_per_pixel, &world) * camera_ray.weight
+            }
+            None => Color::new(0.0, 0.0, 0.0, 1.0),
+        };
+        splat(offset, texel_color);
+        sum_texel_color += texel_color;
+        let texel_luminance = world.working_space.luminance(texel_color);
+        sum_sq_luminance += texel_luminance * texel_luminance;
+    }
+    end_pixel_sample();
+
+    PixelEstimate::from_sums(
+        sum_texel_color,
+        sum_sq_luminance,
+        camera.samples_per_pixel,
+        world.working_space,
+    )
 }
 
 fn render_inner_multithread_old(
     world: &HittableList,
     camera: &Camera,
-    image_string: &mut String,
+    film: &mut Film,
     progress_bar: &mut ProgressBar,
-) {
+) -> Vec<PixelEstimate> {
     let (image_width, image_height) = camera.get_image_xy();
     let total_ray_pixel_tasks: i32 = image_width * image_height;
 
     thread::scope(|s: &thread::Scope<'_, '_>| {
         let num_threads_to_spawn: i32 = total_ray_pixel_tasks;
-        let mut all_thread_handles: Vec<ScopedJoinHandle<Color>> =
+        let mut all_thread_handles: Vec<ScopedJoinHandle<(PixelEstimate, Vec<(Vec2, Color)>)>> =
             Vec::with_capacity(num_threads_to_spawn as usize);
 
         for i in 0..num_threads_to_spawn {
@@ -89,27 +267,37 @@ fn render_inner_multithread_old(
             let thread_x: i32 = thread_id % image_width;
             let thread_y = thread_id / image_width;
             let thread_handle = s.spawn(move || {
-                let texel_color = render_inner_thread(world, camera, thread_x, thread_y);
-                return texel_color;
+                let mut samples: Vec<(Vec2, Color)> = Vec::new();
+                let texel_color =
+                    render_inner_thread(world, camera, thread_x, thread_y, |offset, color| {
+                        samples.push((offset, color))
+                    });
+                return (texel_color, samples);
             });
             all_thread_handles.push(thread_handle);
         }
 
-        let mut thread_results: Vec<Color> = Vec::new();
-        for thread_handle in all_thread_handles {
-            let thread_result = thread_handle.join().unwrap();
+        let mut thread_results: Vec<PixelEstimate> = Vec::new();
+        for (i, thread_handle) in all_thread_handles.into_iter().enumerate() {
+            let (thread_result, samples) = thread_handle.join().unwrap();
             thread_results.push(thread_result);
-        }
-
-        for (i, result) in thread_results.iter().enumerate() {
-            write_color(image_string, *result / camera.samples_per_pixel as f32);
+            for (offset, color) in samples {
+                film.splat(
+                    i as i32 % image_width,
+                    i as i32 / image_width,
+                    offset,
+                    color,
+                );
+            }
 
             if i as i32 % progress_bar.calc_increment() as i32 == 0 {
                 progress_bar.print_progress_percent();
                 progress_bar.inc();
             }
         }
-    });
+
+        thread_results
+    })
 }
 
 type PixelFutureRingBuffer = RingBuffer<PixelFuture, 3>;
@@ -171,7 +359,13 @@ async fn consume_pixelfutures(
             count += 1;
 
             let mut image_string = image_string.lock().await;
-            write_color(&mut image_string, res);
+            write_color(
+                &mut image_string,
+                res,
+                OutputTransform::Srgb,
+                &ColorGrade::new(),
+                ColorSpace::LinearSrgb,
+            );
 
             let mut progress_bar = progress_bar.lock().await;
             if count as i32 % progress_bar.calc_increment() as i32 == 0 {
@@ -190,8 +384,10 @@ fn render_pixels(
     camera: Camera,
     job_count: usize,
     job_generator: Arc<AtomicUsize>,
-    output_arr: *mut Color,
-) {
+    output_arr: *mut PixelEstimate,
+) -> Film {
+    let (image_width, image_height) = camera.get_image_xy();
+    let mut film: Film = Film::new(image_width, image_height, camera.filter);
     const BATCH_SIZE: usize = 512;
     loop {
         let job_start = job_generator.fetch_add(BATCH_SIZE, std::sync::atomic::Ordering::Relaxed);
@@ -201,14 +397,17 @@ fn render_pixels(
             }
             let x = i as i32 % camera.image_width;
             let y = i as i32 / camera.image_width;
-            let color = render_inner_thread(&world, &camera, x, y);
+            let pixel_estimate = render_inner_thread(&world, &camera, x, y, |offset, color| {
+                film.splat(x, y, offset, color)
+            });
 
-            unsafe { output_arr.add(i).write(color) };
+            unsafe { output_arr.add(i).write(pixel_estimate) };
         }
         if job_start >= job_count {
             break;
         }
     }
+    film
 }
 
 async fn render_inner_multithread(
@@ -247,22 +446,19 @@ async fn render_inner_multithread(
     *image_string = arc_image_string.to_string();
     *progress_bar = arc_progressbar.clone();
 }
-struct SendWrap(*mut Color);
+struct SendWrap(*mut PixelEstimate);
 unsafe impl Send for SendWrap {}
 pub fn render_inner_new_multithread(
     world: &HittableList,
     camera: &Camera,
-    image_string: &mut String,
-) -> Vec<Color> {
+    film: &mut Film,
+) -> Vec<PixelEstimate> {
     let num_threads = (std::thread::available_parallelism().unwrap().get() - 1).max(1);
     let num_jobs = (camera.image_width * camera.image_height) as usize;
 
     let job_generator = Arc::new(AtomicUsize::new(0));
-    let mut write_buffer = Vec::<MaybeUninit<Color>>::with_capacity(num_jobs as usize);
-    let output_arr: *mut palette::Alpha<
-        palette::rgb::Rgb<palette::encoding::Linear<palette::encoding::Srgb>>,
-        f32,
-    > = write_buffer.as_mut_ptr().cast::<Color>();
+    let mut write_buffer = Vec::<MaybeUninit<PixelEstimate>>::with_capacity(num_jobs as usize);
+    let output_arr: *mut PixelEstimate = write_buffer.as_mut_ptr().cast::<PixelEstimate>();
     let world = Arc::new(world.clone());
     let mut jobbers = Vec::new();
     for i in 0..num_threads {
@@ -272,51 +468,44 @@ pub fn render_inner_new_multithread(
         let job_generator = job_generator.clone();
         jobbers.push(task::spawn_blocking(move || {
             let output_arr = output_arr;
-            render_pixels(world, camera, num_jobs, job_generator, output_arr.0);
+            render_pixels(world, camera, num_jobs, job_generator, output_arr.0)
         }));
     }
     println!("waiting..");
     for jobber in jobbers {
-        tokio::runtime::Handle::current().block_on(jobber).unwrap();
+        let thread_film = tokio::runtime::Handle::current().block_on(jobber).unwrap();
+        film.merge(&thread_film);
     }
     println!("done rendering");
     unsafe { write_buffer.set_len(num_jobs) };
-    let write_buffer =
-        unsafe { core::mem::transmute::<Vec<MaybeUninit<Color>>, Vec<Color>>(write_buffer) };
+    let write_buffer = unsafe {
+        core::mem::transmute::<Vec<MaybeUninit<PixelEstimate>>, Vec<PixelEstimate>>(write_buffer)
+    };
     write_buffer
 }
 
-pub fn render_inner(world: &HittableList, camera: &Camera, image_string: &mut String) {
+pub fn render_inner(world: &HittableList, camera: &Camera) -> Framebuffer {
     let (image_width, image_height) = camera.get_image_xy();
     let mut progress_bar: ProgressBar =
         ProgressBar::new((image_width * image_height) as f64, 20 as usize);
 
+    let mut film: Film = Film::new(image_width, image_height, camera.filter);
+    let mut render_results: Vec<PixelEstimate> = Vec::new();
     const MULTITHREAD_ENABLE: bool = true;
     if MULTITHREAD_ENABLE {
         const OLD_MULTITHREAD_CODE: bool = false;
         if OLD_MULTITHREAD_CODE {
-            render_inner_multithread_old(world, camera, image_string, &mut progress_bar);
+            render_results =
+                render_inner_multithread_old(world, camera, &mut film, &mut progress_bar);
         } else {
-            let render_results = render_inner_new_multithread(world, camera, image_string);
-
-            for res in render_results {
-                write_color(image_string, res / camera.samples_per_pixel as f32);
-            }
+            render_results = render_inner_new_multithread(world, camera, &mut film);
         }
     } else {
         for y in 0..image_height {
             for x in 0..image_width {
-                let mut sum_texel_color: Color = Color::new(0.0, 0.0, 0.0, 1.0);
-                for _aa in 0..camera.samples_per_pixel {
-                    let ray: Ray = camera.get_ray(x, y);
-
-                    let texel_color: Color = ray_color(&ray, camera.max_ray_per_pixel, &world);
-                    sum_texel_color += texel_color;
-                }
-                write_color(
-                    image_string,
-                    sum_texel_color / camera.samples_per_pixel as f32,
-                );
+                render_results.push(render_inner_thread(world, camera, x, y, |offset, color| {
+                    film.splat(x, y, offset, color)
+                }));
 
                 if (x + y * image_width) % progress_bar.calc_increment() as i32 == 0 {
                     progress_bar.print_progress_percent();
@@ -327,25 +516,51 @@ pub fn render_inner(world: &HittableList, camera: &Camera, image_string: &mut St
     }
 
     // assert!(progress_bar.is_finished());
+
+    let mut framebuffer: Framebuffer = Framebuffer::new(image_width, image_height);
+    framebuffer.color_space = world.working_space;
+    for (i, pixel_estimate) in render_results.iter().enumerate() {
+        framebuffer.pixels[i] = pixel_estimate.color;
+        framebuffer.variance[i] = pixel_estimate.variance;
+    }
+    film.resolve_into(&mut framebuffer);
+    framebuffer
 }
 
-fn write_color(accum_string_file: &mut String, texel_color: Color) {
-    let mut scaled_texel_color = Color {
-        color: palette::rgb::Rgb {
-            red: texel_color.red,
-            green: texel_color.green,
-            blue: texel_color.blue,
-            standard: std::marker::PhantomData,
-        },
-        alpha: 1.0,
-    };
+/// 8 bit RGB of a linear color, as written to images.
+pub fn encode_color(
+    color: Color,
+    output: OutputTransform,
+    grade: &ColorGrade,
+    working_space: ColorSpace,
+) -> [u8; 3] {
+    let [red, green, blue] = grade.apply(output.encode(color, working_space));
+    let gamma_corrected: Srgba = Srgba::new(red, green, blue, 1.0);
+    let color_u8 = color_to_u8_srgba(&gamma_corrected);
+    [color_u8[0], color_u8[1], color_u8[2]]
+}
 
-    let gamma_corrected: Srgba = scaled_texel_color.clamp().into();
-    let texel_color_u8 = color_to_u8_srgba(&gamma_corrected);
+/// Code values up to `output.max_code_value()` for image files, at 8 bits the same as `encode_color`.
+fn quantize_color(
+    color: Color,
+    output: OutputTransform,
+    grade: &ColorGrade,
+    working_space: ColorSpace,
+) -> [u16; 3] {
+    let max_value = output.max_code_value();
+    grade
+        .apply(output.encode(color, working_space))
+        .map(|value| ((value.clamp(0.0, 1.0) * (max_value as f32 + 1.0)) as u16).min(max_value))
+}
 
-    let ir = texel_color_u8[0];
-    let ig = texel_color_u8[1];
-    let ib = texel_color_u8[2];
+fn write_color(
+    accum_string_file: &mut String,
+    texel_color: Color,
+    output: OutputTransform,
+    grade: &ColorGrade,
+    working_space: ColorSpace,
+) {
+    let [ir, ig, ib] = quantize_color(texel_color, output, grade, working_space);
 
     *accum_string_file += &ir.to_string();
     *accum_string_file += &' '.to_string();
@@ -356,17 +571,24 @@ fn write_color(accum_string_file: &mut String, texel_color: Color) {
 }
 
 fn ray_color(ray: &Ray, depth: i32, world: &HittableList) -> Color {
+    trace_ray(ray, depth, world, true)
+}
+
+/// `include_sampled_lights` is false after a bounce that already sampled the lights directly,
+/// so light a ray escapes into (the sky and its sun) is not counted twice.
+fn trace_ray(ray: &Ray, depth: i32, world: &HittableList, include_sampled_lights: bool) -> Color {
     if depth <= 0 {
         return Color::new(0.0, 0.0, 0.0, 0.0);
     }
 
-    if let Some(hit_result) = world.hit_all(
+    if let Some(mut hit_result) = world.hit_all(
         ray,
         Interval {
             min: 0.0001,
             max: f32::INFINITY,
         },
     ) {
+        to_working_space(world, &mut hit_result);
         let mut scattererd: Ray = Ray::default();
         let mut diffuse: Color = Color::new(0.0, 0.0, 0.0, 1.0);
         let mut emissive: Color = Color::new(0.0, 0.0, 0.0, 1.0);
@@ -378,15 +600,112 @@ fn ray_color(ray: &Ray, depth: i32, world: &HittableList) -> Color {
             &mut emissive,
             &mut scattererd,
         ) {
-            return emissive + diffuse * ray_color(&scattererd, depth - 1, world);
+            scattererd.time = ray.time;
+            let direct = direct_lighting(world, ray, &hit_result);
+            let sampled_lights = has_diffuse_lobe(hit_result.material_id);
+            return emissive
+                + direct
+                + diffuse * trace_ray(&scattererd, depth - 1, world, !sampled_lights);
         }
         return diffuse + emissive;
     }
 
-    // BG
-    let unit_dir = ray.direction.normalize();
-    let a = (unit_dir.y + 1.0) * 0.5;
-    Color::new(1.0, 1.0, 1.0, 1.0) * (1.0 - a) + Color::new(0.5, 0.7, 1.0, 1.0) * a
+    background_color(ray, world, include_sampled_lights)
+}
+
+fn background_color(ray: &Ray, world: &HittableList, include_sampled_lights: bool) -> Color {
+    let color = if let Some(sky) = &world.sky {
+        if !include_sampled_lights {
+            // The sun and the dome were both sampled as lights
+            return Color::new(0.0, 0.0, 0.0, 1.0);
+        }
+        sky.radiance(ray.direction, true)
+    } else {
+        let unit_dir = ray.direction.normalize();
+        let a = (unit_dir.y + 1.0) * 0.5;
+        Color::new(1.0, 1.0, 1.0, 1.0) * (1.0 - a) + Color::new(0.5, 0.7, 1.0, 1.0) * a
+    };
+    world.working_space.convert_linear_srgb(color)
+}
+
+/// Scene colors are linear sRGB, the material math runs in the world's working space.
+fn to_working_space(world: &HittableList, hit_result: &mut HitResult) {
+    let surface = &mut hit_result.surface;
+    surface.albedo = world.working_space.convert_linear_srgb(surface.albedo);
+    surface.emissive = world.working_space.convert_linear_srgb(surface.emissive);
+}
+
+/// Traces a path carrying a hero wavelength and its rotations, converted back to RGB at the end.
+fn spectral_ray_color(ray: &Ray, depth: i32, world: &HittableList) -> Color {
+    let mut wavelengths = SampledWavelengths::sample_hero(next_1d());
+    let radiance = trace_ray_spectral(ray, depth, world, &mut wavelengths, true);
+    world
+        .working_space
+        .convert_linear_srgb(radiance.to_color(&wavelengths))
+}
+
+/// Spectral version of `trace_ray`, RGB albedos and emission are upsampled per wavelength.
+/// A dispersive dielectric refracts with the ir of the hero and drops the other wavelengths.
+fn trace_ray_spectral(
+    ray: &Ray,
+    depth: i32,
+    world: &HittableList,
+    wavelengths: &mut SampledWavelengths,
+    include_sampled_lights: bool,
+) -> SampledSpectrum {
+    if depth <= 0 {
+        return SampledSpectrum::new(0.0);
+    }
+
+    if let Some(mut hit_result) = world.hit_all(
+        ray,
+        Interval {
+            min: 0.0001,
+            max: f32::INFINITY,
+        },
+    ) {
+        if hit_result.material_id == MATERIAL_DIELECTRIC {
+            if let Some(ir) = hit_result.surface.dispersion.ior(wavelengths.hero()) {
+                wavelengths.terminate_secondary();
+                hit_result.surface.ir = ir;
+            }
+        }
+        to_working_space(world, &mut hit_result);
+
+        let mut scattererd: Ray = Ray::default();
+        let mut diffuse: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+        let mut emissive: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+        let scattered = scatter(
+            hit_result.material_id,
+            ray,
+            &hit_result,
+            &mut diffuse,
+            &mut emissive,
+            &mut scattererd,
+        );
+        scattererd.time = ray.time;
+        // Smits' upsampling expects linear sRGB
+        let upsample = |color: Color, wavelengths: &SampledWavelengths| {
+            SampledSpectrum::from_rgb(world.working_space.to_linear_srgb(color), wavelengths)
+        };
+        let emissive = upsample(emissive, wavelengths);
+        let diffuse = upsample(diffuse, wavelengths);
+        if scattered {
+            let direct = upsample(direct_lighting(world, ray, &hit_result), wavelengths);
+            let sampled_lights = has_diffuse_lobe(hit_result.material_id);
+            let incoming =
+                trace_ray_spectral(&scattererd, depth - 1, world, wavelengths, !sampled_lights);
+            return emissive + direct + diffuse * incoming;
+        }
+        return diffuse + emissive;
+    }
+
+    SampledSpectrum::from_rgb(
+        world
+            .working_space
+            .to_linear_srgb(background_color(ray, world, include_sampled_lights)),
+        wavelengths,
+    )
 }
 
 struct PixelFutureState {
@@ -454,15 +773,18 @@ impl PixelFuture {
 
             let mut sum_texel_color: Color = Color::new(0.0, 0.0, 0.0, 1.0);
             for _aa in 0..shared_state.camera.samples_per_pixel {
-                let ray: Ray = shared_state
+                let Some(camera_ray) = shared_state
                     .camera
-                    .get_ray(shared_state.pixel_x, shared_state.pixel_y);
+                    .get_ray(shared_state.pixel_x, shared_state.pixel_y)
+                else {
+                    continue;
+                };
                 let color: Color = ray_color(
-                    &ray,
+                    &camera_ray.ray,
                     shared_state.camera.max_ray_per_pixel,
                     shared_state.world.borrow(),
                 );
-                sum_texel_color += color;
+                sum_texel_color += color * camera_ray.weight;
             }
 
             // Signal that the timer has completed and wake up the last
@@ -487,6 +809,27 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_quantize_color() {
+        let grade = ColorGrade::new();
+        let space = ColorSpace::LinearSrgb;
+        let srgb = OutputTransform::Srgb;
+        for value in [0.0, 0.18, 0.5, 1.0, 2.0] {
+            let color = Color::new(value, value * 0.5, 0.0, 1.0);
+            let eight_bit = encode_color(color, srgb, &grade, space).map(u16::from);
+            assert_eq!(quantize_color(color, srgb, &grade, space), eight_bit);
+        }
+
+        // PQ keeps 16 bits and reaches full scale at 10000 nits
+        let pq = OutputTransform::Rec2020Pq {
+            paper_white_nits: 100.0,
+        };
+        let white = Color::new(100.0, 100.0, 100.0, 1.0);
+        assert_eq!(quantize_color(white, pq, &grade, space), [65535; 3]);
+        let dim = quantize_color(Color::new(0.01, 0.01, 0.01, 1.0), pq, &grade, space);
+        assert!(dim[0] > 255 && dim[0] < 65535);
+    }
+
     #[test]
     #[ignore]
     fn test_renderer_render() {
@@ -512,7 +855,12 @@ mod tests {
         let mut world = world0;
 
         let render_file_path = "../img/render.ppm";
-        let result = render(&mut world, &mut camera, render_file_path);
+        let result = render(
+            &mut world,
+            &mut camera,
+            &RenderConfig::default(),
+            render_file_path,
+        );
 
         // Ignore the file errors
         if result.is_err() {
@@ -0,0 +1,468 @@
+use std::{cell::RefCell, sync::Arc};
+
+use glam::Vec2;
+
+use crate::random::rand;
+
+/// Source of sample values for the camera, lens and BSDF sampling.
+/// Values are addressed by pixel, sample index and dimension, so a sampler can spread the
+/// samples of one pixel evenly over each dimension instead of drawing them independently.
+pub trait Sampler {
+    /// Value in [0, 1) for `dimension` of sample `index` in pixel (x, y).
+    fn sample_1d(&self, x: i32, y: i32, index: u32, dimension: u32) -> f32;
+
+    /// Point in [0, 1)^2 using `dimension` and `dimension + 1`.
+    fn sample_2d(&self, x: i32, y: i32, index: u32, dimension: u32) -> Vec2 {
+        Vec2::new(
+            self.sample_1d(x, y, index, dimension),
+            self.sample_1d(x, y, index, dimension + 1),
+        )
+    }
+}
+
+/// Independent uniform random numbers, what the renderer used before samplers.
+#[derive(Clone, Default)]
+pub struct IndependentSampler {}
+
+impl Sampler for IndependentSampler {
+    fn sample_1d(&self, _x: i32, _y: i32, _index: u32, _dimension: u32) -> f32 {
+        rand::<f32>()
+    }
+}
+
+/// Jittered strata, one per sample in 1D and a grid of about sqrt(n) by sqrt(n) in 2D.
+/// The strata are visited in a different shuffled order for every pixel and dimension.
+#[derive(Clone)]
+pub struct StratifiedSampler {
+    pub samples_per_pixel: u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn sample_1d(&self, x: i32, y: i32, index: u32, dimension: u32) -> f32 {
+        let n = self.samples_per_pixel.max(1);
+        let seed = hash(&[x as u32, y as u32, dimension]);
+        let stratum = permutation_element(index % n, n, seed);
+        let jitter = hash_to_unit(hash(&[x as u32, y as u32, index, dimension, 1]));
+        ((stratum as f32 + jitter) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn sample_2d(&self, x: i32, y: i32, index: u32, dimension: u32) -> Vec2 {
+        let n = self.samples_per_pixel.max(1);
+        let strata_x = (n as f32).sqrt().ceil() as u32;
+        let strata_y = n.div_ceil(strata_x);
+        let seed = hash(&[x as u32, y as u32, dimension]);
+        let stratum = permutation_element(index % n, strata_x * strata_y, seed);
+
+        let jitter_x = hash_to_unit(hash(&[x as u32, y as u32, index, dimension, 1]));
+        let jitter_y = hash_to_unit(hash(&[x as u32, y as u32, index, dimension, 2]));
+        Vec2::new(
+            ((stratum % strata_x) as f32 + jitter_x) / strata_x as f32,
+            ((stratum / strata_x) as f32 + jitter_y) / strata_y as f32,
+        )
+        .min(Vec2::splat(ONE_MINUS_EPSILON))
+    }
+}
+
+const HALTON_PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Halton sequence, the radical inverse of the sample index in the n-th prime base.
+/// Every pixel gets its own random rotation of the points. Dimensions past the table of
+/// primes fall back to independent random numbers.
+#[derive(Clone, Default)]
+pub struct HaltonSampler {}
+
+impl Sampler for HaltonSampler {
+    fn sample_1d(&self, x: i32, y: i32, index: u32, dimension: u32) -> f32 {
+        let Some(&base) = HALTON_PRIMES.get(dimension as usize) else {
+            return rand::<f32>();
+        };
+        let rotation = hash_to_unit(hash(&[x as u32, y as u32, dimension]));
+        let value = radical_inverse(base, index) + rotation;
+        (value - value.floor()).min(ONE_MINUS_EPSILON)
+    }
+}
+
+/// Owen scrambled Sobol points, the first two Sobol dimensions are used for each 2D sample
+/// and every dimension pair gets its own scramble and shuffled sample order.
+/// Best with a power of two samples per pixel.
+#[derive(Clone, Default)]
+pub struct SobolSampler {}
+
+impl Sampler for SobolSampler {
+    fn sample_1d(&self, x: i32, y: i32, index: u32, dimension: u32) -> f32 {
+        self.sample_2d(x, y, index, dimension).x
+    }
+
+    fn sample_2d(&self, x: i32, y: i32, index: u32, dimension: u32) -> Vec2 {
+        let seed = hash(&[x as u32, y as u32, dimension]);
+        let shuffled_index = nested_uniform_scramble(index, seed);
+        let sobol_x = nested_uniform_scramble(shuffled_index.reverse_bits(), hash(&[seed, 1]));
+        let sobol_y =
+            nested_uniform_scramble(sobol_second_dimension(shuffled_index), hash(&[seed, 2]));
+        Vec2::new(bits_to_unit(sobol_x), bits_to_unit(sobol_y))
+    }
+}
+
+/// Side of the blue noise tile, it repeats over the image.
+pub const BLUE_NOISE_SIZE: usize = 64;
+
+/// Sobol points shared by every pixel, shifted per pixel by a blue noise tile.
+/// The per pixel error then has a blue noise distribution over the screen, which looks
+/// much smoother than white noise at low sample counts.
+#[derive(Clone)]
+pub struct BlueNoiseSampler {
+    tile: Arc<Vec<f32>>, // Values in [0, 1), row major BLUE_NOISE_SIZE^2
+}
+
+impl Default for BlueNoiseSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlueNoiseSampler {
+    pub fn new() -> Self {
+        Self {
+            tile: Arc::new(void_and_cluster(BLUE_NOISE_SIZE, 1.5)),
+        }
+    }
+
+    fn noise(&self, x: i32, y: i32, dimension: u32) -> f32 {
+        // Offsetting the tile per dimension decorrelates the dimensions
+        let size = BLUE_NOISE_SIZE as i32;
+        let tx = (x + 17 * dimension as i32).rem_euclid(size);
+        let ty = (y + 41 * dimension as i32).rem_euclid(size);
+        self.tile[(tx + ty * size) as usize]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn sample_1d(&self, x: i32, y: i32, index: u32, dimension: u32) -> f32 {
+        self.sample_2d(x, y, index, dimension).x
+    }
+
+    fn sample_2d(&self, x: i32, y: i32, index: u32, dimension: u32) -> Vec2 {
+        let seed = hash(&[dimension]);
+        let shuffled_index = nested_uniform_scramble(index, seed);
+        let point = Vec2::new(
+            bits_to_unit(nested_uniform_scramble(
+                shuffled_index.reverse_bits(),
+                hash(&[seed, 1]),
+            )),
+            bits_to_unit(nested_uniform_scramble(
+                sobol_second_dimension(shuffled_index),
+                hash(&[seed, 2]),
+            )),
+        );
+        let shifted =
+            point + Vec2::new(self.noise(x, y, dimension), self.noise(x, y, dimension + 1));
+        (shifted - shifted.floor()).min(Vec2::splat(ONE_MINUS_EPSILON))
+    }
+}
+
+/// The pixel sample a thread is currently tracing.
+struct PixelSample {
+    sampler: Arc<dyn Sampler + Sync + Send>,
+    x: i32,
+    y: i32,
+    index: u32,
+    dimension: u32,
+}
+
+thread_local! {
+    static PIXEL_SAMPLE: RefCell<Option<PixelSample>> = const { RefCell::new(None) };
+}
+
+/// Makes `next_1d` and `next_2d` on this thread draw from `sampler` for sample `index` of
+/// pixel (x, y), starting at dimension 0.
+pub fn begin_pixel_sample(sampler: &Arc<dyn Sampler + Sync + Send>, x: i32, y: i32, index: u32) {
+    PIXEL_SAMPLE.with(|pixel_sample| {
+        *pixel_sample.borrow_mut() = Some(PixelSample {
+            sampler: sampler.clone(),
+            x,
+            y,
+            index,
+            dimension: 0,
+        });
+    });
+}
+
+pub fn end_pixel_sample() {
+    PIXEL_SAMPLE.with(|pixel_sample| *pixel_sample.borrow_mut() = None);
+}
+
+/// Next dimension of the current pixel sample, random outside of one.
+pub fn next_1d() -> f32 {
+    PIXEL_SAMPLE.with(|pixel_sample| match pixel_sample.borrow_mut().as_mut() {
+        Some(s) => {
+            s.dimension += 1;
+            s.sampler.sample_1d(s.x, s.y, s.index, s.dimension - 1)
+        }
+        None => rand::<f32>(),
+    })
+}
+
+/// Next two dimensions of the current pixel sample, random outside of one.
+pub fn next_2d() -> Vec2 {
+    PIXEL_SAMPLE.with(|pixel_sample| match pixel_sample.borrow_mut().as_mut() {
+        Some(s) => {
+            s.dimension += 2;
+            s.sampler.sample_2d(s.x, s.y, s.index, s.dimension - 2)
+        }
+        None => Vec2::new(rand::<f32>(), rand::<f32>()),
+    })
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+fn bits_to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+fn hash_to_unit(value: u32) -> f32 {
+    bits_to_unit(value)
+}
+
+/// Mixes the values into a well distributed 32 bit hash.
+fn hash(values: &[u32]) -> u32 {
+    let mut h: u64 = 0x9e37_79b9_7f4a_7c15;
+    for &value in values {
+        h ^= value as u64;
+        h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h ^= h >> 31;
+        h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 29;
+    }
+    (h >> 32) as u32
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed: f64 = 0.0;
+    while index > 0 {
+        let digit = index % base;
+        index /= base;
+        inv_base_n *= inv_base;
+        reversed += digit as f64 * inv_base_n;
+    }
+    reversed as f32
+}
+
+/// Second Sobol dimension as a 32 bit fraction, the first is the bit reversed index.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result: u32 = 0;
+    let mut direction: u32 = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Hash based Owen scrambling from Burley, "Practical Hash-based Owen Scrambling".
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// Element `i` of a random permutation of 0..n chosen by `seed`, from Kensler,
+/// "Correlated Multi-Jittered Sampling".
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n.max(1) - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+/// Blue noise tile from Ulichney's void and cluster method, each texel holds its rank
+/// divided by the texel count.
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let num_texels = size * size;
+
+    // Toroidal Gaussian energy of one point, indexed by the wrapped offset
+    let mut kernel: Vec<f32> = vec![0.0; num_texels];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = dx.min(size - dx) as f32;
+            let wy = dy.min(size - dy) as f32;
+            kernel[dx + dy * size] = (-(wx * wx + wy * wy) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+
+    let mut energy: Vec<f32> = vec![0.0; num_texels];
+    let mut points: Vec<bool> = vec![false; num_texels];
+    let update = |energy: &mut Vec<f32>, texel: usize, sign: f32| {
+        let (px, py) = (texel % size, texel / size);
+        for y in 0..size {
+            for x in 0..size {
+                let dx = (x + size - px) % size;
+                let dy = (y + size - py) % size;
+                energy[x + y * size] += sign * kernel[dx + dy * size];
+            }
+        }
+    };
+    let tightest_cluster = |energy: &Vec<f32>, points: &Vec<bool>| {
+        (0..num_texels)
+            .filter(|&i| points[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |energy: &Vec<f32>, points: &Vec<bool>| {
+        (0..num_texels)
+            .filter(|&i| !points[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // Initial pattern, a tenth of the texels picked at random
+    let num_initial = (num_texels / 10).max(1);
+    let mut placed = 0;
+    while placed < num_initial {
+        let texel = (hash(&[placed as u32, 0x5eed]) as usize) % num_texels;
+        let texel = (texel..texel + num_texels)
+            .map(|i| i % num_texels)
+            .find(|&i| !points[i])
+            .unwrap();
+        points[texel] = true;
+        update(&mut energy, texel, 1.0);
+        placed += 1;
+    }
+
+    // Move points from the tightest cluster into the largest void until it settles
+    loop {
+        let cluster = tightest_cluster(&energy, &points);
+        points[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = largest_void(&energy, &points);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks: Vec<usize> = vec![0; num_texels];
+
+    // Rank the initial points by removing the tightest clusters first
+    let initial_points = points.clone();
+    let initial_energy = energy.clone();
+    for rank in (0..num_initial).rev() {
+        let cluster = tightest_cluster(&energy, &points);
+        points[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+
+    // Then fill the largest voids until every texel has a rank
+    points = initial_points;
+    energy = initial_energy;
+    for rank in num_initial..num_texels {
+        let void = largest_void(&energy, &points);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .iter()
+        .map(|&rank| (rank as f32 + 0.5) / num_texels as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every cell of a 4x4 grid holds exactly one of 16 samples.
+    fn assert_stratified_4x4(sampler: &dyn Sampler, dimension: u32) {
+        let mut cells = [0; 16];
+        for index in 0..16 {
+            let u = sampler.sample_2d(3, 5, index, dimension);
+            assert!(u.x >= 0.0 && u.x < 1.0 && u.y >= 0.0 && u.y < 1.0);
+            cells[(u.x * 4.0) as usize + (u.y * 4.0) as usize * 4] += 1;
+        }
+        assert_eq!(cells, [1; 16]);
+    }
+
+    #[test]
+    fn test_samplers_stratify_pixel_samples() {
+        let stratified = StratifiedSampler {
+            samples_per_pixel: 16,
+        };
+        assert_stratified_4x4(&stratified, 0);
+        assert_stratified_4x4(&stratified, 6);
+        assert_stratified_4x4(&SobolSampler {}, 0);
+        assert_stratified_4x4(&SobolSampler {}, 4);
+
+        // Halton base 2 puts 8 samples in different eighths before the rotation
+        let halton = HaltonSampler {};
+        let mut eighths = [0; 8];
+        let rotation = halton.sample_1d(1, 2, 0, 0);
+        for index in 0..8 {
+            let value = halton.sample_1d(1, 2, index, 0) - rotation;
+            eighths[((value - value.floor()) * 8.0 + 0.5) as usize % 8] += 1;
+        }
+        assert_eq!(eighths, [1; 8]);
+    }
+
+    #[test]
+    fn test_blue_noise_tile_and_thread_sampler() {
+        let tile = void_and_cluster(16, 1.5);
+        let mut sorted = tile.clone();
+        sorted.sort_by(f32::total_cmp);
+        for (rank, value) in sorted.iter().enumerate() {
+            assert_eq!(*value, (rank as f32 + 0.5) / 256.0);
+        }
+
+        let sampler: Arc<dyn Sampler + Sync + Send> = Arc::new(SobolSampler {});
+        begin_pixel_sample(&sampler, 7, 9, 3);
+        let camera_sample = next_2d();
+        let lens_sample = next_2d();
+        let scatter_sample = next_1d();
+        end_pixel_sample();
+        assert_eq!(camera_sample, sampler.sample_2d(7, 9, 3, 0));
+        assert_eq!(lens_sample, sampler.sample_2d(7, 9, 3, 2));
+        assert_eq!(scatter_sample, sampler.sample_1d(7, 9, 3, 4));
+    }
+}
@@ -0,0 +1,308 @@
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+
+use crate::{
+    interval::Interval,
+    ray::{sphere_uv, HitResult, Hittable, Ray, SurfaceAttributes},
+};
+
+/// Signed distance function, negative inside the surface.
+pub trait Sdf {
+    fn distance(&self, p: Vec3) -> f32;
+}
+
+/// Wraps any closure as an `Sdf`, useful for procedural shapes like fractals.
+pub struct SdfFn<F: Fn(Vec3) -> f32>(pub F);
+
+impl<F: Fn(Vec3) -> f32> Sdf for SdfFn<F> {
+    fn distance(&self, p: Vec3) -> f32 {
+        (self.0)(p)
+    }
+}
+
+pub struct SdfSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Vec3) -> f32 {
+        (p - self.center).length() - self.radius
+    }
+}
+
+pub struct SdfBox {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: Vec3) -> f32 {
+        let q = (p - self.center).abs() - self.half_extents;
+        q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+    }
+}
+
+/// Box with its edges rounded by `radius`, `half_extents` includes the rounding.
+pub struct SdfRoundBox {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+    pub radius: f32,
+}
+
+impl Sdf for SdfRoundBox {
+    fn distance(&self, p: Vec3) -> f32 {
+        let q = (p - self.center).abs() - self.half_extents + Vec3::splat(self.radius);
+        q.max(Vec3::ZERO).length() + q.max_element().min(0.0) - self.radius
+    }
+}
+
+/// Torus around the y axis.
+pub struct SdfTorus {
+    pub center: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Vec3) -> f32 {
+        let p = p - self.center;
+        let q = Vec2::new(Vec2::new(p.x, p.z).length() - self.major_radius, p.y);
+        q.length() - self.minor_radius
+    }
+}
+
+/// Polynomial smooth min, `k` is the blend distance.
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+pub struct SdfSmoothUnion {
+    pub a: Arc<dyn Sdf + Sync + Send>,
+    pub b: Arc<dyn Sdf + Sync + Send>,
+    pub k: f32,
+}
+
+impl Sdf for SdfSmoothUnion {
+    fn distance(&self, p: Vec3) -> f32 {
+        smooth_min(self.a.distance(p), self.b.distance(p), self.k)
+    }
+}
+
+/// Removes `b` from `a` with a rounded seam of size `k`.
+pub struct SdfSmoothSubtraction {
+    pub a: Arc<dyn Sdf + Sync + Send>,
+    pub b: Arc<dyn Sdf + Sync + Send>,
+    pub k: f32,
+}
+
+impl Sdf for SdfSmoothSubtraction {
+    fn distance(&self, p: Vec3) -> f32 {
+        -smooth_min(-self.a.distance(p), self.b.distance(p), self.k)
+    }
+}
+
+/// Infinite repetition of `sdf` with the given period per axis, 0 disables an axis.
+pub struct SdfRepetition {
+    pub sdf: Arc<dyn Sdf + Sync + Send>,
+    pub period: Vec3,
+}
+
+impl Sdf for SdfRepetition {
+    fn distance(&self, p: Vec3) -> f32 {
+        let repeat = |x: f32, period: f32| {
+            if period > 0.0 {
+                x - period * (x / period).round()
+            } else {
+                x
+            }
+        };
+        let q = Vec3::new(
+            repeat(p.x, self.period.x),
+            repeat(p.y, self.period.y),
+            repeat(p.z, self.period.z),
+        );
+        self.sdf.distance(q)
+    }
+}
+
+/// Twists `sdf` around the y axis by `amount` radians per unit of height.
+/// The result is not a true distance, lower `SdfHittable::step_scale` when using it.
+pub struct SdfTwist {
+    pub sdf: Arc<dyn Sdf + Sync + Send>,
+    pub amount: f32,
+}
+
+impl Sdf for SdfTwist {
+    fn distance(&self, p: Vec3) -> f32 {
+        let (sin, cos) = (self.amount * p.y).sin_cos();
+        let q = Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+        self.sdf.distance(q)
+    }
+}
+
+/// Sphere traces an `Sdf` inside the box between `bounds_min` and `bounds_max`.
+#[derive(Clone)]
+pub struct SdfHittable {
+    pub sdf: Arc<dyn Sdf + Sync + Send>,
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+    pub max_steps: i32,
+    pub hit_epsilon: f32,
+    pub step_scale: f32, // < 1.0 for distance functions that overestimate
+    pub material_id: i32,
+    pub surface: SurfaceAttributes,
+}
+
+impl SdfHittable {
+    pub fn new(
+        sdf: Arc<dyn Sdf + Sync + Send>,
+        bounds_min: Vec3,
+        bounds_max: Vec3,
+        material_id: i32,
+        surface: SurfaceAttributes,
+    ) -> Self {
+        Self {
+            sdf,
+            bounds_min,
+            bounds_max,
+            max_steps: 256,
+            hit_epsilon: 0.0001,
+            step_scale: 1.0,
+            material_id,
+            surface,
+        }
+    }
+
+    /// Central differences of the distance field.
+    pub fn normal(&self, p: Vec3) -> Vec3 {
+        const NORMAL_EPSILON: f32 = 0.0005;
+        let dx = Vec3::new(NORMAL_EPSILON, 0.0, 0.0);
+        let dy = Vec3::new(0.0, NORMAL_EPSILON, 0.0);
+        let dz = Vec3::new(0.0, 0.0, NORMAL_EPSILON);
+        Vec3::new(
+            self.sdf.distance(p + dx) - self.sdf.distance(p - dx),
+            self.sdf.distance(p + dy) - self.sdf.distance(p - dy),
+            self.sdf.distance(p + dz) - self.sdf.distance(p - dz),
+        )
+        .normalize_or_zero()
+    }
+
+    fn bounds_t(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let inv_direction = ray.direction.recip();
+        let t0 = (self.bounds_min - ray.origin) * inv_direction;
+        let t1 = (self.bounds_max - ray.origin) * inv_direction;
+        let t_enter = t0.min(t1).max_element();
+        let t_exit = t0.max(t1).min_element();
+        if t_enter > t_exit {
+            return None;
+        }
+        Some((t_enter, t_exit))
+    }
+}
+
+impl Hittable for SdfHittable {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let (t_enter, t_exit) = self.bounds_t(ray)?;
+        let mut t = t_enter.max(interval.min);
+        let t_max = t_exit.min(interval.max);
+        if t > t_max {
+            return None;
+        }
+
+        // Scattered rays start on the surface, step out of the hit shell before picking a side
+        const SHELL_ESCAPE_STEPS: i32 = 16;
+        let mut start_distance = self.sdf.distance(ray.at(t));
+        for _step in 0..SHELL_ESCAPE_STEPS {
+            if start_distance.abs() >= self.hit_epsilon {
+                break;
+            }
+            t += self.hit_epsilon;
+            start_distance = self.sdf.distance(ray.at(t));
+        }
+
+        // Rays starting inside the surface march on the negated field
+        let side = start_distance.signum();
+        let mut hit_t: Option<f32> = None;
+        for _step in 0..self.max_steps {
+            let distance = side * self.sdf.distance(ray.at(t));
+            if distance < self.hit_epsilon {
+                hit_t = Some(t);
+                break;
+            }
+            t += distance * self.step_scale;
+            if t > t_max {
+                break;
+            }
+        }
+
+        let t = hit_t?;
+        if !interval.surrounds(t) {
+            return None;
+        }
+
+        let location = ray.at(t);
+        let outward_normal = self.normal(location);
+        let mut hit_result: HitResult = HitResult {
+            location,
+            normal: outward_normal,
+            t,
+            uv: sphere_uv(outward_normal),
+            front_face: None,
+            material_id: self.material_id,
+            surface: self.surface,
+        };
+        hit_result.set_face_normal(ray, outward_normal);
+
+        return Some(hit_result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ray::Sphere;
+
+    use super::*;
+
+    #[test]
+    fn test_sdf_sphere_matches_analytic_sphere() {
+        let center = Vec3::new(0.5, 0.0, -4.0);
+        let sdf_hittable = SdfHittable::new(
+            Arc::new(SdfSphere {
+                center,
+                radius: 1.0,
+            }),
+            center - Vec3::splat(1.1),
+            center + Vec3::splat(1.1),
+            0,
+            SurfaceAttributes::default(),
+        );
+        let sphere = Sphere {
+            center,
+            radius: 1.0,
+            material_id: 0,
+            surface: SurfaceAttributes::default(),
+        };
+
+        let ray = Ray::new(Vec3::new(0.0, 0.2, 0.0), Vec3::new(0.1, 0.0, -1.0));
+        let sdf_hit = sdf_hittable
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        let sphere_hit = sphere
+            .hit(&ray, Interval::new(0.0001, f32::INFINITY))
+            .unwrap();
+        assert!((sdf_hit.t - sphere_hit.t).abs() < 1e-3);
+        assert!((sdf_hit.normal - sphere_hit.normal).length() < 1e-2);
+
+        let miss_ray = Ray::new(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(sdf_hittable
+            .hit(&miss_ray, Interval::new(0.0001, f32::INFINITY))
+            .is_none());
+    }
+}
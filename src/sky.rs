@@ -0,0 +1,337 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{
+    color::Color,
+    framebuffer::luminance,
+    light::{DirectionalLight, Light, LightSample},
+    math::math::deg_to_rad,
+    sampler::next_2d,
+    spectrum::xyz_to_linear_srgb,
+    warp::sample_cdf,
+};
+
+/// Resolution of the luminance table SkyLight samples from, rows go from the zenith down.
+const SKY_LIGHT_ROWS: usize = 32;
+const SKY_LIGHT_COLUMNS: usize = 64;
+
+/// Angular diameter of the sun seen from earth, in degrees.
+pub const SUN_ANGULAR_DIAMETER: f32 = 0.53;
+/// Luminance of the sun disk above the atmosphere, kcd/m^2 like the Preetham sky.
+const SUN_LUMINANCE: f32 = 1.6e6;
+
+/// CIE xyY to linear sRGB, unclamped so luminance above 1 is kept.
+fn xyy_to_linear_srgb(x: f32, y: f32, luminance: f32) -> [f32; 3] {
+    if y <= 0.0 {
+        return [0.0; 3];
+    }
+    xyz_to_linear_srgb(Vec3::new(
+        x * luminance / y,
+        luminance,
+        (1.0 - x - y) * luminance / y,
+    ))
+}
+
+/// Perez distribution coefficients A..E for one of Y, x or y.
+#[derive(Clone, Copy)]
+struct PerezCoefficients([f32; 5]);
+
+impl PerezCoefficients {
+    fn f(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Preetham analytic daylight sky with a matching sun disk.
+/// Radiance is in kcd/m^2 multiplied by `radiance_scale`. Set it on the world with
+/// `HittableList::set_sky` so the sun and the dome are also sampled as lights.
+#[derive(Clone)]
+pub struct PreethamSky {
+    pub sun_elevation: f32, // Deg above the horizon
+    pub sun_azimuth: f32,   // Deg around +y, 0 is +x
+    pub turbidity: f32,     // 2 is a clear day, 10 is hazy
+    pub radiance_scale: f32,
+
+    sun_direction: Vec3,
+    zenith: [f32; 3], // Y, x, y at the zenith
+    perez: [PerezCoefficients; 3],
+    perez_zenith: [f32; 3],
+    sun_radiance: Color,
+}
+
+impl PreethamSky {
+    pub fn new(sun_elevation: f32, sun_azimuth: f32, turbidity: f32) -> Self {
+        let mut sky = Self {
+            sun_elevation,
+            sun_azimuth,
+            turbidity,
+            radiance_scale: 0.1,
+            sun_direction: Vec3::Y,
+            zenith: [0.0; 3],
+            perez: [PerezCoefficients([0.0; 5]); 3],
+            perez_zenith: [0.0; 3],
+            sun_radiance: Color::new(0.0, 0.0, 0.0, 1.0),
+        };
+        sky.initialize();
+        sky
+    }
+
+    /// Recomputes the model after changing the public parameters.
+    pub fn initialize(&mut self) {
+        let t = self.turbidity.clamp(1.7, 10.0);
+        let elevation = deg_to_rad(self.sun_elevation.clamp(0.0, 90.0) as f64) as f32;
+        let azimuth = deg_to_rad(self.sun_azimuth as f64) as f32;
+        self.sun_direction = Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        );
+
+        let theta_s = std::f32::consts::FRAC_PI_2 - elevation;
+        let theta_s2 = theta_s * theta_s;
+        let theta_s3 = theta_s2 * theta_s;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let zenith_x = t * t * (0.00166 * theta_s3 - 0.00375 * theta_s2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta_s3 + 0.06377 * theta_s2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta_s3 - 0.21196 * theta_s2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta_s3 - 0.00610 * theta_s2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta_s3 + 0.08970 * theta_s2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta_s3 - 0.26756 * theta_s2 + 0.06670 * theta_s + 0.26688);
+        self.zenith = [zenith_luminance, zenith_x, zenith_y];
+
+        self.perez = [
+            PerezCoefficients([
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ]),
+            PerezCoefficients([
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ]),
+            PerezCoefficients([
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ]),
+        ];
+        for i in 0..3 {
+            self.perez_zenith[i] = self.perez[i].f(1.0, theta_s);
+        }
+
+        // Sun color from Rayleigh and aerosol extinction along the relative optical air mass
+        let theta_s_deg = 90.0 - self.sun_elevation.clamp(0.0, 90.0);
+        let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s_deg).powf(-1.253));
+        let beta = 0.04608365822050 * t - 0.04586025928522;
+        let transmittance = |wavelength_um: f32| {
+            let rayleigh = (-0.008735 * wavelength_um.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * wavelength_um.powf(-1.3) * air_mass).exp();
+            rayleigh * aerosol
+        };
+        self.sun_radiance = Color::new(
+            transmittance(0.680),
+            transmittance(0.550),
+            transmittance(0.440),
+            1.0,
+        ) * SUN_LUMINANCE;
+    }
+
+    pub fn sun_radiance(&self) -> Color {
+        self.sun_radiance * self.radiance_scale
+    }
+
+    pub fn sun_solid_angle(&self) -> f32 {
+        let cos_radius = (deg_to_rad(SUN_ANGULAR_DIAMETER as f64 * 0.5) as f32).cos();
+        2.0 * std::f32::consts::PI * (1.0 - cos_radius)
+    }
+
+    /// The sun disk as a light, its irradiance matches the disk seen in `radiance`.
+    pub fn sun_light(&self) -> DirectionalLight {
+        DirectionalLight {
+            direction: -self.sun_direction,
+            irradiance: self.sun_radiance() * self.sun_solid_angle(),
+            angular_diameter: SUN_ANGULAR_DIAMETER,
+        }
+    }
+
+    /// The dome without the sun as a light, see SkyLight.
+    pub fn sky_light(&self) -> SkyLight {
+        SkyLight::new(self.clone())
+    }
+
+    /// Sky radiance seen along `direction`, the sun disk is left out when it is sampled as a light.
+    pub fn radiance(&self, direction: Vec3, include_sun: bool) -> Color {
+        let direction = direction.normalize();
+        // Below the horizon repeat the horizon, the model is not defined there
+        let cos_theta = direction.y.max(0.01);
+        let cos_gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+
+        let mut yxy = [0.0; 3];
+        for i in 0..3 {
+            yxy[i] = self.zenith[i] * self.perez[i].f(cos_theta, gamma) / self.perez_zenith[i];
+        }
+        let [luminance, x, y] = yxy;
+        let [red, green, blue] = xyy_to_linear_srgb(x, y, luminance.max(0.0));
+        let mut radiance =
+            Color::new(red.max(0.0), green.max(0.0), blue.max(0.0), 1.0) * self.radiance_scale;
+
+        let cos_sun_radius = (deg_to_rad(SUN_ANGULAR_DIAMETER as f64 * 0.5) as f32).cos();
+        if include_sun && cos_gamma >= cos_sun_radius {
+            radiance += self.sun_radiance();
+        }
+
+        radiance
+    }
+}
+
+/// The sky dome without the sun as a light, importance sampled from a table of its luminance
+/// over the whole sphere since the horizon is repeated below it.
+#[derive(Clone)]
+pub struct SkyLight {
+    sky: PreethamSky,
+    row_cdf: Vec<f32>,    // SKY_LIGHT_ROWS + 1 entries
+    column_cdf: Vec<f32>, // SKY_LIGHT_COLUMNS + 1 entries per row
+}
+
+fn sky_light_direction(cos_theta: f32, phi: f32) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin())
+}
+
+fn normalize_cdf(cdf: &mut [f32]) {
+    let total = cdf[cdf.len() - 1];
+    for value in cdf.iter_mut() {
+        *value /= total;
+    }
+}
+
+impl SkyLight {
+    pub fn new(sky: PreethamSky) -> Self {
+        let mut row_cdf: Vec<f32> = vec![0.0];
+        let mut column_cdf: Vec<f32> = Vec::with_capacity((SKY_LIGHT_COLUMNS + 1) * SKY_LIGHT_ROWS);
+        let cell_phi = 2.0 * PI / SKY_LIGHT_COLUMNS as f32;
+        for row in 0..SKY_LIGHT_ROWS {
+            let (cos_top, cos_bottom) = Self::row_bounds(row);
+            let cell_solid_angle = (cos_top - cos_bottom) * cell_phi;
+            let cos_center = 0.5 * (cos_top + cos_bottom);
+
+            let start = column_cdf.len();
+            column_cdf.push(0.0);
+            for column in 0..SKY_LIGHT_COLUMNS {
+                let phi = (column as f32 + 0.5) * cell_phi;
+                let radiance = sky.radiance(sky_light_direction(cos_center, phi), false);
+                // Small floor so every direction the sky shows can be sampled
+                let weight = (luminance(radiance).max(0.0) + 1e-4) * cell_solid_angle;
+                column_cdf.push(column_cdf[start + column] + weight);
+            }
+            row_cdf.push(row_cdf[row] + column_cdf[start + SKY_LIGHT_COLUMNS]);
+            normalize_cdf(&mut column_cdf[start..]);
+        }
+        normalize_cdf(&mut row_cdf);
+
+        Self {
+            sky,
+            row_cdf,
+            column_cdf,
+        }
+    }
+
+    /// Cosines of the polar angle at the top and bottom of a row.
+    fn row_bounds(row: usize) -> (f32, f32) {
+        let theta = |row: usize| row as f32 / SKY_LIGHT_ROWS as f32 * PI;
+        (theta(row).cos(), theta(row + 1).cos())
+    }
+}
+
+impl Light for SkyLight {
+    fn clone_dyn(&self) -> Box<dyn Light + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn sample(&self, _location: Vec3) -> Option<LightSample> {
+        let u = next_2d();
+        let (row, v) = sample_cdf(&self.row_cdf, u.y);
+        let columns = &self.column_cdf[row * (SKY_LIGHT_COLUMNS + 1)..][..SKY_LIGHT_COLUMNS + 1];
+        let (column, s) = sample_cdf(columns, u.x);
+
+        // Uniform in solid angle within the cell
+        let (cos_top, cos_bottom) = Self::row_bounds(row);
+        let cos_theta = cos_top + (cos_bottom - cos_top) * v;
+        let cell_phi = 2.0 * PI / SKY_LIGHT_COLUMNS as f32;
+        let direction = sky_light_direction(cos_theta, (column as f32 + s) * cell_phi);
+
+        let probability =
+            (self.row_cdf[row + 1] - self.row_cdf[row]) * (columns[column + 1] - columns[column]);
+        let pdf = probability / ((cos_top - cos_bottom) * cell_phi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance: f32::INFINITY,
+            radiance: self.sky.radiance(direction, false) / pdf,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::rand;
+
+    #[test]
+    fn test_preetham_zenith_and_sun_color() {
+        let sky = PreethamSky::new(45.0, 30.0, 3.0);
+        let zenith = sky.radiance(Vec3::Y, true);
+        let luminance = 0.2126 * zenith.red + 0.7152 * zenith.green + 0.0722 * zenith.blue;
+        assert!((luminance - sky.zenith[0] * sky.radiance_scale).abs() < 1e-2 * luminance);
+        assert!(zenith.blue > zenith.red);
+
+        // The sun disk only shows when it is not sampled as a light
+        let sun_direction = sky.sun_direction;
+        assert!(sky.radiance(sun_direction, true).green > sky.radiance(sun_direction, false).green);
+
+        // Lower sun passes through more air and turns red
+        let low_sky = PreethamSky::new(5.0, 30.0, 3.0);
+        let high_ratio = sky.sun_radiance().red / sky.sun_radiance().blue;
+        let low_ratio = low_sky.sun_radiance().red / low_sky.sun_radiance().blue;
+        assert!(low_ratio > high_ratio);
+    }
+
+    #[test]
+    fn test_sky_light_matches_the_dome() {
+        // Irradiance on an upward facing surface, sampled from the light and uniformly
+        let sky = PreethamSky::new(30.0, 60.0, 3.0);
+        let light = sky.sky_light();
+        let num_samples = 40000;
+
+        let mut sampled = 0.0;
+        let mut uniform = 0.0;
+        for _ in 0..num_samples {
+            let sample = light.sample(Vec3::ZERO).unwrap();
+            sampled += luminance(sample.radiance) * sample.direction.y.max(0.0);
+
+            let cos_theta = rand::<f32>();
+            let direction = sky_light_direction(cos_theta, rand::<f32>() * 2.0 * PI);
+            uniform += luminance(sky.radiance(direction, false)) * cos_theta * 2.0 * PI;
+        }
+        let sampled = sampled / num_samples as f32;
+        let uniform = uniform / num_samples as f32;
+        assert!((sampled - uniform).abs() < 0.05 * uniform);
+    }
+}
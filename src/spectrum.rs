@@ -0,0 +1,342 @@
+use std::{
+    ops::{Add, AddAssign, Mul},
+    sync::OnceLock,
+};
+
+use glam::Vec3;
+
+use crate::color::Color;
+
+/// Visible range covered by the spectral mode, in nm.
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 720.0;
+/// Wavelengths traced together along one path, the hero and its evenly spaced rotations.
+pub const NUM_WAVELENGTHS: usize = 4;
+
+/// CIE 1931 2 degree color matching functions, the multi lobe fit from Wyman et al.,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    let lobe = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// XYZ of the constant spectrum 1 over the traced range, scaled so Y is 1.
+fn flat_spectrum_xyz() -> Vec3 {
+    static FLAT_XYZ: OnceLock<Vec3> = OnceLock::new();
+    *FLAT_XYZ.get_or_init(|| {
+        let mut xyz = Vec3::ZERO;
+        let mut lambda = LAMBDA_MIN + 0.5;
+        while lambda < LAMBDA_MAX {
+            xyz += cie_xyz(lambda);
+            lambda += 1.0;
+        }
+        xyz / xyz.y
+    })
+}
+
+/// CIE XYZ to linear sRGB (D65), unclamped.
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> [f32; 3] {
+    [
+        3.240_454 * xyz.x - 1.537_138_5 * xyz.y - 0.498_531_4 * xyz.z,
+        -0.969_266 * xyz.x + 1.876_010_8 * xyz.y + 0.041_556 * xyz.z,
+        0.055_643_4 * xyz.x - 0.204_025_9 * xyz.y + 1.057_225_2 * xyz.z,
+    ]
+}
+
+const D65_WHITE_XYZ: Vec3 = Vec3::new(0.950_47, 1.0, 1.088_83);
+
+// Smits, "An RGB to Spectrum Conversion for Reflectances", 10 bins from 380 to 720 nm
+const SMITS_WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+fn smits_basis(basis: &[f32; 10], lambda: f32) -> f32 {
+    // Linear between the bin centers
+    let bin_width = (LAMBDA_MAX - LAMBDA_MIN) / 10.0;
+    let t = ((lambda - LAMBDA_MIN) / bin_width - 0.5).clamp(0.0, 9.0);
+    let i = (t as usize).min(8);
+    let frac = t - i as f32;
+    basis[i] * (1.0 - frac) + basis[i + 1] * frac
+}
+
+/// Value at `lambda` of a smooth spectrum matching a linear sRGB color, with Smits' method.
+/// Linear in the color, so it also works for emission above 1.
+pub fn rgb_to_spectrum(color: Color, lambda: f32) -> f32 {
+    let (r, g, b) = (
+        color.red.max(0.0),
+        color.green.max(0.0),
+        color.blue.max(0.0),
+    );
+    let basis = |spectrum: &[f32; 10]| smits_basis(spectrum, lambda);
+
+    let value = if r <= g && r <= b {
+        r * basis(&SMITS_WHITE)
+            + if g <= b {
+                (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+            } else {
+                (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * basis(&SMITS_WHITE)
+            + if r <= b {
+                (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+            } else {
+                (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+            }
+    } else {
+        b * basis(&SMITS_WHITE)
+            + if r <= g {
+                (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+            } else {
+                (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+            }
+    };
+    value.max(0.0)
+}
+
+/// Wavelengths of one path in nm with their sampling pdfs.
+/// A pdf of zero marks a wavelength dropped after a dispersive interaction.
+#[derive(Clone, Copy, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [f32; NUM_WAVELENGTHS],
+    pub pdf: [f32; NUM_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    /// Hero wavelength from `u` in [0, 1), the others evenly spaced after it with wrap around.
+    pub fn sample_hero(u: f32) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; NUM_WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / NUM_WAVELENGTHS as f32).fract();
+            *l = LAMBDA_MIN + offset * range;
+        }
+        Self {
+            lambda,
+            pdf: [1.0 / range; NUM_WAVELENGTHS],
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// Keeps only the hero, for interactions that send each wavelength a different way.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|pdf| *pdf == 0.0) {
+            return;
+        }
+        for pdf in self.pdf[1..].iter_mut() {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= NUM_WAVELENGTHS as f32;
+    }
+}
+
+/// Spectral values at the wavelengths of a `SampledWavelengths`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledSpectrum(pub [f32; NUM_WAVELENGTHS]);
+
+impl SampledSpectrum {
+    pub fn new(value: f32) -> Self {
+        Self([value; NUM_WAVELENGTHS])
+    }
+
+    pub fn from_rgb(color: Color, wavelengths: &SampledWavelengths) -> Self {
+        Self(
+            wavelengths
+                .lambda
+                .map(|lambda| rgb_to_spectrum(color, lambda)),
+        )
+    }
+
+    /// Monte Carlo estimate of the XYZ color, Y of the constant spectrum 1 is 1.
+    pub fn to_xyz(&self, wavelengths: &SampledWavelengths) -> Vec3 {
+        let mut xyz = Vec3::ZERO;
+        for i in 0..NUM_WAVELENGTHS {
+            if wavelengths.pdf[i] > 0.0 {
+                xyz += cie_xyz(wavelengths.lambda[i]) * (self.0[i] / wavelengths.pdf[i]);
+            }
+        }
+        xyz / (NUM_WAVELENGTHS as f32 * flat_y_integral())
+    }
+
+    /// Linear sRGB of the spectrum. The equal energy white of the constant spectrum is
+    /// mapped to D65, so white surfaces under white light stay white.
+    pub fn to_color(&self, wavelengths: &SampledWavelengths) -> Color {
+        let xyz = self.to_xyz(wavelengths) * D65_WHITE_XYZ / flat_spectrum_xyz();
+        let [red, green, blue] = xyz_to_linear_srgb(xyz);
+        Color::new(red, green, blue, 1.0)
+    }
+}
+
+fn flat_y_integral() -> f32 {
+    static Y_INTEGRAL: OnceLock<f32> = OnceLock::new();
+    *Y_INTEGRAL.get_or_init(|| {
+        let mut integral = 0.0;
+        let mut lambda = LAMBDA_MIN + 0.5;
+        while lambda < LAMBDA_MAX {
+            integral += cie_xyz(lambda).y;
+            lambda += 1.0;
+        }
+        integral
+    })
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl Mul<f32> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self(self.0.map(|value| value * rhs))
+    }
+}
+
+/// Wavelength dependent index of refraction for dielectrics, used in the spectral mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dispersion {
+    /// The constant `SurfaceAttributes::ir`.
+    #[default]
+    None,
+    /// n = a + b / lambda^2, lambda in micrometers.
+    Cauchy { a: f32, b: f32 },
+    /// n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i), lambda in micrometers and c in um^2.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7 crown glass.
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612_1, 0.231_792_35, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+    /// Dense flint glass with strong dispersion, Schott N-SF11.
+    pub const SF11: Dispersion = Dispersion::Sellmeier {
+        b: [1.737_596_9, 0.313_747_35, 1.898_781],
+        c: [0.013_188_707, 0.062_306_814, 155.236_29],
+    };
+
+    /// Index of refraction at `lambda` nm, `None` when it does not depend on the wavelength.
+    pub fn ior(&self, lambda: f32) -> Option<f32> {
+        let lambda_um = lambda * 1e-3;
+        let lambda_um2 = lambda_um * lambda_um;
+        match *self {
+            Dispersion::None => None,
+            Dispersion::Cauchy { a, b } => Some(a + b / lambda_um2),
+            Dispersion::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * lambda_um2 / (lambda_um2 - c[i]);
+                }
+                Some(n2.max(1.0).sqrt())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Averages the spectral estimate of a color over stratified hero wavelengths.
+    fn round_trip(color: Color) -> Color {
+        const SAMPLES: i32 = 256;
+        let mut sum = Vec3::ZERO;
+        for i in 0..SAMPLES {
+            let wavelengths = SampledWavelengths::sample_hero((i as f32 + 0.5) / SAMPLES as f32);
+            let c = SampledSpectrum::from_rgb(color, &wavelengths).to_color(&wavelengths);
+            sum += Vec3::new(c.red, c.green, c.blue);
+        }
+        sum /= SAMPLES as f32;
+        Color::new(sum.x, sum.y, sum.z, 1.0)
+    }
+
+    #[test]
+    fn test_rgb_spectrum_round_trip() {
+        let white = round_trip(Color::new(1.0, 1.0, 1.0, 1.0));
+        assert!((white.red - 1.0).abs() < 0.02);
+        assert!((white.green - 1.0).abs() < 0.02);
+        assert!((white.blue - 1.0).abs() < 0.02);
+
+        // Linear in the color so emission above 1 keeps its scale
+        let bright = round_trip(Color::new(4.0, 4.0, 4.0, 1.0));
+        assert!((bright.green - 4.0 * white.green).abs() < 1e-3);
+
+        let red = round_trip(Color::new(0.8, 0.1, 0.1, 1.0));
+        assert!(red.red > 0.6 && red.green < 0.25 && red.blue < 0.25);
+
+        // Dropping the secondary wavelengths keeps the estimate unbiased
+        const SAMPLES: i32 = 1024;
+        let mut y_hero_only = 0.0;
+        for i in 0..SAMPLES {
+            let mut wavelengths =
+                SampledWavelengths::sample_hero((i as f32 + 0.5) / SAMPLES as f32);
+            wavelengths.terminate_secondary();
+            y_hero_only += SampledSpectrum::new(1.0).to_xyz(&wavelengths).y;
+        }
+        assert!((y_hero_only / SAMPLES as f32 - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_dispersion_ior() {
+        // N-BK7 at the helium d line is 1.5168
+        let n_d = Dispersion::BK7.ior(587.56).unwrap();
+        assert!((n_d - 1.5168).abs() < 1e-3);
+        assert!(Dispersion::BK7.ior(450.0).unwrap() > Dispersion::BK7.ior(650.0).unwrap());
+
+        let cauchy = Dispersion::Cauchy {
+            a: 1.5046,
+            b: 0.00420,
+        };
+        assert!((cauchy.ior(500.0).unwrap() - (1.5046 + 0.0042 / 0.25)).abs() < 1e-5);
+        assert_eq!(Dispersion::None.ior(500.0), None);
+    }
+}
@@ -0,0 +1,217 @@
+use std::{fs::File, io, path::Path};
+
+use crate::{
+    camera::{Camera, Projection},
+    framebuffer::Framebuffer,
+    ray::HittableList,
+    renderer::{render_framebuffer, save_framebuffer, RenderConfig},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// How the two eyes share the output image.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye on the left half.
+    #[default]
+    SideBySide,
+    /// Left eye on the top half.
+    OverUnder,
+}
+
+/// Two cameras offset from one `Camera` along its right axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoRig {
+    pub interocular_distance: f32, // World units, 0.064 is an average adult in meters
+    pub convergence_distance: f32, // Objects this far away have no parallax, in front of it they pop out
+    pub layout: StereoLayout,
+    pub omnidirectional: bool, // 360 degree equirectangular eyes, convergence is at infinity
+}
+
+impl Default for StereoRig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StereoRig {
+    pub fn new() -> Self {
+        Self {
+            interocular_distance: 0.064,
+            convergence_distance: 2.0,
+            layout: StereoLayout::SideBySide,
+            omnidirectional: false,
+        }
+    }
+
+    /// Copy of `camera` for one eye. Perspective eyes are parallel and converge by shifting
+    /// their viewports, which avoids the vertical parallax of toed in cameras.
+    pub fn eye_camera(&self, camera: &Camera, eye: Eye) -> Camera {
+        let side = match eye {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        };
+        let half_distance = 0.5 * self.interocular_distance;
+
+        let mut eye_camera = camera.clone();
+        if self.omnidirectional {
+            eye_camera.projection = Projection::Equirectangular {
+                eye_offset: side * half_distance,
+            };
+            eye_camera.aspect_ratio = 2.0;
+        } else {
+            eye_camera.initialize();
+            eye_camera.position += camera.right() * side * half_distance;
+
+            let (image_width, image_height) = eye_camera.get_image_xy();
+            let viewport_width = 2.0
+                * (0.5 * camera.fov.to_radians()).tan()
+                * self.convergence_distance
+                * image_width as f32
+                / image_height as f32;
+            eye_camera.viewport_shift.x -= side * half_distance / viewport_width;
+        }
+        eye_camera
+    }
+
+    /// Renders both eyes into one image laid out by `layout`. AOVs are saved per eye,
+    /// `render.ppm` gets `render_left.depth.pfm` and `render_right.depth.pfm`.
+    pub fn render(
+        &self,
+        world: &HittableList,
+        camera: &Camera,
+        config: &RenderConfig,
+        render_file_path: &str,
+    ) -> Result<File, io::Error> {
+        let mut eyes: Vec<Framebuffer> = Vec::with_capacity(2);
+        for (eye, name) in [(Eye::Left, "left"), (Eye::Right, "right")] {
+            println!("Rendering {} eye...", name);
+            let mut eye_camera = self.eye_camera(camera, eye);
+            let (framebuffer, aovs) = render_framebuffer(world, &mut eye_camera, config);
+            if let (true, Some(aovs)) = (config.write_aovs, &aovs) {
+                aovs.save(&eye_file_path(render_file_path, name))?;
+            }
+            eyes.push(framebuffer);
+        }
+
+        let combined = combine_eyes(&eyes[0], &eyes[1], self.layout);
+        save_framebuffer(&combined, world, config, render_file_path)
+    }
+}
+
+/// `render.ppm` to `render_left.ppm`.
+fn eye_file_path(render_file_path: &str, name: &str) -> String {
+    let path = Path::new(render_file_path);
+    let stem = path.with_extension("");
+    match path.extension() {
+        Some(extension) => format!(
+            "{}_{}.{}",
+            stem.display(),
+            name,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}_{}", stem.display(), name),
+    }
+}
+
+/// Both eyes of the same size in one framebuffer, pixels and variance.
+pub fn combine_eyes(left: &Framebuffer, right: &Framebuffer, layout: StereoLayout) -> Framebuffer {
+    assert_eq!((left.width, left.height), (right.width, right.height));
+    let (width, height) = (left.width, left.height);
+    let (offset_x, offset_y) = match layout {
+        StereoLayout::SideBySide => (width, 0),
+        StereoLayout::OverUnder => (0, height),
+    };
+
+    let mut combined = Framebuffer::new(width + offset_x, height + offset_y);
+    combined.color_space = left.color_space;
+    for (eye, (x0, y0)) in [(left, (0, 0)), (right, (offset_x, offset_y))] {
+        for y in 0..height {
+            for x in 0..width {
+                let index = combined.index(x0 + x, y0 + y);
+                combined.pixels[index] = eye.get(x, y);
+                combined.variance[index] = eye.variance[eye.index(x, y)];
+            }
+        }
+    }
+    combined
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use crate::color::Color;
+
+    use super::*;
+
+    #[test]
+    fn test_combine_eyes() {
+        let mut left = Framebuffer::new(2, 1);
+        let mut right = Framebuffer::new(2, 1);
+        left.pixels[1] = Color::new(1.0, 0.0, 0.0, 1.0);
+        right.pixels[0] = Color::new(0.0, 0.0, 1.0, 1.0);
+        right.variance[0] = 0.5;
+
+        let side_by_side = combine_eyes(&left, &right, StereoLayout::SideBySide);
+        assert_eq!((side_by_side.width, side_by_side.height), (4, 1));
+        assert_eq!(side_by_side.get(1, 0).red, 1.0);
+        assert_eq!(side_by_side.get(2, 0).blue, 1.0);
+        assert_eq!(side_by_side.variance[2], 0.5);
+
+        let over_under = combine_eyes(&left, &right, StereoLayout::OverUnder);
+        assert_eq!((over_under.width, over_under.height), (2, 2));
+        assert_eq!(over_under.get(0, 1).blue, 1.0);
+
+        assert_eq!(
+            eye_file_path("out/render.ppm", "left"),
+            "out/render_left.ppm"
+        );
+    }
+
+    #[test]
+    fn test_eyes_converge() {
+        let mut camera = Camera::new();
+        camera.image_width = 64;
+        camera.fov = 60.0;
+        let rig = StereoRig {
+            interocular_distance: 0.1,
+            convergence_distance: 3.0,
+            ..StereoRig::new()
+        };
+
+        // The center rays of both eyes meet at the convergence distance
+        let mut centers = Vec::new();
+        for eye in [Eye::Left, Eye::Right] {
+            let mut eye_camera = rig.eye_camera(&camera, eye);
+            eye_camera.initialize();
+            let ray = eye_camera
+                .get_ray_offset(31, 31, Vec2::new(0.5, 0.5))
+                .unwrap()
+                .ray;
+            let t = 3.0 / -ray.direction.normalize().z;
+            centers.push(ray.origin + ray.direction.normalize() * t);
+        }
+        assert!((centers[0] - centers[1]).length() < 1e-3);
+        assert!(centers[0].x.abs() < 1e-3);
+
+        // Omnidirectional eyes start on opposite sides of the position
+        let rig = StereoRig {
+            omnidirectional: true,
+            ..rig
+        };
+        let mut origins = Vec::new();
+        for eye in [Eye::Left, Eye::Right] {
+            let mut eye_camera = rig.eye_camera(&camera, eye);
+            eye_camera.initialize();
+            assert_eq!(eye_camera.get_image_xy(), (64, 32));
+            origins.push(eye_camera.get_ray(10, 16).unwrap().ray.origin);
+        }
+        assert!((origins[0] + origins[1]).length() < 1e-5);
+        assert!(((origins[0] - origins[1]).length() - 0.1).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,507 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Receives the frames of a sequence as 8 bit RGB, row major with the top row first.
+pub trait FrameWriter {
+    fn write_frame(&mut self, pixels: &[[u8; 3]]) -> Result<(), io::Error>;
+    /// Writes whatever closes the file, no frames can follow.
+    fn finish(&mut self) -> Result<(), io::Error>;
+}
+
+/// Frame rate as a fraction with a denominator of at most 1000, 29.97 becomes 2997:100.
+fn frame_rate_ratio(frame_rate: f32) -> (u32, u32) {
+    fn gcd(a: u32, b: u32) -> u32 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    let numerator = (frame_rate * 1000.0).round().max(1.0) as u32;
+    let divisor = gcd(numerator, 1000);
+    (numerator / divisor, 1000 / divisor)
+}
+
+/// Uncompressed YUV4MPEG2 video, full range BT.601 with 4:2:0 chroma as JPEG places it.
+/// Plays in most video players and pipes straight into encoders.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Y4mWriter<BufWriter<File>> {
+    pub fn create(
+        path: &str,
+        width: usize,
+        height: usize,
+        frame_rate: f32,
+    ) -> Result<Self, io::Error> {
+        Self::new(
+            BufWriter::new(File::create(path)?),
+            width,
+            height,
+            frame_rate,
+        )
+    }
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        frame_rate: f32,
+    ) -> Result<Self, io::Error> {
+        let (numerator, denominator) = frame_rate_ratio(frame_rate);
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=FULL",
+            width, height, numerator, denominator
+        )?;
+        Ok(Self {
+            writer,
+            width,
+            height,
+        })
+    }
+}
+
+impl<W: Write> FrameWriter for Y4mWriter<W> {
+    fn write_frame(&mut self, pixels: &[[u8; 3]]) -> Result<(), io::Error> {
+        if pixels.len() != self.width * self.height {
+            return Err(invalid_data("frame size does not match the video"));
+        }
+        let to_ycbcr = |[r, g, b]: [u8; 3]| {
+            let (r, g, b) = (r as f32, g as f32, b as f32);
+            [
+                0.299 * r + 0.587 * g + 0.114 * b,
+                128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b,
+                128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b,
+            ]
+        };
+        let to_u8 = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+
+        let luma: Vec<u8> = pixels.iter().map(|p| to_u8(to_ycbcr(*p)[0])).collect();
+
+        // Chroma is averaged over 2x2 blocks, odd sizes repeat the last row or column
+        let (chroma_width, chroma_height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut cb: Vec<u8> = Vec::with_capacity(chroma_width * chroma_height);
+        let mut cr: Vec<u8> = Vec::with_capacity(chroma_width * chroma_height);
+        for y in 0..chroma_height {
+            for x in 0..chroma_width {
+                let mut sum = [0.0; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let px = (2 * x + dx).min(self.width - 1);
+                    let py = (2 * y + dy).min(self.height - 1);
+                    let ycbcr = to_ycbcr(pixels[px + py * self.width]);
+                    sum[1] += ycbcr[1];
+                    sum[2] += ycbcr[2];
+                }
+                cb.push(to_u8(sum[1] / 4.0));
+                cr.push(to_u8(sum[2] / 4.0));
+            }
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&luma)?;
+        self.writer.write_all(&cb)?;
+        self.writer.write_all(&cr)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+}
+
+/// Up to 256 colors representing `pixels`, by median cut.
+pub fn quantize_palette(pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]> {
+    // Large frames are subsampled, the palette barely changes
+    let stride = (pixels.len() / 65536).max(1);
+    let mut boxes: Vec<Vec<[u8; 3]>> = vec![pixels.iter().step_by(stride).copied().collect()];
+    if boxes[0].is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    let channel_range = |colors: &[[u8; 3]], channel: usize| {
+        let min = colors.iter().map(|c| c[channel]).min().unwrap_or(0);
+        let max = colors.iter().map(|c| c[channel]).max().unwrap_or(0);
+        max - min
+    };
+
+    while boxes.len() < max_colors.min(256) {
+        // Split the box with the widest channel at its median
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| {
+                let (channel, range) = (0..3)
+                    .map(|channel| (channel, channel_range(colors, channel)))
+                    .max_by_key(|(_, range)| *range)
+                    .unwrap();
+                (i, channel, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let Some((i, channel, range)) = widest else {
+            break;
+        };
+        if range == 0 {
+            break;
+        }
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|c| c[channel]);
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let mut sum = [0u64; 3];
+            for color in colors {
+                for channel in 0..3 {
+                    sum[channel] += color[channel] as u64;
+                }
+            }
+            let n = colors.len().max(1) as u64;
+            sum.map(|s| ((s + n / 2) / n) as u8)
+        })
+        .collect()
+}
+
+/// Index of the palette color closest to `color`.
+fn nearest_color(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance =
+        |p: &[u8; 3]| -> i32 { (0..3).map(|c| (p[c] as i32 - color[c] as i32).pow(2)).sum() };
+    let (index, _) = palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| distance(p))
+        .unwrap();
+    index as u8
+}
+
+/// Palette indices of `pixels`, optionally with Floyd-Steinberg error diffusion.
+pub fn map_to_palette(
+    pixels: &[[u8; 3]],
+    width: usize,
+    palette: &[[u8; 3]],
+    dither: bool,
+) -> Vec<u8> {
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut lookup = |color: [u8; 3]| -> u8 {
+        *cache
+            .entry(color)
+            .or_insert_with(|| nearest_color(palette, color))
+    };
+
+    if !dither {
+        return pixels.iter().map(|p| lookup(*p)).collect();
+    }
+
+    let height = pixels.len() / width.max(1);
+    let mut error: Vec<[f32; 3]> = vec![[0.0; 3]; pixels.len()];
+    let mut indices: Vec<u8> = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        for x in 0..width {
+            let i = x + y * width;
+            let wanted: [f32; 3] = [0, 1, 2].map(|c| pixels[i][c] as f32 + error[i][c]);
+            let index = lookup(wanted.map(|v| v.round().clamp(0.0, 255.0) as u8));
+            indices.push(index);
+
+            let chosen = palette[index as usize];
+            let residual: [f32; 3] = [0, 1, 2].map(|c| wanted[c] - chosen[c] as f32);
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let (nx, ny) = (x as isize + dx, y + dy);
+                if nx >= 0 && (nx as usize) < width && ny < height {
+                    let n = nx as usize + ny * width;
+                    for c in 0..3 {
+                        error[n][c] += residual[c] * weight;
+                    }
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+    indices
+}
+
+/// Variable length LZW as GIF uses it, codes packed from the low bit up.
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    const MAX_CODES: u16 = 4096;
+    let clear_code: u16 = 1 << min_code_size;
+    let end_code: u16 = clear_code + 1;
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut bit_buffer: u32 = 0;
+    let mut bit_count: u32 = 0;
+    let mut write_code = |code: u16, code_size: u32, output: &mut Vec<u8>| {
+        bit_buffer |= (code as u32) << bit_count;
+        bit_count += code_size;
+        while bit_count >= 8 {
+            output.push(bit_buffer as u8);
+            bit_buffer >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    let mut code_size: u32 = min_code_size as u32 + 1;
+    let mut next_code: u16 = end_code + 1;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    write_code(clear_code, code_size, &mut output);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let Some(current) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+        if let Some(&code) = table.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+
+        write_code(current, code_size, &mut output);
+        if next_code < MAX_CODES {
+            table.insert((current, index), next_code);
+            next_code += 1;
+            if next_code > (1 << code_size) && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            // Table is full, start over
+            write_code(clear_code, code_size, &mut output);
+            table.clear();
+            next_code = end_code + 1;
+            code_size = min_code_size as u32 + 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        write_code(current, code_size, &mut output);
+    }
+    write_code(end_code, code_size, &mut output);
+    if bit_count > 0 {
+        output.push(bit_buffer as u8);
+    }
+    output
+}
+
+/// Looping animated GIF, every frame gets its own palette.
+pub struct GifEncoder<W: Write> {
+    writer: W,
+    pub width: usize,
+    pub height: usize,
+    pub delay: u16, // Hundredths of a second per frame
+    pub dither: bool,
+}
+
+impl GifEncoder<BufWriter<File>> {
+    pub fn create(
+        path: &str,
+        width: usize,
+        height: usize,
+        frame_rate: f32,
+        dither: bool,
+    ) -> Result<Self, io::Error> {
+        Self::new(
+            BufWriter::new(File::create(path)?),
+            width,
+            height,
+            frame_rate,
+            dither,
+        )
+    }
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        frame_rate: f32,
+        dither: bool,
+    ) -> Result<Self, io::Error> {
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(invalid_data("image is too large for a GIF"));
+        }
+
+        writer.write_all(b"GIF89a")?;
+        // Logical screen without a global color table
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        writer.write_all(&[0, 0, 0])?;
+        // Netscape extension, loop forever
+        writer.write_all(&[0x21, 0xFF, 11])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[3, 1, 0, 0, 0])?;
+
+        Ok(Self {
+            writer,
+            width,
+            height,
+            delay: (100.0 / frame_rate).round().max(1.0) as u16,
+            dither,
+        })
+    }
+}
+
+impl<W: Write> FrameWriter for GifEncoder<W> {
+    fn write_frame(&mut self, pixels: &[[u8; 3]]) -> Result<(), io::Error> {
+        if pixels.len() != self.width * self.height {
+            return Err(invalid_data("frame size does not match the animation"));
+        }
+        let mut palette = quantize_palette(pixels, 256);
+        let indices = map_to_palette(pixels, self.width, &palette, self.dither);
+
+        // Color tables hold a power of two entries, at least 2
+        let table_bits = (palette.len().max(2).next_power_of_two().trailing_zeros()).max(1);
+        palette.resize(1 << table_bits, [0, 0, 0]);
+
+        let writer = &mut self.writer;
+        // Graphic control extension with the frame delay
+        writer.write_all(&[0x21, 0xF9, 4, 0])?;
+        writer.write_all(&self.delay.to_le_bytes())?;
+        writer.write_all(&[0, 0])?;
+        // Image descriptor covering the screen, with a local color table
+        writer.write_all(&[0x2C, 0, 0, 0, 0])?;
+        writer.write_all(&(self.width as u16).to_le_bytes())?;
+        writer.write_all(&(self.height as u16).to_le_bytes())?;
+        writer.write_all(&[0x80 | (table_bits as u8 - 1)])?;
+        for color in palette.iter() {
+            writer.write_all(color)?;
+        }
+
+        let min_code_size = (table_bits as u8).max(2);
+        writer.write_all(&[min_code_size])?;
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            writer.write_all(&[block.len() as u8])?;
+            writer.write_all(block)?;
+        }
+        writer.write_all(&[0])?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        self.writer.write_all(&[0x3B])?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inverse of lzw_encode.
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear_code = 1usize << min_code_size;
+        let end_code = clear_code + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear_code).map(|i| vec![i as u8]).collect();
+            table.push(Vec::new());
+            table.push(Vec::new());
+        };
+        reset(&mut table);
+
+        let mut output: Vec<u8> = Vec::new();
+        let mut code_size = min_code_size as usize + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut bit = 0;
+        while bit + code_size <= data.len() * 8 {
+            let code = (0..code_size)
+                .map(|i| ((data[(bit + i) / 8] >> ((bit + i) % 8)) as usize & 1) << i)
+                .sum::<usize>();
+            bit += code_size;
+            if code == clear_code {
+                reset(&mut table);
+                code_size = min_code_size as usize + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                break;
+            }
+            let entry = match (&previous, code < table.len()) {
+                (_, true) => table[code].clone(),
+                (Some(previous), false) => [previous.clone(), vec![previous[0]]].concat(),
+                (None, false) => panic!("invalid code"),
+            };
+            if let Some(previous) = previous {
+                if table.len() < 4096 {
+                    table.push([previous, vec![entry[0]]].concat());
+                }
+            }
+            if table.len() == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+            output.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+        output
+    }
+
+    #[test]
+    fn test_gif_lzw_and_palette() {
+        // Long enough to fill the code table and clear it
+        let indices: Vec<u8> = (0..40000u32)
+            .map(|i| ((i * i / 7 + i / 13) % 256) as u8)
+            .collect();
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 8), 8), indices);
+        let few: Vec<u8> = (0..100).map(|i| (i % 3) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&few, 2), 2), few);
+
+        // Two colors come out exactly, dithering a gray between them mixes both
+        let pixels: Vec<[u8; 3]> = (0..16)
+            .map(|i| if i < 8 { [0, 0, 0] } else { [255, 255, 255] })
+            .collect();
+        let palette = quantize_palette(&pixels, 256);
+        assert_eq!(palette.len(), 2);
+        let gray = vec![[128, 128, 128]; 16];
+        let indices = map_to_palette(&gray, 4, &palette, true);
+        assert!(indices.contains(&0) && indices.contains(&1));
+
+        let mut bytes = Vec::new();
+        let mut gif = GifEncoder::new(&mut bytes, 4, 4, 10.0, true).unwrap();
+        gif.write_frame(&pixels).unwrap();
+        gif.finish().unwrap();
+        assert!(bytes.starts_with(b"GIF89a"));
+        assert_eq!(*bytes.last().unwrap(), 0x3B);
+    }
+
+    #[test]
+    fn test_y4m_frame_layout() {
+        let mut bytes = Vec::new();
+        let mut y4m = Y4mWriter::new(&mut bytes, 3, 2, 29.97).unwrap();
+        y4m.write_frame(&[[255, 255, 255]; 6]).unwrap();
+        y4m.write_frame(&[[0, 0, 0]; 6]).unwrap();
+        y4m.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W3 H2 F2997:100 Ip A1:1 C420jpeg XCOLORRANGE=FULL\n";
+        assert!(bytes.starts_with(header));
+        // 6 luma and 2 + 2 chroma samples per frame
+        let frame_size = b"FRAME\n".len() + 6 + 2 + 2;
+        assert_eq!(bytes.len(), header.len() + 2 * frame_size);
+        let first = &bytes[header.len() + 6..header.len() + frame_size];
+        assert_eq!(first, &[255, 255, 255, 255, 255, 255, 128, 128, 128, 128]);
+        assert!(y4m_rejects_wrong_size());
+    }
+
+    fn y4m_rejects_wrong_size() -> bool {
+        let mut y4m = Y4mWriter::new(Vec::new(), 3, 2, 24.0).unwrap();
+        y4m.write_frame(&[[0, 0, 0]; 4]).is_err()
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    sync::Arc,
+};
+
+use glam::{Mat4, Vec3};
+
+use crate::{
+    color::Color,
+    interval::Interval,
+    medium::medium_hit_result,
+    random::rand,
+    ray::{HitResult, Hittable, Ray, SurfaceAttributes},
+};
+
+/// Magic bytes of the voxel format: "RTVX", u32 width, height, depth and channels (1 for density,
+/// 4 for density + rgb emission), followed by little endian f32 voxels with x varying fastest.
+const VOXEL_GRID_MAGIC: &[u8; 4] = b"RTVX";
+
+/// Voxels reserved up front when reading, larger grids grow as the data arrives so a
+/// truncated file with a huge header fails on the missing data instead of on allocation.
+const MAX_PREALLOCATED_VOXELS: usize = 1 << 24;
+
+/// Delta and ratio tracking terminate randomly, this bounds the work for very dense grids.
+const MAX_TRACKING_STEPS: i32 = 4096;
+
+/// Dense 3D grid of densities with optional per-voxel emission, sampled over the unit cube.
+pub struct VoxelGrid {
+    pub resolution: [usize; 3],
+    pub density: Vec<f32>,
+    pub emission: Option<Vec<Vec3>>,
+    max_density: f32,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, io::Error> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32, io::Error> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+/// Number of voxels of a grid, every dimension has to be at least 1.
+fn num_voxels(resolution: [usize; 3]) -> Result<usize, io::Error> {
+    if resolution.contains(&0) {
+        return Err(invalid_data("voxel grid dimensions must not be zero"));
+    }
+    resolution[0]
+        .checked_mul(resolution[1])
+        .and_then(|n| n.checked_mul(resolution[2]))
+        .ok_or_else(|| invalid_data("voxel grid is too large"))
+}
+
+impl VoxelGrid {
+    pub fn new(
+        resolution: [usize; 3],
+        density: Vec<f32>,
+        emission: Option<Vec<Vec3>>,
+    ) -> Result<Self, io::Error> {
+        let num_voxels = num_voxels(resolution)?;
+        if density.len() != num_voxels {
+            return Err(invalid_data("density does not match the resolution"));
+        }
+        if let Some(emission) = &emission {
+            if emission.len() != num_voxels {
+                return Err(invalid_data("emission does not match the resolution"));
+            }
+        }
+        let max_density = density.iter().cloned().fold(0.0, f32::max);
+
+        Ok(Self {
+            resolution,
+            density,
+            emission,
+            max_density,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read(&mut reader)
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, io::Error> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != VOXEL_GRID_MAGIC {
+            return Err(invalid_data("not a voxel grid file"));
+        }
+
+        let resolution = [
+            read_u32(reader)? as usize,
+            read_u32(reader)? as usize,
+            read_u32(reader)? as usize,
+        ];
+        let channels = read_u32(reader)?;
+        if channels != 1 && channels != 4 {
+            return Err(invalid_data("voxel grid must have 1 or 4 channels"));
+        }
+
+        let num_voxels = num_voxels(resolution)?;
+        let mut density: Vec<f32> = Vec::with_capacity(num_voxels.min(MAX_PREALLOCATED_VOXELS));
+        let mut emission: Vec<Vec3> = Vec::new();
+        for _i in 0..num_voxels {
+            density.push(read_f32(reader)?);
+            if channels == 4 {
+                emission.push(Vec3::new(
+                    read_f32(reader)?,
+                    read_f32(reader)?,
+                    read_f32(reader)?,
+                ));
+            }
+        }
+
+        let emission = if channels == 4 { Some(emission) } else { None };
+        Self::new(resolution, density, emission)
+    }
+
+    /// Headerless density only grid, little endian f32 with x varying fastest.
+    pub fn from_raw(bytes: &[u8], resolution: [usize; 3]) -> Result<Self, io::Error> {
+        let num_voxels = num_voxels(resolution)?;
+        if num_voxels.checked_mul(4) != Some(bytes.len()) {
+            return Err(invalid_data("raw voxel data does not match the resolution"));
+        }
+
+        let density: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        Self::new(resolution, density, None)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), io::Error> {
+        writer.write_all(VOXEL_GRID_MAGIC)?;
+        for size in self.resolution {
+            writer.write_all(&(size as u32).to_le_bytes())?;
+        }
+        let channels: u32 = if self.emission.is_some() { 4 } else { 1 };
+        writer.write_all(&channels.to_le_bytes())?;
+
+        for (i, density) in self.density.iter().enumerate() {
+            writer.write_all(&density.to_le_bytes())?;
+            if let Some(emission) = &self.emission {
+                for channel in emission[i].to_array() {
+                    writer.write_all(&channel.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.max_density
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.resolution[0] * (y + self.resolution[1] * z)
+    }
+
+    /// Trilinear interpolation between voxel centers, `p` is in the unit cube.
+    fn trilinear<T>(&self, p: Vec3, fetch: impl Fn(usize) -> T) -> T
+    where
+        T: std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+    {
+        let size = Vec3::new(
+            self.resolution[0] as f32,
+            self.resolution[1] as f32,
+            self.resolution[2] as f32,
+        );
+        let voxel = (p * size - Vec3::splat(0.5)).clamp(Vec3::ZERO, size - Vec3::ONE);
+        let base = voxel.floor();
+        let frac = voxel - base;
+
+        let x0 = base.x as usize;
+        let y0 = base.y as usize;
+        let z0 = base.z as usize;
+        let x1 = (x0 + 1).min(self.resolution[0] - 1);
+        let y1 = (y0 + 1).min(self.resolution[1] - 1);
+        let z1 = (z0 + 1).min(self.resolution[2] - 1);
+
+        let lerp = |a: T, b: T, t: f32| a * (1.0 - t) + b * t;
+        let c00 = lerp(
+            fetch(self.index(x0, y0, z0)),
+            fetch(self.index(x1, y0, z0)),
+            frac.x,
+        );
+        let c10 = lerp(
+            fetch(self.index(x0, y1, z0)),
+            fetch(self.index(x1, y1, z0)),
+            frac.x,
+        );
+        let c01 = lerp(
+            fetch(self.index(x0, y0, z1)),
+            fetch(self.index(x1, y0, z1)),
+            frac.x,
+        );
+        let c11 = lerp(
+            fetch(self.index(x0, y1, z1)),
+            fetch(self.index(x1, y1, z1)),
+            frac.x,
+        );
+        lerp(lerp(c00, c10, frac.y), lerp(c01, c11, frac.y), frac.z)
+    }
+
+    pub fn density_at(&self, p: Vec3) -> f32 {
+        self.trilinear(p, |i| self.density[i])
+    }
+
+    pub fn emission_at(&self, p: Vec3) -> Vec3 {
+        match &self.emission {
+            Some(emission) => self.trilinear(p, |i| emission[i]),
+            None => Vec3::ZERO,
+        }
+    }
+}
+
+/// Voxel grid placed in the world by `grid_to_world`, which maps the unit cube onto the volume.
+/// `surface.albedo` and `surface.phase` describe scattering, emission comes from the grid.
+#[derive(Clone)]
+pub struct HeterogeneousVolume {
+    pub grid: Arc<VoxelGrid>,
+    pub density_scale: f32,
+    pub emission_scale: f32,
+    pub surface: SurfaceAttributes,
+    grid_to_world: Mat4,
+    world_to_grid: Mat4,
+}
+
+impl HeterogeneousVolume {
+    pub fn new(
+        grid: Arc<VoxelGrid>,
+        grid_to_world: Mat4,
+        density_scale: f32,
+        surface: SurfaceAttributes,
+    ) -> Self {
+        Self {
+            grid,
+            density_scale,
+            emission_scale: 1.0,
+            surface,
+            grid_to_world,
+            world_to_grid: grid_to_world.inverse(),
+        }
+    }
+
+    pub fn grid_to_world(&self) -> Mat4 {
+        self.grid_to_world
+    }
+
+    fn majorant(&self) -> f32 {
+        self.grid.max_density() * self.density_scale
+    }
+
+    /// Ray in grid space, kept unnormalized so t stays in world units.
+    fn grid_ray(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.world_to_grid.transform_point3(ray.origin),
+            direction: self.world_to_grid.transform_vector3(ray.direction),
+            time: ray.time,
+        }
+    }
+
+    /// Overlap of the ray with the unit cube and the interval.
+    fn grid_t(&self, grid_ray: &Ray, interval: &Interval) -> Option<(f32, f32)> {
+        let inv_direction = grid_ray.direction.recip();
+        let t0 = (Vec3::ZERO - grid_ray.origin) * inv_direction;
+        let t1 = (Vec3::ONE - grid_ray.origin) * inv_direction;
+        let t_enter = t0.min(t1).max_element().max(interval.min);
+        let t_exit = t0.max(t1).min_element().min(interval.max);
+        if t_enter >= t_exit {
+            return None;
+        }
+        Some((t_enter, t_exit))
+    }
+}
+
+impl Hittable for HeterogeneousVolume {
+    fn clone_dyn(&self) -> Box<dyn Hittable + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    /// Delta tracking, returns a real collision sampled proportionally to the local density.
+    fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitResult> {
+        let majorant = self.majorant();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let grid_ray = self.grid_ray(ray);
+        let (mut t, t_exit) = self.grid_t(&grid_ray, &interval)?;
+
+        for _step in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - rand::<f32>()).ln() / majorant;
+            if t >= t_exit {
+                return None;
+            }
+
+            let p = grid_ray.at(t);
+            let density = self.grid.density_at(p) * self.density_scale;
+            if rand::<f32>() * majorant < density {
+                let emission = self.grid.emission_at(p) * self.emission_scale;
+                let mut surface = self.surface;
+                surface.emissive = Color::new(emission.x, emission.y, emission.z, 1.0);
+                return Some(medium_hit_result(ray, t, surface));
+            }
+        }
+
+        None
+    }
+
+    /// Ratio tracking, an unbiased estimate of exp(-integral of density).
+    fn transmittance(&self, ray: &Ray, interval: Interval) -> f32 {
+        let majorant = self.majorant();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+        let grid_ray = self.grid_ray(ray);
+        let Some((mut t, t_exit)) = self.grid_t(&grid_ray, &interval) else {
+            return 1.0;
+        };
+
+        let mut transmittance = 1.0;
+        for _step in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - rand::<f32>()).ln() / majorant;
+            if t >= t_exit {
+                break;
+            }
+            let density = self.grid.density_at(grid_ray.at(t)) * self.density_scale;
+            transmittance *= 1.0 - density / majorant;
+        }
+
+        transmittance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voxel_grid_roundtrip() {
+        let resolution = [2, 3, 4];
+        let density: Vec<f32> = (0..24).map(|i| i as f32 * 0.5).collect();
+        let emission: Vec<Vec3> = (0..24).map(|i| Vec3::splat(i as f32)).collect();
+        let grid = VoxelGrid::new(resolution, density.clone(), Some(emission)).unwrap();
+
+        let mut bytes: Vec<u8> = Vec::new();
+        grid.write(&mut bytes).unwrap();
+        let loaded = VoxelGrid::read(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(loaded.resolution, resolution);
+        assert_eq!(loaded.density, density);
+        assert_eq!(loaded.max_density(), 11.5);
+        assert_eq!(loaded.emission.unwrap()[7], Vec3::splat(7.0));
+        assert!(VoxelGrid::read(&mut &b"nope"[..]).is_err());
+
+        // Zero and overflowing dimensions are rejected before any voxel is read
+        let header = |resolution: [u32; 3]| {
+            let mut bytes: Vec<u8> = VOXEL_GRID_MAGIC.to_vec();
+            for value in resolution.iter().chain([1u32].iter()) {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes
+        };
+        assert!(VoxelGrid::read(&mut header([0, 4, 4]).as_slice()).is_err());
+        assert!(VoxelGrid::read(&mut header([u32::MAX; 3]).as_slice()).is_err());
+        assert!(VoxelGrid::from_raw(&[], [0, 1, 1]).is_err());
+        assert!(VoxelGrid::new([2, 2, 2], vec![1.0; 7], None).is_err());
+    }
+
+    #[test]
+    fn test_ratio_tracking_matches_beer_lambert() {
+        let grid = Arc::new(VoxelGrid::new([2, 2, 2], vec![1.0; 8], None).unwrap());
+        let grid_to_world = Mat4::from_scale_rotation_translation(
+            Vec3::splat(2.0),
+            glam::Quat::IDENTITY,
+            Vec3::new(-1.0, -1.0, -6.0),
+        );
+        let volume =
+            HeterogeneousVolume::new(grid, grid_to_world, 0.5, SurfaceAttributes::default());
+
+        // 2 units through density 0.5
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        const SAMPLES: i32 = 20000;
+        let mut sum = 0.0;
+        for _i in 0..SAMPLES {
+            sum += volume.transmittance(&ray, Interval::new(0.0001, f32::INFINITY));
+        }
+        assert!((sum / SAMPLES as f32 - (-1.0f32).exp()).abs() < 0.02);
+    }
+}
@@ -0,0 +1,255 @@
+//! Closed form mappings from [0, 1)^2 to sampling domains, each with its pdf.
+//! Directions are around +z, use `local_to_world` to orient them around a normal.
+
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use glam::{Vec2, Vec3};
+
+/// Shirley-Chiu concentric mapping onto the unit disk, keeps strata compact.
+pub fn square_to_concentric_disk(u: Vec2) -> Vec2 {
+    let offset = u * 2.0 - Vec2::ONE;
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return Vec2::ZERO;
+    }
+
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, FRAC_PI_4 * (offset.y / offset.x))
+    } else {
+        (offset.y, FRAC_PI_2 - FRAC_PI_4 * (offset.x / offset.y))
+    };
+    Vec2::new(r * theta.cos(), r * theta.sin())
+}
+
+pub fn concentric_disk_pdf(p: Vec2) -> f32 {
+    if p.length_squared() > 1.0 {
+        return 0.0;
+    }
+    1.0 / PI
+}
+
+pub fn square_to_uniform_sphere(u: Vec2) -> Vec3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_sphere_pdf() -> f32 {
+    1.0 / (4.0 * PI)
+}
+
+pub fn square_to_uniform_hemisphere(u: Vec2) -> Vec3 {
+    let z = u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_hemisphere_pdf(v: Vec3) -> f32 {
+    if v.z < 0.0 {
+        return 0.0;
+    }
+    1.0 / (2.0 * PI)
+}
+
+/// Malley's method, the concentric disk projected up onto the hemisphere.
+pub fn square_to_cosine_hemisphere(u: Vec2) -> Vec3 {
+    let d = square_to_concentric_disk(u);
+    let z = (1.0 - d.length_squared()).max(0.0).sqrt();
+    Vec3::new(d.x, d.y, z)
+}
+
+pub fn cosine_hemisphere_pdf(v: Vec3) -> f32 {
+    v.z.max(0.0) / PI
+}
+
+/// Uniform over the cap of directions within acos(`cos_theta_max`) of +z.
+pub fn square_to_uniform_cone(u: Vec2, cos_theta_max: f32) -> Vec3 {
+    let cos_theta = 1.0 - u.x * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+pub fn uniform_cone_pdf(v: Vec3, cos_theta_max: f32) -> f32 {
+    if v.z < cos_theta_max {
+        return 0.0;
+    }
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+/// Barycentrics (b0, b1) of a uniform point in a triangle, the third is 1 - b0 - b1.
+/// The pdf over the triangle is one over its area.
+pub fn square_to_uniform_triangle(u: Vec2) -> Vec2 {
+    // Heitz's low distortion mapping
+    if u.x < u.y {
+        let b0 = u.x / 2.0;
+        Vec2::new(b0, u.y - b0)
+    } else {
+        let b1 = u.y / 2.0;
+        Vec2::new(u.x - b1, b1)
+    }
+}
+
+pub fn uniform_triangle_pdf(area: f32) -> f32 {
+    1.0 / area
+}
+
+/// Solid angle of the spherical triangle spanned by the unit directions a, b and c.
+pub fn spherical_triangle_area(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+    // Van Oosterom and Strackee
+    let numerator = a.dot(b.cross(c)).abs();
+    let denominator = 1.0 + a.dot(b) + b.dot(c) + c.dot(a);
+    2.0 * numerator.atan2(denominator)
+}
+
+/// Arvo's uniform sampling of the spherical triangle spanned by the unit directions
+/// a, b and c. The pdf is one over `spherical_triangle_area`.
+pub fn square_to_spherical_triangle(u: Vec2, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let area = spherical_triangle_area(a, b, c);
+    if area <= 0.0 {
+        return a;
+    }
+
+    // Interior angle at a, between the planes through the origin and the edges ab and ac
+    let alpha = angle_between(
+        a.cross(b).normalize_or_zero(),
+        a.cross(c).normalize_or_zero(),
+    );
+    let cos_alpha = alpha.cos();
+    let sin_alpha = alpha.sin();
+    let cos_c = a.dot(b);
+
+    // Pick the sub triangle with the sampled area, this sets the new vertex on the arc ac
+    let area_sampled = u.x * area;
+    let s = (area_sampled - alpha).sin();
+    let t = (area_sampled - alpha).cos();
+    let uu = t - cos_alpha;
+    let vv = s + sin_alpha * cos_c;
+    let q = ((vv * t - uu * s) * cos_alpha - vv) / ((vv * s + uu * t) * sin_alpha);
+    let q = q.clamp(-1.0, 1.0);
+    let c_along = (c - a * c.dot(a)).normalize_or_zero();
+    let c_new = a * q + c_along * (1.0 - q * q).max(0.0).sqrt();
+
+    // Then sample along the arc from b to the new vertex
+    let z = 1.0 - u.y * (1.0 - c_new.dot(b));
+    let c_new_along = (c_new - b * c_new.dot(b)).normalize_or_zero();
+    (b * z + c_new_along * (1.0 - z * z).max(0.0).sqrt()).normalize()
+}
+
+pub fn spherical_triangle_pdf(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+    1.0 / spherical_triangle_area(a, b, c)
+}
+
+fn angle_between(a: Vec3, b: Vec3) -> f32 {
+    a.dot(b).clamp(-1.0, 1.0).acos()
+}
+
+/// Bin of a normalized cdf containing `u`, and where in the bin it falls.
+/// The cdf starts at 0 and has one more entry than there are bins.
+pub fn sample_cdf(cdf: &[f32], u: f32) -> (usize, f32) {
+    let num_bins = cdf.len() - 1;
+    let bin = cdf.partition_point(|value| *value <= u).clamp(1, num_bins) - 1;
+    let width = cdf[bin + 1] - cdf[bin];
+    let t = if width > 0.0 {
+        ((u - cdf[bin]) / width).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    (bin, t)
+}
+
+/// Rotates a direction around +z into the frame around `normal`.
+pub fn local_to_world(v: Vec3, normal: Vec3) -> Vec3 {
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    tangent * v.x + bitangent * v.y + normal * v.z
+}
+
+/// Inverse of `local_to_world`, so directions can be handed to the pdfs above.
+pub fn world_to_local(v: Vec3, normal: Vec3) -> Vec3 {
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    Vec3::new(v.dot(tangent), v.dot(bitangent), v.dot(normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: usize = 64;
+
+    /// Stratified grid of points in [0, 1)^2.
+    fn grid() -> impl Iterator<Item = Vec2> {
+        (0..GRID * GRID).map(|i| {
+            Vec2::new(
+                ((i % GRID) as f32 + 0.5) / GRID as f32,
+                ((i / GRID) as f32 + 0.5) / GRID as f32,
+            )
+        })
+    }
+
+    /// The mean of 1 / pdf over the samples estimates the measure of the domain.
+    fn mean_inverse_pdf(pdf: impl Fn(Vec2) -> f32) -> f32 {
+        grid().map(|u| 1.0 / pdf(u)).sum::<f32>() / (GRID * GRID) as f32
+    }
+
+    #[test]
+    fn test_warps_match_pdfs() {
+        assert!(grid().all(|u| square_to_concentric_disk(u).length() <= 1.0 + 1e-5));
+        let disk = mean_inverse_pdf(|u| concentric_disk_pdf(square_to_concentric_disk(u)));
+        assert!((disk - PI).abs() < 1e-3);
+
+        let sphere_mean = grid().map(square_to_uniform_sphere).sum::<Vec3>() / (GRID * GRID) as f32;
+        assert!(sphere_mean.length() < 1e-2);
+        assert!((mean_inverse_pdf(|_| uniform_sphere_pdf()) - 4.0 * PI).abs() < 1e-3);
+
+        let hemisphere =
+            mean_inverse_pdf(|u| uniform_hemisphere_pdf(square_to_uniform_hemisphere(u)));
+        assert!((hemisphere - 2.0 * PI).abs() < 1e-3);
+
+        // E[cos] is 2 / 3 when directions are cosine weighted
+        let mean_cos = grid()
+            .map(|u| square_to_cosine_hemisphere(u).z)
+            .sum::<f32>()
+            / (GRID * GRID) as f32;
+        assert!((mean_cos - 2.0 / 3.0).abs() < 5e-3);
+        // Projected solid angle of the hemisphere
+        let projected = mean_inverse_pdf(|u| {
+            let v = square_to_cosine_hemisphere(u);
+            cosine_hemisphere_pdf(v) / v.z
+        });
+        assert!((projected - PI).abs() < 1e-3);
+
+        let normal = Vec3::new(0.3, -0.5, 0.8).normalize();
+        let v = Vec3::new(-0.6, 0.2, 0.1);
+        assert!((local_to_world(world_to_local(v, normal), normal) - v).length() < 1e-5);
+
+        let cos_max = 0.8;
+        let cone =
+            mean_inverse_pdf(|u| uniform_cone_pdf(square_to_uniform_cone(u, cos_max), cos_max));
+        assert!((cone - 2.0 * PI * (1.0 - cos_max)).abs() < 1e-3);
+
+        let barycentric_mean =
+            grid().map(square_to_uniform_triangle).sum::<Vec2>() / (GRID * GRID) as f32;
+        assert!((barycentric_mean - Vec2::splat(1.0 / 3.0)).length() < 1e-3);
+        assert_eq!(uniform_triangle_pdf(0.5), 2.0);
+    }
+
+    #[test]
+    fn test_spherical_triangle_sampling() {
+        // One octant of the sphere
+        let (a, b, c) = (Vec3::X, Vec3::Y, Vec3::Z);
+        assert!((spherical_triangle_area(a, b, c) - PI / 2.0).abs() < 1e-5);
+        assert!((spherical_triangle_pdf(a, b, c) * PI / 2.0 - 1.0).abs() < 1e-5);
+
+        let mut mean = Vec3::ZERO;
+        for u in grid() {
+            let v = square_to_spherical_triangle(u, a, b, c);
+            assert!(v.x >= -1e-4 && v.y >= -1e-4 && v.z >= -1e-4);
+            assert!((v.length() - 1.0).abs() < 1e-4);
+            mean += v;
+        }
+        // Uniform over the octant, each axis averages the quarter disk area pi / 4 over pi / 2
+        mean /= (GRID * GRID) as f32;
+        assert!((mean - Vec3::splat(0.5)).length() < 1e-2);
+    }
+}